@@ -3,11 +3,15 @@ use alloc::{
     collections::BTreeMap,
 };
 use linked_list_allocator::LockedHeap;
-use spin::{Mutex, Once};
+use spin::Once;
 
-use crate::rpi::{
-    mailbox::{PropertyMessage, PropertyTagList},
-    mmio::P_BASE_PHYSICAL_ADDR,
+use crate::{
+    executor,
+    interrupts::{InterruptGuard, IrqMutex},
+    rpi::{
+        mailbox::{PropertyMessage, PropertyTagList},
+        mmio::P_BASE_PHYSICAL_ADDR,
+    },
 };
 
 extern "C" {
@@ -37,7 +41,7 @@ impl MyAllocatorWrapper {
             let mut msg =
                 PropertyMessage::new(0x0001_0005, GetARMMemoryMessage { base: 0, size: 0 })
                     .prepare();
-            let result = match msg.send() {
+            let result = match executor::block_on(msg.send()) {
                 Some(result) => **result,
                 None => {
                     println!("Warning: failed to get memory size from mailbox, using hardcoded defaults.");
@@ -68,13 +72,18 @@ impl MyAllocatorWrapper {
 }
 
 unsafe impl GlobalAlloc for MyAllocatorWrapper {
+    // IRQs are masked while the heap is locked, as with the other locks here,
+    // so that a fault taken with the lock held isn't recovered from (see
+    // `interrupts::faults`).
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut inner = self.get_inner();
+        let _guard = InterruptGuard::irqs();
         inner.alloc(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut inner = self.get_inner();
+        let _guard = InterruptGuard::irqs();
         inner.dealloc(ptr, layout)
     }
 }
@@ -87,12 +96,112 @@ fn handle_alloc_error(layout: Layout) -> ! {
     panic!("Failed to allocate from layout {:?}", layout)
 }
 
-static C_HEAP: Once<Mutex<BTreeMap<usize, Layout>>> = Once::new();
+/// A snapshot of the heap's usage, in bytes.
+#[derive(Copy, Clone, Debug)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// The largest allocation that would succeed, which is less than `free`
+    /// when the heap is fragmented.
+    pub largest_free: usize,
+}
+
+pub fn heap_stats() -> HeapStats {
+    let inner = ALLOC.get_inner();
+    let _guard = InterruptGuard::irqs();
+    let mut heap = inner.lock();
+    // The allocator doesn't expose its list of holes, so find the largest
+    // one by trying allocations, which are given straight back.
+    let fits = |heap: &mut linked_list_allocator::Heap, size: usize| {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        match heap.allocate_first_fit(layout) {
+            Ok(ptr) => {
+                unsafe { heap.deallocate(ptr, layout) };
+                true
+            }
+            Err(_) => false,
+        }
+    };
+    let (mut low, mut high) = (0, heap.free());
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if fits(&mut *heap, mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    HeapStats {
+        size: heap.size(),
+        used: heap.used(),
+        free: heap.free(),
+        largest_free: low,
+    }
+}
+
+static C_HEAP: Once<IrqMutex<BTreeMap<usize, Layout>>> = Once::new();
+
+/// The layouts of the blocks allocated by Forth's `ALLOCATE`, which like
+/// `free` is given only the address to release.
+static FORTH_HEAP: Once<IrqMutex<BTreeMap<usize, Layout>>> = Once::new();
 
 #[inline]
-fn init_c_heap() -> Mutex<BTreeMap<usize, Layout>> {
+fn init_c_heap() -> IrqMutex<BTreeMap<usize, Layout>> {
     let map = BTreeMap::new();
-    Mutex::new(map)
+    IrqMutex::new(map)
+}
+
+/// The alignment of blocks allocated for Forth, which is enough for any
+/// value Forth stores.
+const FORTH_ALIGN: usize = 16;
+
+/// Allocate `size` bytes for Forth's `ALLOCATE`, returning null on failure.
+pub fn forth_allocate(size: usize) -> *mut u8 {
+    let layout = match Layout::from_size_align(size.max(1), FORTH_ALIGN) {
+        Ok(layout) => layout,
+        Err(_) => return core::ptr::null_mut(),
+    };
+    let ptr = unsafe { ALLOC.alloc(layout) };
+    if !ptr.is_null() {
+        let forth_heap = FORTH_HEAP.call_once(init_c_heap);
+        forth_heap.lock().insert(ptr as usize, layout);
+    }
+    ptr
+}
+
+/// Free a block from `forth_allocate`. Returns false if `ptr` isn't one.
+pub fn forth_free(ptr: *mut u8) -> bool {
+    let forth_heap = FORTH_HEAP.call_once(init_c_heap);
+    let layout = forth_heap.lock().remove(&(ptr as usize));
+    match layout {
+        Some(layout) => {
+            unsafe { ALLOC.dealloc(ptr, layout) };
+            true
+        }
+        None => false,
+    }
+}
+
+/// Resize a block from `forth_allocate`, moving it if need be. Returns null
+/// if `ptr` isn't such a block or there's no room, leaving it as it was.
+pub fn forth_resize(ptr: *mut u8, size: usize) -> *mut u8 {
+    let forth_heap = FORTH_HEAP.call_once(init_c_heap);
+    let mut forth_heap = forth_heap.lock();
+    let layout = match forth_heap.get(&(ptr as usize)) {
+        Some(layout) => *layout,
+        None => return core::ptr::null_mut(),
+    };
+    let new_layout = match Layout::from_size_align(size.max(1), FORTH_ALIGN) {
+        Ok(new_layout) => new_layout,
+        Err(_) => return core::ptr::null_mut(),
+    };
+    let new_ptr = unsafe { ALLOC.realloc(ptr, layout, new_layout.size()) };
+    if !new_ptr.is_null() {
+        forth_heap.remove(&(ptr as usize));
+        forth_heap.insert(new_ptr as usize, new_layout);
+    }
+    new_ptr
 }
 
 #[no_mangle]
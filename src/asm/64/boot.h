@@ -9,6 +9,24 @@
 
 #define LOW_MEMORY              (2 * SECTION_SIZE)
 
+// Where the firmware's stub would put the spin table for cores 1-3.
+#define SPIN_TABLE              0xd8
+
+// Offsets into `smp::SecondaryBoot`.
+#define BOOT_MAIR               0
+#define BOOT_TCR                8
+#define BOOT_TTBR0              16
+#define BOOT_TTBR1              24
+#define BOOT_SCTLR              32
+#define BOOT_STACKS             40
+
+#define CPACR_FPEN              (3 << 20)
+
+// The Cortex-A53's CPUECTLR_EL1, and its bit that takes the core part in
+// cache coherency.
+#define CPUECTLR_EL1            S3_1_C15_C2_1
+#define CPUECTLR_SMPEN          (1 << 6)
+
 #define HCR_RW                  (1 << 31)
 #define HCR_VALUE               HCR_RW
 
@@ -18,6 +36,12 @@
 #define MT_NORMAL_NC_FLAGS      0x44
 #define MAIR_VALUE              (MT_DEVICE_nGnRnE_FLAGS << (8 * MT_DEVICE_nGnRnE)) | (MT_NORMAL_NC_FLAGS << (8 * MT_NORMAL_NC))
 
+// The generic timer counts the 19.2 MHz crystal.
+#define CNTFRQ_VALUE            19200000
+#define CNTHCTL_EL1PCTEN        (1 << 0)
+#define CNTHCTL_EL1PCEN         (1 << 1)
+#define CNTHCTL_VALUE           (CNTHCTL_EL1PCTEN | CNTHCTL_EL1PCEN)
+
 #define SCR_RESERVED            (3 << 4)
 #define SCR_RW                  (1 << 10)
 #define SCR_NS                  (1 << 0)
@@ -34,6 +58,9 @@
 #define SCTLR_D_CACHE_DISABLED  (0 << 2)
 #define SCTLR_MMU_DISABLED      (0 << 0)
 #define SCTLR_MMU_ENABLED       (1 << 0)
+#define SCTLR_I_CACHE_ENABLED   (1 << 12)
+#define SCTLR_D_CACHE_ENABLED   (1 << 2)
+#define SCTLR_MMU_CACHES_ENABLED   (SCTLR_MMU_ENABLED | SCTLR_I_CACHE_ENABLED | SCTLR_D_CACHE_ENABLED)
 #define SCTLR_INIT_MMU_DISABLED    (SCTLR_RESERVED | SCTLR_SPAN | SCTLR_TRAP_EL0_WFE | SCTLR_TRAP_EL0_WFI | SCTLR_EE_LITTLE_ENDIAN | SCTLR_I_CACHE_DISABLED | SCTLR_D_CACHE_DISABLED | SCTLR_MMU_DISABLED)
 
 #define SPSR_MASK_ALL           (7 << 6)
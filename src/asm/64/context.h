@@ -0,0 +1,9 @@
+#ifndef _CONTEXT_H
+#define _CONTEXT_H
+
+// Offsets into a saved task context, see `Context` in src/forth/tasks.rs.
+#define CONTEXT_X19             0
+#define CONTEXT_SP              96
+#define CONTEXT_D8              104
+
+#endif
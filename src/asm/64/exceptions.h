@@ -1,7 +1,10 @@
 #ifndef _EXCEPTIONS_H
 #define _EXCEPTIONS_H
 
-#define S_FRAME_SIZE 256
+// x0-x30, SP, ELR and SPSR: a `TrapFrame`.
+#define S_FRAME_SIZE 272
+// FPCR and FPSR, then q0-q31.
+#define FP_FRAME_SIZE 528
 
 #define SYNC_INVALID_EL1t       0
 #define IRQ_INVALID_EL1t        1
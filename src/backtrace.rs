@@ -0,0 +1,182 @@
+//! Stack backtraces for panics and exception reports.
+//!
+//! The kernel is built with frame pointers forced on, so `x29` always points
+//! at a frame record of the caller's `x29` and the return address. Following
+//! the chain of records gives the return address of each call on the stack.
+//!
+//! The addresses are named from the `.symbols` section, which the Makefile
+//! fills from the symbols of a first link of `build/kernel8.elf`, one line of
+//! `<16 hex digits> <name>` per function, sorted by address. It's empty in
+//! that first link, so the code doesn't move between the two.
+
+use core::{
+    fmt::{self, Write},
+    slice, str,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    interrupts::faults,
+    rpi::{console::Console, uart::UART},
+};
+
+/// The most calls shown in a backtrace.
+const MAX_FRAMES: usize = 64;
+
+extern "C" {
+    static __symbols_start: u8;
+    static __symbols_end: u8;
+    static __text_end: u8;
+}
+
+/// Set while a backtrace is being taken, so that a fault in doing so doesn't
+/// start another one.
+static IN_BACKTRACE: AtomicBool = AtomicBool::new(false);
+
+fn symbol_table() -> &'static [u8] {
+    unsafe {
+        let start = &__symbols_start as *const u8;
+        let end = &__symbols_end as *const u8;
+        slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+fn parse_address(hex: &[u8]) -> Option<usize> {
+    let hex = str::from_utf8(hex).ok()?;
+    usize::from_str_radix(hex, 16).ok()
+}
+
+/// The function containing `address`, and how far into it that is.
+pub fn lookup(address: usize) -> Option<(&'static str, usize)> {
+    if address >= unsafe { &__text_end as *const u8 as usize } {
+        return None;
+    }
+    let mut found = None;
+    for line in symbol_table().split(|&byte| byte == b'\n') {
+        if line.len() < 18 {
+            continue;
+        }
+        let start = match parse_address(&line[..16]) {
+            Some(start) => start,
+            None => continue,
+        };
+        if start > address {
+            break;
+        }
+        found = Some((line, start));
+    }
+    let (line, start) = found?;
+    let name = str::from_utf8(&line[17..]).unwrap_or("<bad symbol>");
+    Some((name, address - start))
+}
+
+/// The frame pointer of the caller.
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe { asm!("mov $0, x29" : "=r"(fp)) };
+    fp
+}
+
+struct Walk {
+    fp: usize,
+    return_addresses: [usize; MAX_FRAMES],
+    count: usize,
+}
+
+/// Follow the frame records from `walk.fp`, stopping at a null, misaligned
+/// or out of order one. Run under `call_catching_faults`, so that a corrupt
+/// record that points at unmapped memory ends the walk where it is.
+extern "C" fn walk_frames(walk: &mut Walk) -> isize {
+    let mut fp = walk.fp;
+    while walk.count < MAX_FRAMES && fp != 0 && fp % 8 == 0 {
+        let record = fp as *const usize;
+        let (next, lr) = unsafe { (record.read_volatile(), record.add(1).read_volatile()) };
+        if lr == 0 {
+            break;
+        }
+        walk.return_addresses[walk.count] = lr;
+        walk.count += 1;
+        // The stack grows down, so the callers' records are further up.
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+    0
+}
+
+/// Writes to the UART and the framebuffer at once, without allocating, as
+/// the heap may be what went wrong.
+struct Output {
+    uart: UART,
+    console: Option<Console>,
+}
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = self.uart.write_str(s);
+        if let Some(console) = &mut self.console {
+            let _ = console.write_str(s);
+        }
+        Ok(())
+    }
+}
+
+fn print_frame(out: &mut Output, index: usize, address: usize) {
+    let _ = match lookup(address) {
+        Some((name, offset)) => writeln!(
+            out,
+            "  {:2}: {:#018x} {}+{:#x}",
+            index, address, name, offset
+        ),
+        None => writeln!(out, "  {:2}: {:#018x} <unknown>", index, address),
+    };
+}
+
+/// Print a backtrace of the calls leading to `pc`, whose frame pointer is
+/// `fp`. With no `pc`, it starts at the call to the function that `fp`
+/// belongs to.
+pub fn print_from(pc: Option<usize>, fp: usize) {
+    let mut out = Output {
+        uart: UART::new(),
+        console: Console::new(),
+    };
+    if IN_BACKTRACE.swap(true, Ordering::SeqCst) {
+        let _ = writeln!(out, "(Faulted while taking a backtrace)");
+        return;
+    }
+
+    let mut walk = Walk {
+        fp,
+        return_addresses: [0; MAX_FRAMES],
+        count: 0,
+    };
+    let walked = faults::call_catching_faults(walk_frames, &mut walk);
+
+    let _ = writeln!(out, "Backtrace:");
+    let mut index = 0;
+    if let Some(pc) = pc {
+        print_frame(&mut out, index, pc);
+        index += 1;
+    }
+    for &lr in walk.return_addresses[..walk.count].iter() {
+        // Name the call rather than what follows it, which may be in the
+        // next function if the call doesn't return.
+        print_frame(&mut out, index, lr - 4);
+        index += 1;
+    }
+    if let Err(fault) = walked {
+        let _ = writeln!(out, "  (stopped by {:?})", fault);
+    } else if walk.count == MAX_FRAMES {
+        let _ = writeln!(out, "  ...");
+    }
+
+    IN_BACKTRACE.store(false, Ordering::SeqCst);
+}
+
+/// Print a backtrace of the calls leading here.
+#[inline(always)]
+pub fn print() {
+    print_from(None, frame_pointer());
+}
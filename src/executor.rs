@@ -0,0 +1,194 @@
+//! An executor for futures, so that drivers can wait for their interrupts
+//! instead of polling the hardware in a loop.
+//!
+//! `spawn` queues a future as a task, and `run_ready` polls the tasks whose
+//! wakers have been called since they were last polled. The Forth
+//! multitasker calls `run_ready` whenever a task pauses, so spawned tasks run
+//! while Forth is idle. Code that needs a result there and then can wait for
+//! it with `block_on`, which sleeps with `wfi` between polls. `self_test`
+//! checks all of that with a timer at boot.
+//!
+//! The leaf futures are woken from IRQ handlers:
+//!
+//! - `rpi::timer::Timer::after`
+//! - `UART::read_byte`
+//! - `PropertyMessageWrapper::send`
+//! - `rpi::usb::keyboard::next_event`
+//!
+//! `async fn` and `.await` don't build for `no_std` with the pinned
+//! toolchain, so futures are written as state machines by hand and put
+//! together with the combinators from the `futures` crate.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::Duration,
+};
+use futures::{
+    future::{BoxFuture, FutureExt},
+    task::{waker_ref, ArcWake},
+};
+use spin::Mutex;
+
+use crate::{
+    interrupts::{masked_exceptions, ExceptionMask, InterruptGuard, IrqMutex},
+    rpi::timer::Timer,
+};
+
+struct Task {
+    /// `None` once the future has finished.
+    future: Mutex<Option<BoxFuture<'static, ()>>>,
+    /// Whether the task is in `READY`, so that it's only there once.
+    queued: AtomicBool,
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(task: &Arc<Task>) {
+        if !task.queued.swap(true, Ordering::SeqCst) {
+            READY.lock().push(task.clone());
+        }
+        WOKEN.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The tasks to poll, in the order they were woken. Wakers are called from
+/// IRQ handlers, so it's locked with IRQs masked, and it always has room
+/// for every task, so that waking one never allocates: the interrupted code
+/// could be holding the heap's lock.
+static READY: IrqMutex<Vec<Arc<Task>>> = IrqMutex::new(Vec::new());
+
+/// The number of tasks that haven't finished.
+static LIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Set by every waker, so that `block_on` doesn't sleep through a wake that
+/// came between polling and `wfi`.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+/// Set while `run_ready` is polling, so that a task that calls `block_on`
+/// doesn't poll itself again.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Run `future` as a task, from the next `run_ready` on.
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let task = Arc::new(Task {
+        future: Mutex::new(Some(future.boxed())),
+        queued: AtomicBool::new(false),
+    });
+    let live = LIVE_TASKS.fetch_add(1, Ordering::SeqCst) + 1;
+    {
+        let mut ready = READY.lock();
+        let len = ready.len();
+        ready.reserve(live.saturating_sub(len));
+    }
+    ArcWake::wake_by_ref(&task);
+}
+
+/// Poll each task that's been woken once, and return whether any were.
+pub fn run_ready() -> bool {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    // Copied out rather than swapped, so that `READY` keeps its room.
+    let ready: Vec<Arc<Task>> = READY.lock().drain(..).collect();
+    let any = !ready.is_empty();
+    for task in ready {
+        task.queued.store(false, Ordering::SeqCst);
+        let waker = waker_ref(&task);
+        let mut cx = Context::from_waker(&*waker);
+        let mut slot = task.future.lock();
+        if let Some(future) = slot.as_mut() {
+            if future.as_mut().poll(&mut cx).is_ready() {
+                *slot = None;
+                LIVE_TASKS.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+    RUNNING.store(false, Ordering::SeqCst);
+    any
+}
+
+fn wake_block_on(_data: *const ()) {
+    WOKEN.store(true, Ordering::SeqCst);
+}
+
+fn clone_block_on(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &BLOCK_ON_VTABLE)
+}
+
+fn drop_block_on(_data: *const ()) {}
+
+static BLOCK_ON_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_block_on, wake_block_on, wake_block_on, drop_block_on);
+
+/// Poll `future` until it's ready, running the other tasks in between. If
+/// IRQs are masked nothing can wake it, so it's polled in a loop, which the
+/// leaf futures here allow for by checking their hardware each time.
+pub fn block_on<F: Future>(mut future: F) -> F::Output {
+    // The future is never moved after this.
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    let waker = unsafe { Waker::from_raw(clone_block_on(ptr::null())) };
+    let mut cx = Context::from_waker(&waker);
+    let can_sleep = !masked_exceptions().contains(ExceptionMask::I);
+    loop {
+        WOKEN.store(false, Ordering::SeqCst);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        if run_ready() || !can_sleep {
+            continue;
+        }
+        // An IRQ still wakes `wfi` while it's masked, and is taken when the
+        // guard unmasks it again.
+        let _guard = InterruptGuard::irqs();
+        if !WOKEN.load(Ordering::SeqCst) && READY.lock().is_empty() {
+            unsafe { asm!("wfi" :::: "volatile") };
+        }
+    }
+}
+
+/// Whether a spawned task waiting on a `Timer` runs to the end while
+/// `block_on` waits on a later one. The timer's tick must be running.
+pub fn self_test() -> bool {
+    let done = Arc::new(AtomicBool::new(false));
+    let task_done = done.clone();
+    spawn(
+        Timer::after(Duration::from_millis(5))
+            .map(move |()| task_done.store(true, Ordering::SeqCst)),
+    );
+    block_on(Timer::after(Duration::from_millis(20)));
+    done.load(Ordering::SeqCst)
+}
+
+/// Where a leaf future leaves its waker for the IRQ handler that will wake
+/// it.
+pub struct WakerSlot(IrqMutex<Option<Waker>>);
+
+impl WakerSlot {
+    pub const fn new() -> WakerSlot {
+        WakerSlot(IrqMutex::new(None))
+    }
+
+    /// Have the next `wake` wake `waker`.
+    pub fn register(&self, waker: &Waker) {
+        let mut slot = self.0.lock();
+        match &*slot {
+            Some(old) if old.will_wake(waker) => (),
+            _ => *slot = Some(waker.clone()),
+        }
+    }
+
+    /// Wake the waker that was registered last, if it hasn't been already.
+    pub fn wake(&self) {
+        let waker = self.0.lock().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
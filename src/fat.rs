@@ -0,0 +1,348 @@
+//! A read-only driver for the FAT16 or FAT32 boot partition on the SD card,
+//! which is where the Makefile puts the kernel and the files it boots with.
+//!
+//! Files are found by path from the root directory, with `/` between
+//! components, and are read whole. Names are matched without regard to case,
+//! against either the long name of an entry or its 8.3 short name.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::rpi::emmc::{self, Card, SECTOR_SIZE};
+
+/// MBR partition types of FAT16 and FAT32 partitions, in the order they're
+/// looked for.
+const PARTITION_TYPES: [u8; 5] = [0x0E, 0x06, 0x04, 0x0C, 0x0B];
+
+const DIR_ENTRY_SIZE: usize = 32;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+/// The attributes that mark a long file name entry.
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// The characters of a long name held in each entry, by byte offset.
+const LONG_NAME_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    Card(emmc::Error),
+    /// There's no FAT partition on the card.
+    NoPartition,
+    /// The partition doesn't hold a FAT16 or FAT32 file system.
+    Unsupported,
+    /// A cluster chain or directory entry points outside the file system.
+    Corrupt,
+    NotFound,
+    /// A directory was found where a file was expected, or the other way
+    /// around.
+    WrongKind,
+}
+
+impl From<emmc::Error> for Error {
+    fn from(error: emmc::Error) -> Error {
+        Error::Card(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Card(error) => write!(f, "{}", error),
+            Error::NoPartition => write!(f, "No FAT partition on the SD card"),
+            Error::Unsupported => write!(f, "Not a FAT16 or FAT32 file system"),
+            Error::Corrupt => write!(f, "FAT file system is corrupt"),
+            Error::NotFound => write!(f, "File not found"),
+            Error::WrongKind => write!(f, "Not a file"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Fat16,
+    Fat32,
+}
+
+/// Where the contents of a directory are.
+#[derive(Copy, Clone, Debug)]
+enum Dir {
+    /// The FAT16 root directory, which is a fixed run of sectors.
+    Fixed { start: u32, sectors: u32 },
+    /// Any other directory, as the first cluster of its chain.
+    Chain(u32),
+}
+
+/// A directory entry.
+struct Entry {
+    cluster: u32,
+    size: u32,
+    directory: bool,
+}
+
+/// A mounted FAT partition. Sector numbers are relative to its start.
+pub struct Volume {
+    kind: Kind,
+    /// The first sector of the partition on the card.
+    start: u32,
+    sectors_per_cluster: u32,
+    /// The first sector of the first FAT.
+    fat_start: u32,
+    /// The sector of cluster two, the first one with data in it.
+    data_start: u32,
+    /// The number of the last cluster with data in it.
+    last_cluster: u32,
+    root: Dir,
+}
+
+#[inline]
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[inline]
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// The checksum of a short name that its long name entries record.
+fn short_name_checksum(name: &[u8]) -> u8 {
+    name.iter()
+        .fold(0, |sum: u8, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// Whether the 8.3 name stored in a directory entry is `name`.
+fn short_name_matches(stored: &[u8], name: &str) -> bool {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name != ".." => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+    if base.len() > 8 || ext.len() > 3 {
+        return false;
+    }
+    let mut expected = [b' '; 11];
+    expected[..base.len()].copy_from_slice(base.as_bytes());
+    expected[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    stored.eq_ignore_ascii_case(&expected)
+}
+
+impl Volume {
+    /// Mount the first FAT partition on the SD card.
+    pub fn open() -> Result<Volume, Error> {
+        let mut found = None;
+        for kind in PARTITION_TYPES.iter() {
+            found = Card::with(|card| card.find_partition(*kind))?;
+            if found.is_some() {
+                break;
+            }
+        }
+        let (start, _) = found.ok_or(Error::NoPartition)?;
+
+        let mut boot = [0; SECTOR_SIZE];
+        Card::with(|card| card.read_sector(start, &mut boot))?;
+        let bytes_per_sector = u16_at(&boot, 11) as usize;
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved = u16_at(&boot, 14) as u32;
+        let fat_count = boot[16] as u32;
+        let root_entries = u16_at(&boot, 17) as u32;
+        let total = match u16_at(&boot, 19) {
+            0 => u32_at(&boot, 32),
+            total => total as u32,
+        };
+        let fat_size = match u16_at(&boot, 22) {
+            0 => u32_at(&boot, 36),
+            size => size as u32,
+        };
+        if boot[510..512] != [0x55, 0xAA]
+            || bytes_per_sector != SECTOR_SIZE
+            || sectors_per_cluster == 0
+            || fat_count == 0
+        {
+            return Err(Error::Unsupported);
+        }
+
+        let root_sectors =
+            (root_entries * DIR_ENTRY_SIZE as u32 + SECTOR_SIZE as u32 - 1) / SECTOR_SIZE as u32;
+        let root_start = reserved + fat_count * fat_size;
+        let data_start = root_start + root_sectors;
+        let clusters = total.saturating_sub(data_start) / sectors_per_cluster;
+        // The number of clusters is what decides the FAT type, however the
+        // partition is labelled.
+        let (kind, root) = if clusters < 4085 {
+            return Err(Error::Unsupported);
+        } else if clusters < 65525 {
+            let root = Dir::Fixed {
+                start: root_start,
+                sectors: root_sectors,
+            };
+            (Kind::Fat16, root)
+        } else {
+            (Kind::Fat32, Dir::Chain(u32_at(&boot, 44)))
+        };
+        Ok(Volume {
+            kind,
+            start,
+            sectors_per_cluster,
+            fat_start: reserved,
+            data_start,
+            last_cluster: clusters + 1,
+            root,
+        })
+    }
+
+    fn read_sector(&self, sector: u32, buffer: &mut [u8; SECTOR_SIZE]) -> Result<(), Error> {
+        Card::with(|card| card.read_sector(self.start + sector, buffer))?;
+        Ok(())
+    }
+
+    fn check_cluster(&self, cluster: u32) -> Result<u32, Error> {
+        if cluster < 2 || cluster > self.last_cluster {
+            return Err(Error::Corrupt);
+        }
+        Ok(cluster)
+    }
+
+    /// The cluster after `cluster` in its chain, or `None` at the end.
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, Error> {
+        let (offset, end) = match self.kind {
+            Kind::Fat16 => (cluster * 2, 0xFFF8),
+            Kind::Fat32 => (cluster * 4, 0x0FFF_FFF8),
+        };
+        let mut sector = [0; SECTOR_SIZE];
+        let at = (offset as usize) % SECTOR_SIZE;
+        self.read_sector(self.fat_start + offset / SECTOR_SIZE as u32, &mut sector)?;
+        let next = match self.kind {
+            Kind::Fat16 => u16_at(&sector, at) as u32,
+            Kind::Fat32 => u32_at(&sector, at) & 0x0FFF_FFFF,
+        };
+        if next >= end {
+            Ok(None)
+        } else {
+            self.check_cluster(next).map(Some)
+        }
+    }
+
+    /// Read the chain of clusters starting at `cluster`, stopping early once
+    /// `limit` bytes have been read.
+    fn read_chain(&self, cluster: u32, limit: usize) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        let mut next = Some(self.check_cluster(cluster)?);
+        while let Some(cluster) = next {
+            if data.len() >= limit {
+                break;
+            }
+            let first = self.data_start + (cluster - 2) * self.sectors_per_cluster;
+            for sector in first..first + self.sectors_per_cluster {
+                let mut buffer = [0; SECTOR_SIZE];
+                self.read_sector(sector, &mut buffer)?;
+                data.extend_from_slice(&buffer);
+            }
+            next = self.next_cluster(cluster)?;
+        }
+        Ok(data)
+    }
+
+    fn read_dir(&self, dir: Dir) -> Result<Vec<u8>, Error> {
+        match dir {
+            Dir::Fixed { start, sectors } => {
+                let mut data = Vec::with_capacity(sectors as usize * SECTOR_SIZE);
+                for sector in start..start + sectors {
+                    let mut buffer = [0; SECTOR_SIZE];
+                    self.read_sector(sector, &mut buffer)?;
+                    data.extend_from_slice(&buffer);
+                }
+                Ok(data)
+            }
+            Dir::Chain(cluster) => self.read_chain(cluster, usize::max_value()),
+        }
+    }
+
+    /// Look `name` up in `dir`.
+    fn find(&self, dir: Dir, name: &str) -> Result<Entry, Error> {
+        let data = self.read_dir(dir)?;
+        // The long name being put together from the entries before a short
+        // one, and the checksum they expect the short name to have.
+        let mut long_name = String::new();
+        let mut long_checksum = None;
+        for entry in data.chunks(DIR_ENTRY_SIZE) {
+            match entry[0] {
+                0x00 => break,
+                0xE5 => {
+                    long_checksum = None;
+                    continue;
+                }
+                _ => (),
+            }
+            let attributes = entry[11];
+            if attributes & 0x3F == ATTR_LONG_NAME {
+                // Long name entries come in reverse order, last part first.
+                if entry[0] & 0x40 != 0 {
+                    long_name.clear();
+                    long_checksum = Some(entry[13]);
+                } else if long_checksum != Some(entry[13]) {
+                    long_checksum = None;
+                }
+                let part: String = LONG_NAME_OFFSETS
+                    .iter()
+                    .map(|offset| u16_at(entry, *offset))
+                    .take_while(|c| *c != 0)
+                    .map(|c| if c < 0x80 { c as u8 as char } else { '?' })
+                    .collect();
+                long_name.insert_str(0, &part);
+                continue;
+            }
+            let long_matches = long_checksum == Some(short_name_checksum(&entry[..11]))
+                && long_name.eq_ignore_ascii_case(name);
+            long_checksum = None;
+            if attributes & ATTR_VOLUME_ID != 0 {
+                continue;
+            }
+            if long_matches || short_name_matches(&entry[..11], name) {
+                let cluster = (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32;
+                return Ok(Entry {
+                    cluster,
+                    size: u32_at(entry, 28),
+                    directory: attributes & ATTR_DIRECTORY != 0,
+                });
+            }
+        }
+        Err(Error::NotFound)
+    }
+
+    /// Read the whole of the file at `path`.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let mut dir = self.root;
+        let mut components = path.split('/').filter(|name| !name.is_empty()).peekable();
+        while let Some(name) = components.next() {
+            let entry = self.find(dir, name)?;
+            if components.peek().is_some() {
+                if !entry.directory {
+                    return Err(Error::WrongKind);
+                }
+                // A cluster of zero in `..` means the root directory.
+                dir = match entry.cluster {
+                    0 => self.root,
+                    cluster => Dir::Chain(cluster),
+                };
+            } else if entry.directory {
+                return Err(Error::WrongKind);
+            } else if entry.size == 0 {
+                return Ok(Vec::new());
+            } else {
+                let size = entry.size as usize;
+                let mut data = self.read_chain(entry.cluster, size)?;
+                if data.len() < size {
+                    return Err(Error::Corrupt);
+                }
+                data.truncate(size);
+                return Ok(data);
+            }
+        }
+        Err(Error::WrongKind)
+    }
+}
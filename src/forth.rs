@@ -0,0 +1,227 @@
+use alloc::{boxed::Box, vec::Vec};
+
+/// Define primitive words. Each body evaluates to a `Result<(), Throw>`, and
+/// is wrapped in a native function following the calling convention that
+/// compiled code uses (see `codegen`).
+macro_rules! primitives {
+    ($( $(#[$attr:meta])* fn $name:ident($vm:ident) $body:block )*) => {
+        $(
+            $(#[$attr])*
+            extern "C" fn $name($vm: &mut Vm) -> isize {
+                let result: Result<(), Throw> = (|| $body)();
+                Throw::into_code(result)
+            }
+        )*
+    };
+}
+
+mod assembler;
+mod block;
+mod codegen;
+mod compiler;
+mod control;
+mod dictionary;
+mod editor;
+mod files;
+mod float;
+#[cfg(feature = "semihosting")]
+mod image;
+mod interpreter;
+mod mailbox;
+mod memory;
+mod mmio;
+mod numeric;
+mod process;
+#[cfg(feature = "forth-tests")]
+mod selftest;
+mod stack;
+mod tasks;
+mod throw;
+mod tools;
+mod words;
+
+pub use self::throw::Throw;
+use self::{
+    dictionary::{Dictionary, Entry, Flags, Header, Xt},
+    stack::Stack,
+};
+use crate::interrupts::faults::call_catching_faults;
+
+pub type Cell = isize;
+
+pub const TRUE: Cell = -1;
+pub const FALSE: Cell = 0;
+
+const DATA_STACK_CELLS: usize = 1024;
+const RETURN_STACK_CELLS: usize = 1024;
+
+/// What each task has its own copy of, besides its stacks: the variables
+/// that standard words expose by address, such as `BASE` and `STATE`, and
+/// the buffers and state of the interpreter.
+#[repr(C)]
+pub struct User {
+    pub base: Cell,
+    /// `TRUE` while compiling, `FALSE` while interpreting.
+    pub state: Cell,
+    /// Offset of the parse position in the input buffer.
+    pub to_in: Cell,
+    /// The number of the block being interpreted, or zero.
+    pub blk: Cell,
+    input: interpreter::Input,
+    colon: Option<Colon>,
+    hold: numeric::Hold,
+    /// The message given to the `ABORT"` that is being thrown, if any.
+    abort_message: Option<(usize, usize)>,
+}
+
+impl User {
+    fn new(base: Cell) -> User {
+        User {
+            base,
+            state: FALSE,
+            to_in: 0,
+            blk: 0,
+            input: interpreter::Input::new(),
+            colon: None,
+            hold: numeric::Hold::new(),
+            abort_message: None,
+        }
+    }
+}
+
+/// The colon definition currently being compiled.
+struct Colon {
+    xt: Xt,
+    /// The shared exit sequence of the definition, see `codegen`.
+    exit: usize,
+    /// Data stack depth when the definition started, to catch unbalanced
+    /// control structures.
+    depth: usize,
+    /// Unresolved branches out of the enclosing `DO` loops, from `LEAVE` and
+    /// `?DO`. Each loop remembers how long this was when it started.
+    leaves: Vec<usize>,
+}
+
+/// The state of the Forth machine. Compiled code receives a pointer to this
+/// as its only argument.
+pub struct Vm {
+    user: User,
+    data: Stack,
+    ret: Stack,
+    floats: Stack,
+    dict: Dictionary,
+    /// The number of significant digits that `F.` and friends show.
+    precision: usize,
+    blocks: block::Blocks,
+    editor: editor::Editor,
+    tasks: tasks::Tasks,
+    /// The image that `LOAD-IMAGE` is unwinding to install, if any.
+    #[cfg(feature = "semihosting")]
+    pending_image: Option<image::Image>,
+}
+
+impl Vm {
+    /// Create a new machine with the primitive words installed. The `Vm` is
+    /// boxed because its user variables are visible by address, so it must
+    /// not move.
+    pub fn new() -> Box<Vm> {
+        let mut vm = Box::new(Vm {
+            user: User::new(10),
+            data: Stack::new(
+                DATA_STACK_CELLS,
+                Throw::STACK_OVERFLOW,
+                Throw::STACK_UNDERFLOW,
+            ),
+            ret: Stack::new(
+                RETURN_STACK_CELLS,
+                Throw::RETURN_STACK_OVERFLOW,
+                Throw::RETURN_STACK_UNDERFLOW,
+            ),
+            floats: Stack::new(
+                float::FLOAT_STACK_CELLS,
+                Throw::FLOATING_STACK_OVERFLOW,
+                Throw::FLOATING_STACK_UNDERFLOW,
+            ),
+            dict: Dictionary::new(),
+            precision: float::DEFAULT_PRECISION,
+            blocks: block::Blocks::new(),
+            editor: editor::Editor::new(),
+            tasks: tasks::Tasks::new(),
+            #[cfg(feature = "semihosting")]
+            pending_image: None,
+        });
+        words::install(&mut vm).expect("Failed to install Forth primitives");
+        vm
+    }
+
+    #[inline]
+    pub fn push(&mut self, value: Cell) -> Result<(), Throw> {
+        self.data.push(value)
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Result<Cell, Throw> {
+        self.data.pop()
+    }
+
+    #[inline]
+    pub fn push_flag(&mut self, flag: bool) -> Result<(), Throw> {
+        self.data.push(if flag { TRUE } else { FALSE })
+    }
+
+    #[inline]
+    pub fn compiling(&self) -> bool {
+        self.user.state != FALSE
+    }
+
+    /// Execute `xt`. A data abort, alignment fault or `BRK` that it takes is
+    /// turned into an exception here, as if the word had thrown it.
+    pub fn execute(&mut self, xt: Xt) -> Result<(), Throw> {
+        let entry = unsafe { (*xt).entry() };
+        self.call(entry)
+    }
+
+    /// Call the code at `entry`, catching faults as `execute` does.
+    pub fn call(&mut self, entry: Entry) -> Result<(), Throw> {
+        match call_catching_faults(entry, self) {
+            Ok(code) => Throw::from_code(code),
+            Err(fault) => Err(Throw::from(fault)),
+        }
+    }
+
+    /// Reset the machine after an uncaught exception.
+    fn abort(&mut self) {
+        self.data.clear();
+        self.floats.clear();
+        self.reset();
+    }
+
+    /// Return to interpreting the console, as `QUIT` does, leaving the data
+    /// stack alone.
+    fn reset(&mut self) {
+        self.ret.clear();
+        self.user.state = FALSE;
+        self.user.blk = 0;
+        self.user.input.reset();
+        self.user.abort_message = None;
+        if let Some(colon) = self.user.colon.take() {
+            // Throw away the half-compiled definition.
+            self.dict.forget(colon.xt);
+            self.forget_tasks(colon.xt as usize);
+        }
+        self.dict.search_assembler(false);
+    }
+}
+
+/// Run the Forth outer interpreter on the console forever.
+pub fn run() -> ! {
+    let mut vm = Vm::new();
+    vm.autoexec();
+    interpreter::quit(&mut vm)
+}
+
+/// Run the Forth test suite and exit QEMU with the result.
+#[cfg(feature = "forth-tests")]
+pub fn run_tests() -> ! {
+    selftest::run()
+}
@@ -0,0 +1,1135 @@
+//! The `ASSEMBLER` vocabulary, for writing words in AArch64 machine code.
+//!
+//! `CODE name` starts a definition and makes the assembler words visible,
+//! and `END-CODE` finishes it. In between, the interpreter keeps running, and
+//! each mnemonic lays down one instruction at `HERE`. Operands are pushed in
+//! the order the usual assembly syntax has them, so `add x0, x1, #4` is
+//! written `X0 X1 4 ADD,` and `ldr x2, [sp, #16]` is `X2 SP 16 LDR,`. A plain
+//! number is an immediate operand; the register words push tagged values, so
+//! most instructions accept either as their last operand.
+//!
+//! ```forth
+//! CODE CURRENT-EL  ( -- el )
+//!     X0 CURRENTEL MRS,
+//!     X0 X0 2 LSR,
+//!     X0 PUSH,
+//! END-CODE
+//! ```
+//!
+//! A code word is entered like any other word, with a frame of its own and
+//! the `Vm` pointer in `x19`, which it must leave alone. `x0`-`x18` are
+//! scratch registers, and `x20`-`x28` are saved on entry so they are free to
+//! use as well. `PUSH,` and `POP,` move a register to or from the data stack
+//! by calling into the kernel, which clobbers `x0`-`x18`, so values that must
+//! survive them belong in `x20`-`x28`. `END-CODE` lays down a return, and
+//! `NEXT,` returns early.
+//!
+//! Branches are made with structured words that keep their addresses on the
+//! data stack: `cond IF, ... ELSE, ... THEN,` and `BEGIN, ... cond UNTIL,`,
+//! `BEGIN, ... AGAIN,` or `BEGIN, ... cond WHILE, ... REPEAT,`. The condition
+//! is the one under which the `IF,` or `WHILE,` body runs, or under which
+//! `UNTIL,` leaves the loop.
+//!
+//! The caches are cleaned and invalidated over the new code by `END-CODE`.
+
+use alloc::{format, vec, vec::Vec};
+
+use super::{
+    codegen::{self, INSTR_SIZE},
+    compiler::literal_runtime,
+    dictionary::{Entry, Flags, Reloc},
+    words::{ASSEMBLER, NONE},
+    Cell, Colon, Throw, Vm,
+};
+
+/// Register words push a number in the low bits, with this tag above it so
+/// that the instruction words can tell registers from immediates.
+const REGISTER_TAG: Cell = 0x5245_4700 << 32;
+const REGISTER_TAG_MASK: Cell = !0x7F;
+const REGISTER_WIDE: Cell = 1 << 5;
+/// Register 31 names the stack pointer rather than the zero register.
+const REGISTER_SP: Cell = 1 << 6;
+
+const ZR: u32 = 31;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Register {
+    number: u32,
+    /// An `x` register rather than a `w` register.
+    wide: bool,
+    sp: bool,
+}
+
+impl Register {
+    const X1: Register = Register {
+        number: 1,
+        wide: true,
+        sp: false,
+    };
+
+    fn from_cell(cell: Cell) -> Option<Register> {
+        if cell & REGISTER_TAG_MASK != REGISTER_TAG {
+            return None;
+        }
+        Some(Register {
+            number: (cell & 0x1F) as u32,
+            wide: cell & REGISTER_WIDE != 0,
+            sp: cell & REGISTER_SP != 0,
+        })
+    }
+
+    fn into_cell(self) -> Cell {
+        let mut cell = REGISTER_TAG | self.number as Cell;
+        if self.wide {
+            cell |= REGISTER_WIDE;
+        }
+        if self.sp {
+            cell |= REGISTER_SP;
+        }
+        cell
+    }
+
+    /// The zero register of the same width.
+    fn zero(self) -> Register {
+        Register {
+            number: ZR,
+            wide: self.wide,
+            sp: false,
+        }
+    }
+
+    /// The `sf` bit of data-processing instructions.
+    #[inline]
+    fn sf(self) -> u32 {
+        (self.wide as u32) << 31
+    }
+
+    /// The register number for a field where 31 means the zero register.
+    fn or_zr(self) -> Result<u32, Throw> {
+        if self.sp {
+            return Err(Throw::INVALID_OPERAND);
+        }
+        Ok(self.number)
+    }
+
+    /// The register number for a field where 31 means the stack pointer.
+    fn or_sp(self) -> Result<u32, Throw> {
+        if self.number == ZR && !self.sp {
+            return Err(Throw::INVALID_OPERAND);
+        }
+        Ok(self.number)
+    }
+
+    /// The register number for a base address, which must be 64 bits wide.
+    fn base(self) -> Result<u32, Throw> {
+        if !self.wide {
+            return Err(Throw::INVALID_OPERAND);
+        }
+        self.or_sp()
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Operand {
+    Register(Register),
+    Immediate(Cell),
+}
+
+fn same_width(registers: &[Register]) -> Result<(), Throw> {
+    let wide = registers[0].wide;
+    if registers.iter().any(|register| register.wide != wide) {
+        return Err(Throw::INVALID_OPERAND);
+    }
+    Ok(())
+}
+
+/// The bits of an immediate for a `wide` or 32-bit operation. 32-bit
+/// immediates may be given as either unsigned or negative numbers.
+fn immediate_bits(imm: Cell, wide: bool) -> Result<u64, Throw> {
+    if wide {
+        Ok(imm as u64)
+    } else if imm >= i32::min_value() as Cell && imm <= u32::max_value() as Cell {
+        Ok(imm as u32 as u64)
+    } else {
+        Err(Throw::INVALID_OPERAND)
+    }
+}
+
+/// `add`, `adds`, `sub` and `subs`.
+fn add_sub(
+    sub: bool,
+    set_flags: bool,
+    rd: Register,
+    rn: Register,
+    op: Operand,
+) -> Result<u32, Throw> {
+    same_width(&[rd, rn])?;
+    let rd_field = |extended: bool| {
+        if set_flags || !extended {
+            rd.or_zr()
+        } else {
+            rd.or_sp()
+        }
+    };
+    match op {
+        Operand::Immediate(imm) => {
+            // A negative immediate is the opposite operation.
+            let (sub, imm) = if imm < 0 {
+                (!sub, imm.checked_neg().ok_or(Throw::INVALID_OPERAND)?)
+            } else {
+                (sub, imm)
+            };
+            let (imm12, shift) = if imm < 0x1000 {
+                (imm as u32, 0)
+            } else if imm & 0xFFF == 0 && imm < 0x100_0000 {
+                ((imm >> 12) as u32, 1)
+            } else {
+                return Err(Throw::INVALID_OPERAND);
+            };
+            let base = 0x1100_0000 | rd.sf() | (sub as u32) << 30 | (set_flags as u32) << 29;
+            Ok(base | shift << 22 | imm12 << 10 | rn.or_sp()? << 5 | rd_field(true)?)
+        }
+        Operand::Register(rm) => {
+            same_width(&[rd, rm])?;
+            let base = rd.sf() | (sub as u32) << 30 | (set_flags as u32) << 29;
+            if rd.sp || rn.sp {
+                // The extended register form, which can name the stack
+                // pointer: `uxtx` (or `uxtw`) with no shift is a plain add.
+                let option = if rd.wide { 0b011 } else { 0b010 };
+                Ok(base
+                    | 0x0B20_0000
+                    | rm.or_zr()? << 16
+                    | option << 13
+                    | rn.or_sp()? << 5
+                    | rd_field(true)?)
+            } else {
+                Ok(base | 0x0B00_0000 | rm.or_zr()? << 16 | rn.or_zr()? << 5 | rd_field(false)?)
+            }
+        }
+    }
+}
+
+const AND: u32 = 0b00;
+const ORR: u32 = 0b01;
+const EOR: u32 = 0b10;
+const ANDS: u32 = 0b11;
+
+/// Encode `value` as the `N:immr:imms` fields of a logical immediate, if it
+/// is a repeating pattern of a rotated run of ones.
+fn bitmask_immediate(value: u64, width: u32) -> Option<u32> {
+    let mask = if width == 64 { !0 } else { (1 << width) - 1 };
+    let value = value & mask;
+    if value == 0 || value == mask {
+        return None;
+    }
+    // Find the smallest element that the value repeats.
+    let mut size = width;
+    while size > 2 {
+        let half = size / 2;
+        let half_mask = (1 << half) - 1;
+        if value & half_mask != (value >> half) & half_mask {
+            break;
+        }
+        size = half;
+    }
+    let element_mask = if size == 64 { !0 } else { (1 << size) - 1 };
+    let element = value & element_mask;
+    // Rotate the element right until it is a run of ones at the bottom.
+    let ones = element.count_ones();
+    let rotation = (0..size).find(|rotation| {
+        let rotated = if *rotation == 0 {
+            element
+        } else {
+            ((element >> rotation) | (element << (size - rotation))) & element_mask
+        };
+        rotated == (1 << ones) - 1
+    })?;
+    let immr = (size - rotation) % size;
+    let n = (size == 64) as u32;
+    // The high bits of `imms` give the element size, and the low bits the
+    // number of ones less one.
+    let imms = (!(size * 2 - 1) & 0x3F) | (ones - 1);
+    Some(n << 12 | immr << 6 | imms)
+}
+
+/// `and`, `orr`, `eor` and `ands`, or with `invert` their `bic`, `orn`,
+/// `eon` and `bics` counterparts.
+fn logical(opc: u32, invert: bool, rd: Register, rn: Register, op: Operand) -> Result<u32, Throw> {
+    same_width(&[rd, rn])?;
+    let base = rd.sf() | opc << 29;
+    match op {
+        Operand::Immediate(imm) => {
+            let width = if rd.wide { 64 } else { 32 };
+            let mut value = immediate_bits(imm, rd.wide)?;
+            if invert {
+                value = !value;
+            }
+            let fields = bitmask_immediate(value, width).ok_or(Throw::INVALID_OPERAND)?;
+            let rd = if opc == ANDS {
+                rd.or_zr()?
+            } else {
+                rd.or_sp()?
+            };
+            Ok(base | 0x1200_0000 | fields << 10 | rn.or_zr()? << 5 | rd)
+        }
+        Operand::Register(rm) => {
+            same_width(&[rd, rm])?;
+            Ok(base
+                | 0x0A00_0000
+                | (invert as u32) << 21
+                | rm.or_zr()? << 16
+                | rn.or_zr()? << 5
+                | rd.or_zr()?)
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Shift {
+    Lsl,
+    Lsr,
+    Asr,
+}
+
+fn shift(kind: Shift, rd: Register, rn: Register, op: Operand) -> Result<u32, Throw> {
+    same_width(&[rd, rn])?;
+    let fields = rn.or_zr()? << 5 | rd.or_zr()?;
+    match op {
+        Operand::Immediate(amount) => {
+            let width = if rd.wide { 64 } else { 32 };
+            if amount < 0 || amount >= width as Cell {
+                return Err(Throw::INVALID_OPERAND);
+            }
+            let amount = amount as u32;
+            // These are aliases of the bitfield moves `ubfm` and `sbfm`.
+            let (opcode, immr, imms) = match kind {
+                Shift::Lsl => (0x5300_0000, (width - amount) % width, width - 1 - amount),
+                Shift::Lsr => (0x5300_0000, amount, width - 1),
+                Shift::Asr => (0x1300_0000, amount, width - 1),
+            };
+            Ok(opcode | rd.sf() | (rd.wide as u32) << 22 | immr << 16 | imms << 10 | fields)
+        }
+        Operand::Register(rm) => {
+            let opcode = match kind {
+                Shift::Lsl => 0x1AC0_2000,
+                Shift::Lsr => 0x1AC0_2400,
+                Shift::Asr => 0x1AC0_2800,
+            };
+            two_source(opcode, rd, rn, rm)
+        }
+    }
+}
+
+/// A data-processing instruction with two source registers.
+fn two_source(opcode: u32, rd: Register, rn: Register, rm: Register) -> Result<u32, Throw> {
+    same_width(&[rd, rn, rm])?;
+    Ok(opcode | rd.sf() | rm.or_zr()? << 16 | rn.or_zr()? << 5 | rd.or_zr()?)
+}
+
+const MOVN: u32 = 0x1280_0000;
+const MOVZ: u32 = 0x5280_0000;
+const MOVK: u32 = 0x7280_0000;
+
+fn move_wide(opcode: u32, rd: Register, imm: u64, halfword: u32) -> Result<u32, Throw> {
+    let imm = (imm >> (16 * halfword)) & 0xFFFF;
+    Ok(opcode | rd.sf() | halfword << 21 | (imm as u32) << 5 | rd.or_zr()?)
+}
+
+/// `mov`, which is one of several instructions depending on the operands,
+/// or a sequence of them for an immediate that can't be made in one.
+fn mov_instrs(rd: Register, op: Operand) -> Result<Vec<u32>, Throw> {
+    let imm = match op {
+        Operand::Register(rm) if rd.sp || rm.sp => {
+            return Ok(vec![add_sub(false, false, rd, rm, Operand::Immediate(0))?])
+        }
+        Operand::Register(rm) => return Ok(vec![logical(ORR, false, rd, rd.zero(), op)?]),
+        Operand::Immediate(imm) => imm,
+    };
+    let value = immediate_bits(imm, rd.wide)?;
+    let width = if rd.wide { 64 } else { 32 };
+    let halfwords = width / 16;
+    let mask = if rd.wide { !0 } else { 0xFFFF_FFFF };
+    let nonzero: Vec<u32> = (0..halfwords)
+        .filter(|halfword| (value >> (16 * halfword)) & 0xFFFF != 0)
+        .collect();
+    let inverted = !value & mask;
+    let not_ones: Vec<u32> = (0..halfwords)
+        .filter(|halfword| (inverted >> (16 * halfword)) & 0xFFFF != 0)
+        .collect();
+    if nonzero.len() <= 1 {
+        let halfword = nonzero.first().cloned().unwrap_or(0);
+        Ok(vec![move_wide(MOVZ, rd, value, halfword)?])
+    } else if not_ones.len() <= 1 {
+        let halfword = not_ones.first().cloned().unwrap_or(0);
+        Ok(vec![move_wide(MOVN, rd, inverted, halfword)?])
+    } else if rd.number != ZR && bitmask_immediate(value, width).is_some() {
+        Ok(vec![logical(ORR, false, rd, rd.zero(), op)?])
+    } else {
+        let mut instrs = vec![move_wide(MOVZ, rd, value, nonzero[0])?];
+        for halfword in &nonzero[1..] {
+            instrs.push(move_wide(MOVK, rd, value, *halfword)?);
+        }
+        Ok(instrs)
+    }
+}
+
+/// The access size of a load or store, as a power of two.
+const BYTE: u32 = 0;
+const HALFWORD: u32 = 1;
+
+/// `ldr` and `str`, and their byte and halfword forms if `size` is given.
+/// Otherwise the size follows the width of `rt`.
+fn load_store(
+    load: bool,
+    size: Option<u32>,
+    rt: Register,
+    rn: Register,
+    op: Operand,
+) -> Result<u32, Throw> {
+    if size.is_some() && rt.wide {
+        return Err(Throw::INVALID_OPERAND);
+    }
+    let size = size.unwrap_or(if rt.wide { 3 } else { 2 });
+    let unsigned_offset = size << 30 | 0x3900_0000 | (load as u32) << 22;
+    let fields = rn.base()? << 5 | rt.or_zr()?;
+    match op {
+        Operand::Immediate(offset) => {
+            let scale = 1 << size;
+            if offset >= 0 && offset % scale == 0 && offset / scale < 0x1000 {
+                Ok(unsigned_offset | ((offset / scale) as u32) << 10 | fields)
+            } else if offset >= -256 && offset < 256 {
+                // `ldur` and `stur`.
+                Ok(unsigned_offset & !0x0100_0000 | (offset as u32 & 0x1FF) << 12 | fields)
+            } else {
+                Err(Throw::INVALID_OPERAND)
+            }
+        }
+        Operand::Register(rm) => {
+            if !rm.wide {
+                return Err(Throw::INVALID_OPERAND);
+            }
+            Ok(unsigned_offset & !0x0100_0000 | 0x0020_6800 | rm.or_zr()? << 16 | fields)
+        }
+    }
+}
+
+/// `ldp` and `stp` with a signed offset.
+fn load_store_pair(
+    load: bool,
+    rt: Register,
+    rt2: Register,
+    rn: Register,
+    offset: Cell,
+) -> Result<u32, Throw> {
+    same_width(&[rt, rt2])?;
+    let (opcode, scale) = if rt.wide {
+        (0xA900_0000, 8)
+    } else {
+        (0x2900_0000, 4)
+    };
+    if offset % scale != 0 || offset / scale < -64 || offset / scale > 63 {
+        return Err(Throw::INVALID_OPERAND);
+    }
+    let imm7 = (offset / scale) as u32 & 0x7F;
+    Ok(opcode
+        | (load as u32) << 22
+        | imm7 << 15
+        | rt2.or_zr()? << 10
+        | rn.base()? << 5
+        | rt.or_zr()?)
+}
+
+/// The condition under which a conditional branch is taken.
+fn condition(cond: Cell) -> Result<u32, Throw> {
+    // `al` and `nv` can't be inverted, which `IF,` and friends need.
+    if cond < 0 || cond >= 14 {
+        return Err(Throw::INVALID_OPERAND);
+    }
+    Ok(cond as u32)
+}
+
+/// What `pop_runtime` returns: the throw code in `x0`, and the popped value in
+/// `x1`.
+#[repr(C)]
+struct Popped {
+    throw: isize,
+    value: Cell,
+}
+
+extern "C" fn pop_runtime(vm: &mut Vm) -> Popped {
+    match vm.pop() {
+        Ok(value) => Popped { throw: 0, value },
+        Err(throw) => Popped {
+            throw: throw.code(),
+            value: 0,
+        },
+    }
+}
+
+impl Vm {
+    fn pop_operand(&mut self) -> Result<Operand, Throw> {
+        let cell = self.pop()?;
+        Ok(match Register::from_cell(cell) {
+            Some(register) => Operand::Register(register),
+            None => Operand::Immediate(cell),
+        })
+    }
+
+    fn pop_register(&mut self) -> Result<Register, Throw> {
+        match self.pop_operand()? {
+            Operand::Register(register) => Ok(register),
+            Operand::Immediate(_) => Err(Throw::INVALID_OPERAND),
+        }
+    }
+
+    fn pop_immediate(&mut self) -> Result<Cell, Throw> {
+        match self.pop_operand()? {
+            Operand::Immediate(imm) => Ok(imm),
+            Operand::Register(_) => Err(Throw::INVALID_OPERAND),
+        }
+    }
+
+    /// Lay down an instruction at `HERE`.
+    fn assemble(&mut self, instr: u32) -> Result<usize, Throw> {
+        self.dict.align_to(INSTR_SIZE)?;
+        self.dict.emit(instr)
+    }
+
+    /// Lay down an instruction that depends on its own address.
+    fn assemble_at(&mut self, f: impl FnOnce(usize) -> Option<u32>) -> Result<usize, Throw> {
+        self.dict.align_to(INSTR_SIZE)?;
+        let instr = f(self.dict.here()).ok_or(Throw::BRANCH_OUT_OF_RANGE)?;
+        self.dict.emit(instr)
+    }
+
+    /// `( rd rn op -- )` for instructions with a destination, a register and
+    /// a register or immediate operand.
+    fn assemble_3(
+        &mut self,
+        f: impl FnOnce(Register, Register, Operand) -> Result<u32, Throw>,
+    ) -> Result<(), Throw> {
+        let op = self.pop_operand()?;
+        let rn = self.pop_register()?;
+        let rd = self.pop_register()?;
+        let instr = f(rd, rn, op)?;
+        self.assemble(instr).map(|_| ())
+    }
+
+    /// `( rt rn offset -- )` for loads and stores.
+    fn assemble_load_store(&mut self, load: bool, size: Option<u32>) -> Result<(), Throw> {
+        self.assemble_3(|rt, rn, op| load_store(load, size, rt, rn, op))
+    }
+
+    /// `( rt rt2 rn offset -- )`
+    fn assemble_pair(&mut self, load: bool) -> Result<(), Throw> {
+        let offset = self.pop_immediate()?;
+        let rn = self.pop_register()?;
+        let rt2 = self.pop_register()?;
+        let rt = self.pop_register()?;
+        let instr = load_store_pair(load, rt, rt2, rn, offset)?;
+        self.assemble(instr).map(|_| ())
+    }
+
+    /// Lay down a conditional branch that is taken when `cond` doesn't hold,
+    /// to `dest` or to be resolved later. Returns its address.
+    fn assemble_unless(&mut self, cond: Cell, dest: Option<usize>) -> Result<usize, Throw> {
+        let inverse = condition(cond)? ^ 1;
+        self.assemble_at(|here| codegen::b_cond(inverse, here, dest.unwrap_or(here)))
+    }
+
+    fn begin_code_word(&mut self, name: &str) -> Result<(), Throw> {
+        if self.user.colon.is_some() {
+            return Err(Throw::NESTED_COMPILATION);
+        }
+        let xt = self.dict.create_header(name, Flags::HIDDEN, 0)?;
+        self.dict.align()?;
+        let exit = self.dict.emit_all(&codegen::CODE_EPILOGUE)?;
+        let entry = self.dict.emit_all(&codegen::CODE_PROLOGUE)?;
+        self.dict.set_code(xt, entry);
+        self.user.colon = Some(Colon {
+            xt,
+            exit,
+            depth: self.data.depth(),
+            leaves: Vec::new(),
+        });
+        self.dict.search_assembler(true);
+        Ok(())
+    }
+}
+
+primitives! {
+    /// `( "name" -- )` Start a definition in machine code.
+    fn code(vm) {
+        let name = vm.parse_new_name()?;
+        vm.begin_code_word(&name)
+    }
+
+    fn end_code(vm) {
+        // A code word is finished just like a colon definition, which also
+        // synchronizes the caches.
+        vm.end_colon()?;
+        vm.dict.search_assembler(false);
+        Ok(())
+    }
+
+    fn assembler(vm) {
+        vm.dict.search_assembler(true);
+        Ok(())
+    }
+
+    fn forth(vm) {
+        vm.dict.search_assembler(false);
+        Ok(())
+    }
+
+    fn next(vm) {
+        vm.compile_exit()
+    }
+
+    /// `( reg -- )`
+    fn push(vm) {
+        let register = vm.pop_register()?;
+        // A `w` register is zero extended.
+        let to = Register {
+            wide: register.wide,
+            ..Register::X1
+        };
+        if register != to {
+            for instr in mov_instrs(to, Operand::Register(register))? {
+                vm.assemble(instr)?;
+            }
+        }
+        vm.compile_native_call(literal_runtime as usize)
+    }
+
+    /// `( reg -- )`
+    fn pop(vm) {
+        let register = vm.pop_register()?;
+        let from = Register {
+            wide: register.wide,
+            ..Register::X1
+        };
+        let instrs = mov_instrs(register, Operand::Register(from))?;
+        vm.compile_native_call(pop_runtime as usize)?;
+        for instr in instrs {
+            vm.assemble(instr)?;
+        }
+        Ok(())
+    }
+
+    fn add(vm) {
+        vm.assemble_3(|rd, rn, op| add_sub(false, false, rd, rn, op))
+    }
+
+    fn adds(vm) {
+        vm.assemble_3(|rd, rn, op| add_sub(false, true, rd, rn, op))
+    }
+
+    fn sub(vm) {
+        vm.assemble_3(|rd, rn, op| add_sub(true, false, rd, rn, op))
+    }
+
+    fn subs(vm) {
+        vm.assemble_3(|rd, rn, op| add_sub(true, true, rd, rn, op))
+    }
+
+    /// `( rn op -- )`
+    fn cmp(vm) {
+        let op = vm.pop_operand()?;
+        let rn = vm.pop_register()?;
+        let instr = add_sub(true, true, rn.zero(), rn, op)?;
+        vm.assemble(instr).map(|_| ())
+    }
+
+    fn and(vm) {
+        vm.assemble_3(|rd, rn, op| logical(AND, false, rd, rn, op))
+    }
+
+    fn ands(vm) {
+        vm.assemble_3(|rd, rn, op| logical(ANDS, false, rd, rn, op))
+    }
+
+    fn orr(vm) {
+        vm.assemble_3(|rd, rn, op| logical(ORR, false, rd, rn, op))
+    }
+
+    fn eor(vm) {
+        vm.assemble_3(|rd, rn, op| logical(EOR, false, rd, rn, op))
+    }
+
+    fn bic(vm) {
+        vm.assemble_3(|rd, rn, op| logical(AND, true, rd, rn, op))
+    }
+
+    /// `( rn op -- )`
+    fn tst(vm) {
+        let op = vm.pop_operand()?;
+        let rn = vm.pop_register()?;
+        let instr = logical(ANDS, false, rn.zero(), rn, op)?;
+        vm.assemble(instr).map(|_| ())
+    }
+
+    /// `( rd op -- )`
+    fn mov(vm) {
+        let op = vm.pop_operand()?;
+        let rd = vm.pop_register()?;
+        for instr in mov_instrs(rd, op)? {
+            vm.assemble(instr)?;
+        }
+        Ok(())
+    }
+
+    /// `( rd rm -- )`
+    fn mvn(vm) {
+        let rm = vm.pop_register()?;
+        let rd = vm.pop_register()?;
+        let instr = logical(ORR, true, rd, rd.zero(), Operand::Register(rm))?;
+        vm.assemble(instr).map(|_| ())
+    }
+
+    fn lsl(vm) {
+        vm.assemble_3(|rd, rn, op| shift(Shift::Lsl, rd, rn, op))
+    }
+
+    fn lsr(vm) {
+        vm.assemble_3(|rd, rn, op| shift(Shift::Lsr, rd, rn, op))
+    }
+
+    fn asr(vm) {
+        vm.assemble_3(|rd, rn, op| shift(Shift::Asr, rd, rn, op))
+    }
+
+    fn mul(vm) {
+        vm.assemble_3(|rd, rn, op| match op {
+            // `madd rd, rn, rm, zr`
+            Operand::Register(rm) => two_source(0x1B00_7C00, rd, rn, rm),
+            Operand::Immediate(_) => Err(Throw::INVALID_OPERAND),
+        })
+    }
+
+    fn udiv(vm) {
+        vm.assemble_3(|rd, rn, op| match op {
+            Operand::Register(rm) => two_source(0x1AC0_0800, rd, rn, rm),
+            Operand::Immediate(_) => Err(Throw::INVALID_OPERAND),
+        })
+    }
+
+    fn sdiv(vm) {
+        vm.assemble_3(|rd, rn, op| match op {
+            Operand::Register(rm) => two_source(0x1AC0_0C00, rd, rn, rm),
+            Operand::Immediate(_) => Err(Throw::INVALID_OPERAND),
+        })
+    }
+
+    fn ldr(vm) {
+        vm.assemble_load_store(true, None)
+    }
+
+    fn str_(vm) {
+        vm.assemble_load_store(false, None)
+    }
+
+    fn ldrb(vm) {
+        vm.assemble_load_store(true, Some(BYTE))
+    }
+
+    fn strb(vm) {
+        vm.assemble_load_store(false, Some(BYTE))
+    }
+
+    fn ldrh(vm) {
+        vm.assemble_load_store(true, Some(HALFWORD))
+    }
+
+    fn strh(vm) {
+        vm.assemble_load_store(false, Some(HALFWORD))
+    }
+
+    fn ldp(vm) {
+        vm.assemble_pair(true)
+    }
+
+    fn stp(vm) {
+        vm.assemble_pair(false)
+    }
+
+    /// `( addr -- )`
+    fn b(vm) {
+        let dest = vm.pop()? as usize;
+        vm.assemble_at(|here| codegen::b(here, dest)).map(|_| ())
+    }
+
+    /// `( addr -- )`
+    fn bl(vm) {
+        let dest = vm.pop()? as usize;
+        let addr = vm.assemble_at(|here| codegen::bl(here, dest))?;
+        vm.dict.record(Reloc::Call(addr));
+        Ok(())
+    }
+
+    /// `( reg -- )`
+    fn br(vm) {
+        let rn = vm.pop_register()?;
+        vm.assemble(codegen::br(rn.base()?)).map(|_| ())
+    }
+
+    /// `( reg -- )`
+    fn blr(vm) {
+        let rn = vm.pop_register()?;
+        vm.assemble(codegen::blr(rn.base()?)).map(|_| ())
+    }
+
+    fn ret(vm) {
+        vm.assemble(codegen::RET).map(|_| ())
+    }
+
+    /// `( reg addr -- )`
+    fn cbz(vm) {
+        let dest = vm.pop()? as usize;
+        let rt = vm.pop_register()?;
+        let (number, sf) = (rt.or_zr()?, rt.sf());
+        vm.assemble_at(|here| codegen::cbz(number, here, dest).map(|instr| instr & !(1 << 31) | sf))
+            .map(|_| ())
+    }
+
+    /// `( reg addr -- )`
+    fn cbnz(vm) {
+        let dest = vm.pop()? as usize;
+        let rt = vm.pop_register()?;
+        let (number, sf) = (rt.or_zr()?, rt.sf());
+        vm.assemble_at(|here| codegen::cbnz(number, here, dest).map(|instr| instr & !(1 << 31) | sf))
+            .map(|_| ())
+    }
+
+    /// `( cond -- orig )`
+    fn if_(vm) {
+        let cond = vm.pop()?;
+        let orig = vm.assemble_unless(cond, None)?;
+        vm.push(orig as Cell)
+    }
+
+    /// `( orig1 -- orig2 )`
+    fn else_(vm) {
+        let orig = vm.pop()? as usize;
+        let jump = vm.compile_jump(None)?;
+        let here = vm.dict.here();
+        vm.resolve(orig, here)?;
+        vm.push(jump as Cell)
+    }
+
+    /// `( orig -- )`
+    fn then(vm) {
+        let orig = vm.pop()? as usize;
+        let here = vm.dict.here();
+        vm.resolve(orig, here)
+    }
+
+    /// `( -- dest )`
+    fn begin(vm) {
+        vm.dict.align_to(INSTR_SIZE)?;
+        let here = vm.dict.here();
+        vm.push(here as Cell)
+    }
+
+    /// `( dest cond -- )`
+    fn until(vm) {
+        let cond = vm.pop()?;
+        let dest = vm.pop()? as usize;
+        let dest = vm.check_dest(dest)?;
+        vm.assemble_unless(cond, Some(dest)).map(|_| ())
+    }
+
+    /// `( dest -- )`
+    fn again(vm) {
+        let dest = vm.pop()? as usize;
+        let dest = vm.check_dest(dest)?;
+        vm.compile_jump(Some(dest)).map(|_| ())
+    }
+
+    /// `( dest cond -- orig dest )`
+    fn while_(vm) {
+        let cond = vm.pop()?;
+        let dest = vm.pop()?;
+        let orig = vm.assemble_unless(cond, None)?;
+        vm.push(orig as Cell)?;
+        vm.push(dest)
+    }
+
+    /// `( orig dest -- )`
+    fn repeat(vm) {
+        let dest = vm.pop()? as usize;
+        let orig = vm.pop()? as usize;
+        let dest = vm.check_dest(dest)?;
+        vm.compile_jump(Some(dest))?;
+        let here = vm.dict.here();
+        vm.resolve(orig, here)
+    }
+
+    /// `( reg sysreg -- )`
+    fn mrs(vm) {
+        let sysreg = vm.pop_immediate()? as u32 & 0xFFFF;
+        let rt = vm.pop_register()?;
+        if !rt.wide {
+            return Err(Throw::INVALID_OPERAND);
+        }
+        vm.assemble(0xD530_0000 | sysreg << 5 | rt.or_zr()?).map(|_| ())
+    }
+
+    /// `( sysreg reg -- )`
+    fn msr(vm) {
+        let rt = vm.pop_register()?;
+        let sysreg = vm.pop_immediate()? as u32 & 0xFFFF;
+        if !rt.wide {
+            return Err(Throw::INVALID_OPERAND);
+        }
+        vm.assemble(0xD510_0000 | sysreg << 5 | rt.or_zr()?).map(|_| ())
+    }
+
+    /// `( op0 op1 crn crm op2 -- sysreg )` Name a system register by its
+    /// encoding, for `MRS,` and `MSR,`.
+    fn sysreg(vm) {
+        let op2 = vm.pop()?;
+        let crm = vm.pop()?;
+        let crn = vm.pop()?;
+        let op1 = vm.pop()?;
+        let op0 = vm.pop()?;
+        let fits = |field: Cell, max: Cell| field >= 0 && field <= max;
+        let valid = (op0 == 2 || op0 == 3) && fits(op1, 7) && fits(crn, 15) && fits(crm, 15);
+        if !valid || !fits(op2, 7) {
+            return Err(Throw::INVALID_OPERAND);
+        }
+        vm.push((op0 - 2) << 14 | op1 << 11 | crn << 7 | crm << 3 | op2)
+    }
+
+    /// `( imm -- )` Mask the interrupts selected by `imm`.
+    fn daifset(vm) {
+        let imm = vm.pop_immediate()? as u32 & 0xF;
+        vm.assemble(0xD503_40DF | imm << 8).map(|_| ())
+    }
+
+    /// `( imm -- )` Unmask the interrupts selected by `imm`.
+    fn daifclr(vm) {
+        let imm = vm.pop_immediate()? as u32 & 0xF;
+        vm.assemble(0xD503_40FF | imm << 8).map(|_| ())
+    }
+
+    /// `( option -- )`
+    fn dmb(vm) {
+        let option = vm.pop_immediate()? as u32 & 0xF;
+        vm.assemble(0xD503_30BF | option << 8).map(|_| ())
+    }
+
+    /// `( option -- )`
+    fn dsb(vm) {
+        let option = vm.pop_immediate()? as u32 & 0xF;
+        vm.assemble(0xD503_309F | option << 8).map(|_| ())
+    }
+
+    fn isb(vm) {
+        vm.assemble(0xD503_3FDF).map(|_| ())
+    }
+
+    fn nop(vm) {
+        vm.assemble(codegen::NOP).map(|_| ())
+    }
+
+    fn wfi(vm) {
+        vm.assemble(0xD503_207F).map(|_| ())
+    }
+
+    fn wfe(vm) {
+        vm.assemble(0xD503_205F).map(|_| ())
+    }
+
+    fn sev(vm) {
+        vm.assemble(0xD503_209F).map(|_| ())
+    }
+
+    /// `( imm -- )`
+    fn svc(vm) {
+        let imm = vm.pop_immediate()? as u32 & 0xFFFF;
+        vm.assemble(0xD400_0001 | imm << 5).map(|_| ())
+    }
+
+    /// `( imm -- )`
+    fn brk(vm) {
+        let imm = vm.pop_immediate()? as u32 & 0xFFFF;
+        vm.assemble(0xD420_0000 | imm << 5).map(|_| ())
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    ("CODE", NONE, code),
+    ("ASSEMBLER", NONE, assembler),
+    ("END-CODE", ASSEMBLER, end_code),
+    ("FORTH", NONE, forth),
+    ("NEXT,", ASSEMBLER, next),
+    ("PUSH,", ASSEMBLER, push),
+    ("POP,", ASSEMBLER, pop),
+    ("ADD,", ASSEMBLER, add),
+    ("ADDS,", ASSEMBLER, adds),
+    ("SUB,", ASSEMBLER, sub),
+    ("SUBS,", ASSEMBLER, subs),
+    ("CMP,", ASSEMBLER, cmp),
+    ("AND,", ASSEMBLER, and),
+    ("ANDS,", ASSEMBLER, ands),
+    ("ORR,", ASSEMBLER, orr),
+    ("EOR,", ASSEMBLER, eor),
+    ("BIC,", ASSEMBLER, bic),
+    ("TST,", ASSEMBLER, tst),
+    ("MOV,", ASSEMBLER, mov),
+    ("MVN,", ASSEMBLER, mvn),
+    ("LSL,", ASSEMBLER, lsl),
+    ("LSR,", ASSEMBLER, lsr),
+    ("ASR,", ASSEMBLER, asr),
+    ("MUL,", ASSEMBLER, mul),
+    ("UDIV,", ASSEMBLER, udiv),
+    ("SDIV,", ASSEMBLER, sdiv),
+    ("LDR,", ASSEMBLER, ldr),
+    ("STR,", ASSEMBLER, str_),
+    ("LDRB,", ASSEMBLER, ldrb),
+    ("STRB,", ASSEMBLER, strb),
+    ("LDRH,", ASSEMBLER, ldrh),
+    ("STRH,", ASSEMBLER, strh),
+    ("LDP,", ASSEMBLER, ldp),
+    ("STP,", ASSEMBLER, stp),
+    ("B,", ASSEMBLER, b),
+    ("BL,", ASSEMBLER, bl),
+    ("BR,", ASSEMBLER, br),
+    ("BLR,", ASSEMBLER, blr),
+    ("RET,", ASSEMBLER, ret),
+    ("CBZ,", ASSEMBLER, cbz),
+    ("CBNZ,", ASSEMBLER, cbnz),
+    ("IF,", ASSEMBLER, if_),
+    ("ELSE,", ASSEMBLER, else_),
+    ("THEN,", ASSEMBLER, then),
+    ("BEGIN,", ASSEMBLER, begin),
+    ("UNTIL,", ASSEMBLER, until),
+    ("AGAIN,", ASSEMBLER, again),
+    ("WHILE,", ASSEMBLER, while_),
+    ("REPEAT,", ASSEMBLER, repeat),
+    ("MRS,", ASSEMBLER, mrs),
+    ("MSR,", ASSEMBLER, msr),
+    ("SYSREG", ASSEMBLER, sysreg),
+    ("DAIFSET,", ASSEMBLER, daifset),
+    ("DAIFCLR,", ASSEMBLER, daifclr),
+    ("DMB,", ASSEMBLER, dmb),
+    ("DSB,", ASSEMBLER, dsb),
+    ("ISB,", ASSEMBLER, isb),
+    ("NOP,", ASSEMBLER, nop),
+    ("WFI,", ASSEMBLER, wfi),
+    ("WFE,", ASSEMBLER, wfe),
+    ("SEV,", ASSEMBLER, sev),
+    ("SVC,", ASSEMBLER, svc),
+    ("BRK,", ASSEMBLER, brk),
+];
+
+const CONDITIONS: &[(&str, Cell)] = &[
+    ("EQ", 0),
+    ("NE", 1),
+    ("CS", 2),
+    ("HS", 2),
+    ("CC", 3),
+    ("LO", 3),
+    ("MI", 4),
+    ("PL", 5),
+    ("VS", 6),
+    ("VC", 7),
+    ("HI", 8),
+    ("LS", 9),
+    ("GE", 10),
+    ("LT", 11),
+    ("GT", 12),
+    ("LE", 13),
+];
+
+/// The options of `DMB,` and `DSB,`.
+const BARRIER_OPTIONS: &[(&str, Cell)] = &[
+    ("OSHLD", 1),
+    ("OSHST", 2),
+    ("OSH", 3),
+    ("NSHLD", 5),
+    ("NSHST", 6),
+    ("NSH", 7),
+    ("ISHLD", 9),
+    ("ISHST", 10),
+    ("ISH", 11),
+    ("LD", 13),
+    ("ST", 14),
+    ("SY", 15),
+];
+
+/// System registers by name, with their `op0 op1 CRn CRm op2` encodings.
+const SYSTEM_REGISTERS: &[(&str, [Cell; 5])] = &[
+    ("MIDR_EL1", [3, 0, 0, 0, 0]),
+    ("MPIDR_EL1", [3, 0, 0, 0, 5]),
+    ("ID_AA64MMFR0_EL1", [3, 0, 0, 7, 0]),
+    ("SCTLR_EL1", [3, 0, 1, 0, 0]),
+    ("CPACR_EL1", [3, 0, 1, 0, 2]),
+    ("TTBR0_EL1", [3, 0, 2, 0, 0]),
+    ("TTBR1_EL1", [3, 0, 2, 0, 1]),
+    ("TCR_EL1", [3, 0, 2, 0, 2]),
+    ("SPSR_EL1", [3, 0, 4, 0, 0]),
+    ("ELR_EL1", [3, 0, 4, 0, 1]),
+    ("SP_EL0", [3, 0, 4, 1, 0]),
+    ("CURRENTEL", [3, 0, 4, 2, 2]),
+    ("ESR_EL1", [3, 0, 5, 2, 0]),
+    ("FAR_EL1", [3, 0, 6, 0, 0]),
+    ("PAR_EL1", [3, 0, 7, 4, 0]),
+    ("MAIR_EL1", [3, 0, 10, 2, 0]),
+    ("VBAR_EL1", [3, 0, 12, 0, 0]),
+    ("TPIDR_EL1", [3, 0, 13, 0, 4]),
+    ("CNTKCTL_EL1", [3, 0, 14, 1, 0]),
+    ("CTR_EL0", [3, 3, 0, 0, 1]),
+    ("DAIF", [3, 3, 4, 2, 1]),
+    ("CNTFRQ_EL0", [3, 3, 14, 0, 0]),
+    ("CNTPCT_EL0", [3, 3, 14, 0, 1]),
+    ("CNTVCT_EL0", [3, 3, 14, 0, 2]),
+    ("CNTP_TVAL_EL0", [3, 3, 14, 2, 0]),
+    ("CNTP_CTL_EL0", [3, 3, 14, 2, 1]),
+    ("CNTP_CVAL_EL0", [3, 3, 14, 2, 2]),
+    ("CNTV_TVAL_EL0", [3, 3, 14, 3, 0]),
+    ("CNTV_CTL_EL0", [3, 3, 14, 3, 1]),
+    ("CNTV_CVAL_EL0", [3, 3, 14, 3, 2]),
+];
+
+/// Define the registers, condition codes, barrier options and system
+/// registers as constants in the `ASSEMBLER` vocabulary.
+pub fn install(vm: &mut Vm) -> Result<(), Throw> {
+    let mut define = |name: &str, value: Cell| -> Result<(), Throw> {
+        vm.constant(name, value)?;
+        vm.dict.set_flags(ASSEMBLER, true);
+        Ok(())
+    };
+    for number in 0..31 {
+        for &wide in &[true, false] {
+            let register = Register {
+                number,
+                wide,
+                sp: false,
+            };
+            let prefix = if wide { "X" } else { "W" };
+            define(&format!("{}{}", prefix, number), register.into_cell())?;
+        }
+    }
+    for &wide in &[true, false] {
+        let zero = Register {
+            number: ZR,
+            wide,
+            sp: false,
+        };
+        define(if wide { "XZR" } else { "WZR" }, zero.into_cell())?;
+        let sp = Register { sp: true, ..zero };
+        define(if wide { "SP" } else { "WSP" }, sp.into_cell())?;
+    }
+    for (name, value) in CONDITIONS.iter().chain(BARRIER_OPTIONS) {
+        define(name, *value)?;
+    }
+    for &(name, [op0, op1, crn, crm, op2]) in SYSTEM_REGISTERS {
+        define(
+            name,
+            (op0 - 2) << 14 | op1 << 11 | crn << 7 | crm << 3 | op2,
+        )?;
+    }
+    Ok(())
+}
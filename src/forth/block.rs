@@ -0,0 +1,293 @@
+//! The block word set, backed by the SD card. Blocks are 1 KiB each, stored
+//! back to back in the partition of type `0xDA` that the Makefile reserves on
+//! the SD card image, with block zero at its start. Blocks are read into a
+//! small cache of buffers on the heap, and the least recently used buffer is
+//! written back (if it was updated) to make room for another block.
+//!
+//! The SD card is initialized the first time a block is needed.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use super::{
+    dictionary::{Entry, Flags},
+    words::NONE,
+    Cell, Throw, Vm,
+};
+use crate::rpi::emmc::{Card, SECTOR_SIZE};
+
+pub const BLOCK_SIZE: usize = 1024;
+
+/// Blocks are displayed and interpreted as 16 lines of this many characters.
+pub const LINE_LENGTH: usize = 64;
+
+const SECTORS_PER_BLOCK: usize = BLOCK_SIZE / SECTOR_SIZE;
+
+/// The number of blocks that can be in memory at once.
+const BUFFER_COUNT: usize = 8;
+
+/// The MBR partition type of the block region.
+const PARTITION_TYPE: u8 = 0xDA;
+
+/// The part of the SD card that holds blocks.
+struct Region {
+    /// The first sector of block zero.
+    start: u32,
+    /// The number of blocks in the region.
+    blocks: usize,
+}
+
+impl Region {
+    fn open() -> Option<Region> {
+        let (start, sectors) = Card::with(|card| card.find_partition(PARTITION_TYPE)).ok()??;
+        Some(Region {
+            start,
+            blocks: sectors as usize / SECTORS_PER_BLOCK,
+        })
+    }
+
+    fn sectors(&self, block: usize) -> impl Iterator<Item = (u32, usize)> {
+        let first = self.start + (block * SECTORS_PER_BLOCK) as u32;
+        (0..SECTORS_PER_BLOCK).map(move |index| (first + index as u32, index * SECTOR_SIZE))
+    }
+
+    fn read(&mut self, block: usize, data: &mut [u8; BLOCK_SIZE]) -> Result<(), Throw> {
+        for (lba, offset) in self.sectors(block) {
+            let mut sector = [0; SECTOR_SIZE];
+            Card::with(|card| card.read_sector(lba, &mut sector)).map_err(|_| Throw::BLOCK_READ)?;
+            data[offset..offset + SECTOR_SIZE].copy_from_slice(&sector);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, block: usize, data: &[u8; BLOCK_SIZE]) -> Result<(), Throw> {
+        for (lba, offset) in self.sectors(block) {
+            let mut sector = [0; SECTOR_SIZE];
+            sector.copy_from_slice(&data[offset..offset + SECTOR_SIZE]);
+            Card::with(|card| card.write_sector(lba, &sector)).map_err(|_| Throw::BLOCK_WRITE)?;
+        }
+        Ok(())
+    }
+}
+
+struct Buffer {
+    block: usize,
+    data: Box<[u8; BLOCK_SIZE]>,
+    dirty: bool,
+    /// The value of `Blocks::clock` when this buffer was last used.
+    used: u64,
+}
+
+/// The block buffers, and the state of the block words.
+pub struct Blocks {
+    region: Option<Region>,
+    buffers: Vec<Buffer>,
+    /// Counts accesses, to find the least recently used buffer.
+    clock: u64,
+    /// The buffer most recently returned by `BLOCK` or `BUFFER`, which
+    /// `UPDATE` marks as modified.
+    current: Option<usize>,
+    /// The block most recently listed, as `SCR` holds it.
+    scr: Cell,
+}
+
+impl Blocks {
+    pub const fn new() -> Blocks {
+        Blocks {
+            region: None,
+            buffers: Vec::new(),
+            clock: 0,
+            current: None,
+            scr: 0,
+        }
+    }
+
+    fn region(&mut self) -> Result<&mut Region, Throw> {
+        if self.region.is_none() {
+            self.region = Region::open();
+        }
+        self.region.as_mut().ok_or(Throw::BLOCK_READ)
+    }
+
+    /// Assign a buffer to `block`, reading its contents from the card if
+    /// `read` is set, and return the address of the buffer's data.
+    fn assign(&mut self, block: Cell, read: bool) -> Result<usize, Throw> {
+        let region = self.region()?;
+        if block < 0 || block as usize >= region.blocks {
+            return Err(Throw::INVALID_BLOCK_NUMBER);
+        }
+        let block = block as usize;
+        self.clock += 1;
+        let index = match self.buffers.iter().position(|buffer| buffer.block == block) {
+            Some(index) => index,
+            None => {
+                let index = self.evict()?;
+                let buffer = &mut self.buffers[index];
+                if read {
+                    let region = self.region.as_mut().unwrap();
+                    // Don't leave a half-read buffer assigned to anything.
+                    buffer.block = usize::max_value();
+                    region.read(block, &mut buffer.data)?;
+                }
+                buffer.block = block;
+                index
+            }
+        };
+        let buffer = &mut self.buffers[index];
+        buffer.used = self.clock;
+        self.current = Some(index);
+        Ok(buffer.data.as_ptr() as usize)
+    }
+
+    /// Find a buffer to reuse, writing it back first if it was updated.
+    fn evict(&mut self) -> Result<usize, Throw> {
+        if self.buffers.len() < BUFFER_COUNT {
+            self.buffers.push(Buffer {
+                block: usize::max_value(),
+                data: Box::new([0; BLOCK_SIZE]),
+                dirty: false,
+                used: 0,
+            });
+            return Ok(self.buffers.len() - 1);
+        }
+        let (index, _) = self
+            .buffers
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, buffer)| buffer.used)
+            .unwrap();
+        self.write_back(index)?;
+        Ok(index)
+    }
+
+    fn write_back(&mut self, index: usize) -> Result<(), Throw> {
+        let buffer = &mut self.buffers[index];
+        if buffer.dirty {
+            let region = self.region.as_mut().ok_or(Throw::BLOCK_WRITE)?;
+            region.write(buffer.block, &buffer.data)?;
+            buffer.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn update(&mut self) {
+        if let Some(index) = self.current {
+            self.buffers[index].dirty = true;
+        }
+    }
+
+    fn save_buffers(&mut self) -> Result<(), Throw> {
+        for index in 0..self.buffers.len() {
+            self.write_back(index)?;
+        }
+        Ok(())
+    }
+
+    fn empty_buffers(&mut self) {
+        self.buffers.clear();
+        self.current = None;
+    }
+}
+
+impl Vm {
+    /// Interpret block `block`.
+    fn load(&mut self, block: Cell) -> Result<(), Throw> {
+        if block == 0 {
+            // `BLK` is zero when not interpreting a block, so block zero
+            // can't be loaded.
+            return Err(Throw::INVALID_BLOCK_NUMBER);
+        }
+        // The buffer could be reused by a nested `LOAD`, so interpret a copy.
+        let addr = self.blocks.assign(block, true)?;
+        let text = unsafe { core::slice::from_raw_parts(addr as *const u8, BLOCK_SIZE) }.to_vec();
+        self.interpret_block(block, text.as_ptr() as usize, text.len())
+    }
+}
+
+primitives! {
+    fn block(vm) {
+        let block = vm.pop()?;
+        let addr = vm.blocks.assign(block, true)?;
+        vm.push(addr as Cell)
+    }
+
+    fn buffer(vm) {
+        let block = vm.pop()?;
+        let addr = vm.blocks.assign(block, false)?;
+        vm.push(addr as Cell)
+    }
+
+    fn update(vm) {
+        vm.blocks.update();
+        Ok(())
+    }
+
+    fn save_buffers(vm) {
+        vm.blocks.save_buffers()
+    }
+
+    fn empty_buffers(vm) {
+        vm.blocks.empty_buffers();
+        Ok(())
+    }
+
+    fn flush(vm) {
+        vm.blocks.save_buffers()?;
+        vm.blocks.empty_buffers();
+        Ok(())
+    }
+
+    fn list(vm) {
+        let block = vm.pop()?;
+        let addr = vm.blocks.assign(block, true)?;
+        vm.blocks.scr = block;
+        let text = unsafe { core::slice::from_raw_parts(addr as *const u8, BLOCK_SIZE) };
+        let mut line_bytes = [0; LINE_LENGTH];
+        for (number, line) in text.chunks(LINE_LENGTH).enumerate() {
+            for (byte, out) in line.iter().zip(line_bytes.iter_mut()) {
+                *out = if (b' '..=b'~').contains(byte) { *byte } else { b' ' };
+            }
+            let line = core::str::from_utf8(&line_bytes).unwrap_or("");
+            print!("\n{:2} {}", number, line);
+        }
+        println!();
+        Ok(())
+    }
+
+    fn load(vm) {
+        let block = vm.pop()?;
+        vm.load(block)
+    }
+
+    fn thru(vm) {
+        let last = vm.pop()?;
+        let first = vm.pop()?;
+        for block in first..=last {
+            vm.load(block)?;
+        }
+        Ok(())
+    }
+
+    fn blk(vm) {
+        let addr = &vm.user.blk as *const Cell as Cell;
+        vm.push(addr)
+    }
+
+    fn scr(vm) {
+        let addr = &vm.blocks.scr as *const Cell as Cell;
+        vm.push(addr)
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    ("BLOCK", NONE, block),
+    ("BUFFER", NONE, buffer),
+    ("UPDATE", NONE, update),
+    ("SAVE-BUFFERS", NONE, save_buffers),
+    ("EMPTY-BUFFERS", NONE, empty_buffers),
+    ("FLUSH", NONE, flush),
+    ("LIST", NONE, list),
+    ("LOAD", NONE, load),
+    ("THRU", NONE, thru),
+    ("BLK", NONE, blk),
+    ("SCR", NONE, scr),
+];
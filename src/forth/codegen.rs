@@ -0,0 +1,264 @@
+//! Instruction encodings for the subroutine-threaded code that colon
+//! definitions compile to.
+//!
+//! Every word's code field points at a native function with the signature
+//! `extern "C" fn(&mut Vm) -> isize`, returning zero on success or a `THROW`
+//! code. A colon definition keeps the `Vm` pointer in the callee-saved `x19`
+//! and compiles each word it references to a direct `bl`, followed by a
+//! `cbnz` that leaves the definition early if the callee threw. Each
+//! definition is laid out as:
+//!
+//! ```text
+//! exit:   ldr     x19, [sp, #16]
+//!         ldp     x29, x30, [sp], #32
+//!         ret
+//! entry:  stp     x29, x30, [sp, #-32]!
+//!         mov     x29, sp
+//!         str     x19, [sp, #16]
+//!         mov     x19, x0
+//!         ...                     // compiled body
+//!         mov     x0, #0
+//!         b       exit
+//! ```
+//!
+//! so that the shared exit sequence is always a backwards branch away.
+
+pub use crate::rpi::mmu::sync_icache;
+
+pub const INSTR_SIZE: usize = 4;
+
+pub const X0: u32 = 0;
+pub const X1: u32 = 1;
+pub const X16: u32 = 16;
+pub const X19: u32 = 19;
+
+pub const RET: u32 = 0xD65F_03C0;
+pub const NOP: u32 = 0xD503_201F;
+
+/// The instructions that save the frame record and `x19`, and stash the `Vm`
+/// pointer in `x19`.
+pub const PROLOGUE: [u32; 4] = [
+    0xA9BE_7BFD, // stp x29, x30, [sp, #-32]!
+    0x9100_03FD, // mov x29, sp
+    0xF900_0BF3, // str x19, [sp, #16]
+    0xAA00_03F3, // mov x19, x0
+];
+
+/// The shared exit sequence. The return value must already be in `x0`.
+pub const EPILOGUE: [u32; 3] = [
+    0xF940_0BF3, // ldr x19, [sp, #16]
+    0xA8C2_7BFD, // ldp x29, x30, [sp], #32
+    RET,
+];
+
+/// The prologue of a `CODE` word, which also saves `x20`-`x28` so that the
+/// code can keep values in them across calls into the kernel.
+pub const CODE_PROLOGUE: [u32; 8] = [
+    0xA9BA_7BFD, // stp x29, x30, [sp, #-96]!
+    0x9100_03FD, // mov x29, sp
+    0xA901_53F3, // stp x19, x20, [sp, #16]
+    0xA902_5BF5, // stp x21, x22, [sp, #32]
+    0xA903_63F7, // stp x23, x24, [sp, #48]
+    0xA904_6BF9, // stp x25, x26, [sp, #64]
+    0xA905_73FB, // stp x27, x28, [sp, #80]
+    0xAA00_03F3, // mov x19, x0
+];
+
+/// The exit sequence matching `CODE_PROLOGUE`.
+pub const CODE_EPILOGUE: [u32; 7] = [
+    0xA945_73FB, // ldp x27, x28, [sp, #80]
+    0xA944_6BF9, // ldp x25, x26, [sp, #64]
+    0xA943_63F7, // ldp x23, x24, [sp, #48]
+    0xA942_5BF5, // ldp x21, x22, [sp, #32]
+    0xA941_53F3, // ldp x19, x20, [sp, #16]
+    0xA8C6_7BFD, // ldp x29, x30, [sp], #96
+    RET,
+];
+
+/// `mov x0, x19`, to pass the `Vm` pointer on to a callee.
+pub const MOV_X0_VM: u32 = 0xAA13_03E0;
+
+#[inline]
+fn branch_offset(from: usize, to: usize) -> isize {
+    (to as isize).wrapping_sub(from as isize)
+}
+
+#[inline]
+fn fits_signed(value: isize, bits: u32) -> bool {
+    let limit = 1 << (bits - 1);
+    value >= -limit && value < limit
+}
+
+/// `movz xd, #imm, lsl #(16 * shift)`
+#[inline]
+pub fn movz(rd: u32, imm: u16, shift: u32) -> u32 {
+    0xD280_0000 | (shift << 21) | ((imm as u32) << 5) | rd
+}
+
+/// `movk xd, #imm, lsl #(16 * shift)`
+#[inline]
+pub fn movk(rd: u32, imm: u16, shift: u32) -> u32 {
+    0xF280_0000 | (shift << 21) | ((imm as u32) << 5) | rd
+}
+
+/// Load a 64-bit constant into `rd`. This always produces four instructions,
+/// so that the constant can be patched in place later.
+pub fn load_constant(rd: u32, value: u64) -> [u32; 4] {
+    [
+        movz(rd, value as u16, 0),
+        movk(rd, (value >> 16) as u16, 1),
+        movk(rd, (value >> 32) as u16, 2),
+        movk(rd, (value >> 48) as u16, 3),
+    ]
+}
+
+/// `b target`, if the target is within range.
+pub fn b(from: usize, to: usize) -> Option<u32> {
+    let offset = branch_offset(from, to);
+    if offset % 4 != 0 || !fits_signed(offset, 28) {
+        return None;
+    }
+    Some(0x1400_0000 | (((offset >> 2) as u32) & 0x03FF_FFFF))
+}
+
+/// `bl target`, if the target is within range.
+pub fn bl(from: usize, to: usize) -> Option<u32> {
+    b(from, to).map(|instr| instr | 0x8000_0000)
+}
+
+/// `blr xn`
+#[inline]
+pub fn blr(rn: u32) -> u32 {
+    0xD63F_0000 | (rn << 5)
+}
+
+/// `br xn`
+#[inline]
+pub fn br(rn: u32) -> u32 {
+    0xD61F_0000 | (rn << 5)
+}
+
+fn compare_and_branch(op: u32, rt: u32, from: usize, to: usize) -> Option<u32> {
+    let offset = branch_offset(from, to);
+    if offset % 4 != 0 || !fits_signed(offset, 21) {
+        return None;
+    }
+    Some(op | ((((offset >> 2) as u32) & 0x7_FFFF) << 5) | rt)
+}
+
+/// `cbz xt, target`
+pub fn cbz(rt: u32, from: usize, to: usize) -> Option<u32> {
+    compare_and_branch(0xB400_0000, rt, from, to)
+}
+
+/// `cbnz xt, target`
+pub fn cbnz(rt: u32, from: usize, to: usize) -> Option<u32> {
+    compare_and_branch(0xB500_0000, rt, from, to)
+}
+
+/// `b.cond target`
+pub fn b_cond(cond: u32, from: usize, to: usize) -> Option<u32> {
+    compare_and_branch(0x5400_0000, cond, from, to)
+}
+
+fn test_and_branch(op: u32, from: usize, to: usize) -> Option<u32> {
+    let offset = branch_offset(from, to);
+    if offset % 4 != 0 || !fits_signed(offset, 16) {
+        return None;
+    }
+    Some(op | ((((offset >> 2) as u32) & 0x3FFF) << 5))
+}
+
+/// `tbnz xt, #63, target`, which branches if `xt` is negative.
+pub fn tbnz_negative(rt: u32, from: usize, to: usize) -> Option<u32> {
+    test_and_branch(0xB7F8_0000 | rt, from, to)
+}
+
+/// Re-encode the branch `instr` at `addr` so that it jumps to `to` instead,
+/// keeping its condition and register. This is how forward branches are
+/// resolved once their destination is known.
+pub fn retarget(addr: usize, instr: u32, to: usize) -> Option<u32> {
+    if instr & 0x7C00_0000 == 0x1400_0000 {
+        // b, bl
+        b(addr, to).map(|new| new | (instr & 0x8000_0000))
+    } else if instr & 0x7E00_0000 == 0x3400_0000 {
+        // cbz, cbnz
+        compare_and_branch(instr & 0xFF00_0000, instr & 0x1F, addr, to)
+    } else if instr & 0xFF00_0010 == 0x5400_0000 {
+        // b.cond
+        b_cond(instr & 0xF, addr, to)
+    } else if instr & 0x7E00_0000 == 0x3600_0000 {
+        // tbz, tbnz
+        test_and_branch(instr & 0xFFF8_001F, addr, to)
+    } else {
+        None
+    }
+}
+
+/// The length in instructions of a `stub`.
+pub const STUB_LEN: usize = 10;
+
+/// The index of the instructions within a `stub` that load its target, so
+/// that `DOES>` can redirect it.
+pub const STUB_TARGET: usize = 4;
+
+/// A tail call to `target` with `arg` in `x1`. Words made by `CREATE` and
+/// `CONSTANT` use one of these as their code, so that they follow the same
+/// calling convention as everything else. The stub is padded to a whole
+/// number of cells, and the data field follows it directly.
+pub fn stub(arg: u64, target: u64) -> [u32; STUB_LEN] {
+    let arg = load_constant(X1, arg);
+    let target = load_constant(X16, target);
+    [
+        arg[0],
+        arg[1],
+        arg[2],
+        arg[3],
+        target[0],
+        target[1],
+        target[2],
+        target[3],
+        br(X16),
+        NOP,
+    ]
+}
+
+/// Decode the target of a `b` or `bl` instruction at `addr`.
+pub fn decode_branch(addr: usize, instr: u32) -> Option<usize> {
+    if instr & 0x7C00_0000 != 0x1400_0000 {
+        return None;
+    }
+    // Sign extend the 26-bit immediate.
+    let imm26 = ((instr << 6) as i32) >> 6;
+    Some((addr as isize + (imm26 as isize) * 4) as usize)
+}
+
+/// Decode the target of a `cbz`, `cbnz`, `b.cond`, `tbz` or `tbnz`
+/// instruction at `addr`.
+pub fn decode_conditional(addr: usize, instr: u32) -> Option<usize> {
+    let offset = if instr & 0x7E00_0000 == 0x3400_0000 || instr & 0xFF00_0010 == 0x5400_0000 {
+        // Sign extend the 19-bit immediate.
+        (((instr << 8) as i32) >> 13) as isize
+    } else if instr & 0x7E00_0000 == 0x3600_0000 {
+        // Sign extend the 14-bit immediate.
+        (((instr << 13) as i32) >> 18) as isize
+    } else {
+        return None;
+    };
+    Some((addr as isize + offset * 4) as usize)
+}
+
+/// Decode the register and value loaded by a `load_constant` sequence.
+pub fn decode_constant(instrs: &[u32]) -> Option<(u32, u64)> {
+    let instrs = instrs.get(..4)?;
+    let rd = instrs[0] & 0x1F;
+    let mut value = 0;
+    for (shift, instr) in instrs.iter().enumerate() {
+        let opcode = if shift == 0 { 0xD280_0000 } else { 0xF280_0000 };
+        if instr & 0xFFE0_001F != opcode | ((shift as u32) << 21) | rd {
+            return None;
+        }
+        value |= (((instr >> 5) & 0xFFFF) as u64) << (16 * shift);
+    }
+    Some((rd, value))
+}
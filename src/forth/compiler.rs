@@ -0,0 +1,295 @@
+use alloc::{string::String, vec::Vec};
+use core::ptr;
+
+use super::{
+    codegen::{self, INSTR_SIZE, X0, X1, X16},
+    dictionary::Reloc,
+    Cell, Colon, Flags, Throw, Vm, Xt, FALSE, TRUE,
+};
+
+pub(super) extern "C" fn literal_runtime(vm: &mut Vm, value: Cell) -> isize {
+    Throw::into_code(vm.push(value))
+}
+
+/// The code of a word made by `CREATE`, until `DOES>` replaces it.
+pub(super) extern "C" fn create_runtime(vm: &mut Vm, param: usize) -> isize {
+    Throw::into_code(vm.push(param as Cell))
+}
+
+/// Point the code of the most recent `CREATE`d word at `entry`, the code that
+/// follows a `DOES>`. The stub passes that code the data field address in
+/// `x1`.
+pub(super) extern "C" fn does_runtime(vm: &mut Vm, entry: usize) -> isize {
+    let code = match vm.dict.latest() {
+        Some(header) if header.param() != 0 => header.code(),
+        _ => return Throw::NOT_CREATED.code(),
+    };
+    let target = codegen::load_constant(X16, entry as u64);
+    let load = code + codegen::STUB_TARGET * INSTR_SIZE;
+    vm.dict.patch_all(load, &target);
+    0
+}
+
+/// Compile a call to `xt` into the current definition, on behalf of a word
+/// that used `POSTPONE` on a non-immediate word.
+pub(super) extern "C" fn compile_call_runtime(vm: &mut Vm, xt: Xt) -> isize {
+    Throw::into_code(vm.compile_call(xt))
+}
+
+impl Vm {
+    #[inline]
+    fn exit_addr(&self) -> Result<usize, Throw> {
+        self.user
+            .colon
+            .as_ref()
+            .map(|colon| colon.exit)
+            .ok_or(Throw::COMPILE_ONLY)
+    }
+
+    #[inline]
+    pub(super) fn colon_mut(&mut self) -> Result<&mut Colon, Throw> {
+        self.user.colon.as_mut().ok_or(Throw::COMPILE_ONLY)
+    }
+
+    /// Lay down the exit sequence and prologue for a new piece of native code,
+    /// returning the addresses of both.
+    pub(super) fn begin_code(&mut self) -> Result<(usize, usize), Throw> {
+        self.dict.align()?;
+        let exit = self.dict.emit_all(&codegen::EPILOGUE)?;
+        let entry = self.dict.emit_all(&codegen::PROLOGUE)?;
+        Ok((exit, entry))
+    }
+
+    /// Emit a branch-and-link to `target`, using an indirect call if it is
+    /// too far away for `bl`.
+    fn emit_call(&mut self, target: usize) -> Result<(), Throw> {
+        match codegen::bl(self.dict.here(), target) {
+            Some(instr) => {
+                let addr = self.dict.emit(instr)?;
+                self.dict.record(Reloc::Call(addr));
+            }
+            None => {
+                let load = codegen::load_constant(X16, target as u64);
+                let addr = self.dict.emit_all(&load)?;
+                self.dict.record(Reloc::Constant(addr));
+                self.dict.emit(codegen::blr(X16))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit a check that leaves the current definition if the preceding call
+    /// returned a throw code.
+    fn emit_throw_check(&mut self) -> Result<(), Throw> {
+        let exit = self.exit_addr()?;
+        let instr = codegen::cbnz(X0, self.dict.here(), exit).ok_or(Throw::BRANCH_OUT_OF_RANGE)?;
+        self.dict.emit(instr)?;
+        Ok(())
+    }
+
+    /// Compile a call to native code following the word calling convention.
+    pub fn compile_native_call(&mut self, target: usize) -> Result<(), Throw> {
+        self.exit_addr()?;
+        self.dict.emit(codegen::MOV_X0_VM)?;
+        self.emit_call(target)?;
+        self.emit_throw_check()
+    }
+
+    /// Compile a call to a native runtime routine that takes one extra
+    /// argument in `x1`.
+    pub fn compile_native_call_with(&mut self, target: usize, arg: Cell) -> Result<(), Throw> {
+        self.exit_addr()?;
+        self.dict.emit(codegen::MOV_X0_VM)?;
+        let load = codegen::load_constant(X1, arg as u64);
+        let addr = self.dict.emit_all(&load)?;
+        self.dict.record(Reloc::Constant(addr));
+        self.emit_call(target)?;
+        self.emit_throw_check()
+    }
+
+    pub fn compile_call(&mut self, xt: Xt) -> Result<(), Throw> {
+        let code = unsafe { (*xt).code() };
+        self.compile_native_call(code)
+    }
+
+    pub fn compile_literal(&mut self, value: Cell) -> Result<(), Throw> {
+        self.compile_native_call_with(literal_runtime as usize, value)
+    }
+
+    /// Compile a call to a runtime routine that decides whether to branch: it
+    /// returns a negative throw code, zero to fall through, or one to branch
+    /// to `dest`. If `dest` is `None` the branch is left to be resolved later.
+    /// Returns the address of the branch.
+    pub fn compile_branch(&mut self, runtime: usize, dest: Option<usize>) -> Result<usize, Throw> {
+        let exit = self.exit_addr()?;
+        self.dict.emit(codegen::MOV_X0_VM)?;
+        self.emit_call(runtime)?;
+        let instr =
+            codegen::tbnz_negative(X0, self.dict.here(), exit).ok_or(Throw::BRANCH_OUT_OF_RANGE)?;
+        self.dict.emit(instr)?;
+        let here = self.dict.here();
+        let instr =
+            codegen::cbnz(X0, here, dest.unwrap_or(here)).ok_or(Throw::BRANCH_OUT_OF_RANGE)?;
+        self.dict.emit(instr)
+    }
+
+    /// Compile an unconditional branch to `dest`, or one to be resolved later
+    /// if `dest` is `None`. Returns the address of the branch.
+    pub fn compile_jump(&mut self, dest: Option<usize>) -> Result<usize, Throw> {
+        self.exit_addr()?;
+        let here = self.dict.here();
+        let instr = codegen::b(here, dest.unwrap_or(here)).ok_or(Throw::BRANCH_OUT_OF_RANGE)?;
+        self.dict.emit(instr)
+    }
+
+    /// Point the forward branch at `orig` at `dest`.
+    pub fn resolve(&mut self, orig: usize, dest: usize) -> Result<(), Throw> {
+        let exit = self.exit_addr()?;
+        if orig < exit || orig >= self.dict.here() || orig % INSTR_SIZE != 0 {
+            return Err(Throw::CONTROL_MISMATCH);
+        }
+        let instr = unsafe { (orig as *const u32).read() };
+        let instr = codegen::retarget(orig, instr, dest).ok_or(Throw::CONTROL_MISMATCH)?;
+        self.dict.patch(orig, instr);
+        Ok(())
+    }
+
+    /// Check that `dest` is a plausible backward branch target in the current
+    /// definition.
+    pub fn check_dest(&self, dest: usize) -> Result<usize, Throw> {
+        let exit = self.exit_addr()?;
+        if dest < exit || dest > self.dict.here() || dest % INSTR_SIZE != 0 {
+            return Err(Throw::CONTROL_MISMATCH);
+        }
+        Ok(dest)
+    }
+
+    /// Copy `len` bytes at `addr` into the current definition, with a branch
+    /// around them. Returns the address of the copy, which is preceded by its
+    /// length as a cell.
+    pub fn compile_string(&mut self, addr: usize, len: usize) -> Result<usize, Throw> {
+        let orig = self.compile_jump(None)?;
+        self.dict.align()?;
+        self.dict.comma(len as Cell)?;
+        let copy = self.dict.here();
+        self.dict.allot(len as isize)?;
+        unsafe { ptr::copy(addr as *const u8, copy as *mut u8, len) };
+        self.dict.align_to(INSTR_SIZE)?;
+        let here = self.dict.here();
+        self.resolve(orig, here)?;
+        Ok(copy)
+    }
+
+    /// Compile a return from the current definition.
+    pub fn compile_exit(&mut self) -> Result<(), Throw> {
+        let exit = self.exit_addr()?;
+        self.dict.emit(codegen::movz(X0, 0, 0))?;
+        let instr = codegen::b(self.dict.here(), exit).ok_or(Throw::BRANCH_OUT_OF_RANGE)?;
+        self.dict.emit(instr)?;
+        Ok(())
+    }
+
+    /// Start compiling a new colon definition named `name`. It stays hidden
+    /// until `end_colon`.
+    pub fn begin_colon(&mut self, name: &str) -> Result<Xt, Throw> {
+        if self.user.colon.is_some() {
+            return Err(Throw::NESTED_COMPILATION);
+        }
+        let xt = self.dict.create_header(name, Flags::HIDDEN, 0)?;
+        let (exit, entry) = self.begin_code()?;
+        self.dict.set_code(xt, entry);
+        self.user.colon = Some(Colon {
+            xt,
+            exit,
+            depth: self.data.depth(),
+            leaves: Vec::new(),
+        });
+        self.user.state = TRUE;
+        Ok(xt)
+    }
+
+    pub fn end_colon(&mut self) -> Result<(), Throw> {
+        let depth = match self.user.colon.as_ref() {
+            Some(colon) => colon.depth,
+            None => return Err(Throw::COMPILE_ONLY),
+        };
+        if self.data.depth() != depth {
+            return Err(Throw::CONTROL_MISMATCH);
+        }
+        self.compile_exit()?;
+        let colon = self.user.colon.take().unwrap();
+        codegen::sync_icache(colon.xt as usize, self.dict.here());
+        self.dict.set_flags(Flags::HIDDEN, false);
+        self.user.state = FALSE;
+        Ok(())
+    }
+
+    /// Compile a call to `runtime` that passes it the address of a new piece
+    /// of code, followed by a return. The rest of the definition is compiled
+    /// into the new code. This is how `DOES>` and `ACTIVATE` hand the end of
+    /// a definition to something else to run.
+    pub fn begin_detached_code(&mut self, runtime: usize) -> Result<(), Throw> {
+        // The address of the new code isn't known until after the call to
+        // `runtime` is laid down, so load a placeholder and patch it in
+        // below.
+        self.dict.align_to(INSTR_SIZE)?;
+        let load = self.dict.here() + INSTR_SIZE;
+        self.compile_native_call_with(runtime, 0)?;
+        self.compile_exit()?;
+        let (exit, entry) = self.begin_code()?;
+        self.colon_mut()?.exit = exit;
+        let instrs = codegen::load_constant(X1, entry as u64);
+        self.dict.patch_all(load, &instrs);
+        Ok(())
+    }
+
+    /// Compile the run-time part of `DOES>`, which ends the defining word,
+    /// and start the code that the defined words will run.
+    pub fn begin_does(&mut self) -> Result<(), Throw> {
+        self.begin_detached_code(does_runtime as usize)?;
+        // The stub leaves the data field address in `x1`, which the prologue
+        // doesn't touch; push it.
+        self.dict.emit(codegen::MOV_X0_VM)?;
+        self.emit_call(literal_runtime as usize)?;
+        self.emit_throw_check()
+    }
+
+    /// Lay down a code stub that calls `runtime` with `arg`, for words like
+    /// constants whose behaviour is fixed when they are defined.
+    fn emit_stub(&mut self, arg: usize, runtime: usize) -> Result<usize, Throw> {
+        self.dict.align()?;
+        let stub = codegen::stub(arg as u64, runtime as u64);
+        let code = self.dict.emit_all(&stub)?;
+        self.dict.record(Reloc::Constant(code));
+        self.dict
+            .record(Reloc::Constant(code + codegen::STUB_TARGET * INSTR_SIZE));
+        codegen::sync_icache(code, self.dict.here());
+        Ok(code)
+    }
+
+    /// Define a word that pushes the address of its data field, which starts
+    /// at the current `HERE`.
+    pub fn create(&mut self, name: &str) -> Result<Xt, Throw> {
+        let xt = self.dict.create_header(name, Flags::empty(), 0)?;
+        let param = self.dict.here() + codegen::STUB_LEN * INSTR_SIZE;
+        let code = self.emit_stub(param, create_runtime as usize)?;
+        debug_assert_eq!(param, self.dict.here());
+        self.dict.set_code(xt, code);
+        self.dict.set_param(xt, param);
+        Ok(xt)
+    }
+
+    /// Define a word that pushes `value`.
+    pub fn constant(&mut self, name: &str, value: Cell) -> Result<Xt, Throw> {
+        self.constant_with(name, value as usize, literal_runtime as usize)
+    }
+
+    /// Define a word whose code calls `runtime` with `arg`, as `constant`
+    /// does with the runtime that pushes a cell.
+    pub fn constant_with(&mut self, name: &str, arg: usize, runtime: usize) -> Result<Xt, Throw> {
+        let xt = self.dict.create_header(name, Flags::empty(), 0)?;
+        let code = self.emit_stub(arg, runtime)?;
+        self.dict.set_code(xt, code);
+        Ok(xt)
+    }
+}
@@ -0,0 +1,261 @@
+//! Control structures. These are immediate words that lay down branches in the
+//! definition being compiled, keeping unresolved branch addresses (`orig`)
+//! and backward branch targets (`dest`) on the data stack, which doubles as
+//! the control-flow stack.
+//!
+//! Conditional branches call a runtime routine that returns one to take the
+//! branch and zero to fall through (see `Vm::compile_branch`). `DO` loops keep
+//! their limit and index on the return stack, index on top.
+
+use super::{
+    dictionary::{Entry, Flags},
+    words::{COMPILER, COMPILE_ONLY},
+    Cell, Throw, Vm,
+};
+
+/// Take the branch if the flag on the stack is false.
+extern "C" fn zero_branch_runtime(vm: &mut Vm) -> isize {
+    match vm.pop() {
+        Ok(0) => 1,
+        Ok(_) => 0,
+        Err(throw) => throw.code(),
+    }
+}
+
+extern "C" fn do_runtime(vm: &mut Vm) -> isize {
+    let result = (|| {
+        let index = vm.pop()?;
+        let limit = vm.pop()?;
+        vm.ret.push(limit)?;
+        vm.ret.push(index)
+    })();
+    Throw::into_code(result)
+}
+
+/// Skip the loop entirely if the limit and index are equal.
+extern "C" fn question_do_runtime(vm: &mut Vm) -> isize {
+    let result = (|| {
+        let index = vm.pop()?;
+        let limit = vm.pop()?;
+        if index == limit {
+            return Ok(1);
+        }
+        vm.ret.push(limit)?;
+        vm.ret.push(index)?;
+        Ok(0)
+    })();
+    result.unwrap_or_else(Throw::code)
+}
+
+/// Add `step` to the loop index. Returns whether to go around again, and
+/// discards the loop parameters if not.
+fn step_loop(vm: &mut Vm, step: Cell) -> Result<bool, Throw> {
+    let limit = vm.ret.peek(1)?;
+    let index = vm.ret.peek_mut(0)?;
+    // The loop ends when the index crosses the boundary between `limit - 1`
+    // and `limit`, in either direction.
+    let before = index.wrapping_sub(limit);
+    let after = before.wrapping_add(step);
+    *index = index.wrapping_add(step);
+    let crossed = (before < 0) != (after < 0) && (after < 0) == (step < 0);
+    if crossed {
+        vm.ret.pop()?;
+        vm.ret.pop()?;
+    }
+    Ok(!crossed)
+}
+
+extern "C" fn loop_runtime(vm: &mut Vm) -> isize {
+    match step_loop(vm, 1) {
+        Ok(again) => again as isize,
+        Err(throw) => throw.code(),
+    }
+}
+
+extern "C" fn plus_loop_runtime(vm: &mut Vm) -> isize {
+    let result = vm.pop().and_then(|step| step_loop(vm, step));
+    match result {
+        Ok(again) => again as isize,
+        Err(throw) => throw.code(),
+    }
+}
+
+/// The name `SEE` shows for a call to one of the runtime routines above.
+pub(super) fn runtime_name(target: usize) -> Option<&'static str> {
+    let runtimes = [
+        (zero_branch_runtime as usize, "0BRANCH"),
+        (do_runtime as usize, "(DO)"),
+        (question_do_runtime as usize, "(?DO)"),
+        (loop_runtime as usize, "(LOOP)"),
+        (plus_loop_runtime as usize, "(+LOOP)"),
+    ];
+    runtimes
+        .iter()
+        .find(|(runtime, _)| *runtime == target)
+        .map(|(_, name)| *name)
+}
+
+impl Vm {
+    fn pop_addr(&mut self) -> Result<usize, Throw> {
+        self.pop().map(|addr| addr as usize)
+    }
+
+    /// Resolve the `orig` on top of the stack to `HERE`.
+    fn then(&mut self) -> Result<(), Throw> {
+        let orig = self.pop_addr()?;
+        let here = self.dict.here();
+        self.resolve(orig, here)
+    }
+
+    /// Move the top item below the one under it, as `1 CS-ROLL` does.
+    fn cs_swap(&mut self) -> Result<(), Throw> {
+        let top = self.pop()?;
+        let under = self.pop()?;
+        self.push(top)?;
+        self.push(under)
+    }
+
+    /// Compile the end of a `DO` loop, with `runtime` deciding whether to go
+    /// around again, and resolve the branches that leave it.
+    fn end_loop(&mut self, runtime: usize) -> Result<(), Throw> {
+        let dest = self.pop_addr()?;
+        let dest = self.check_dest(dest)?;
+        let leaves = self.pop_addr()?;
+        self.compile_branch(runtime, Some(dest))?;
+        let here = self.dict.here();
+        let colon = self.colon_mut()?;
+        if leaves > colon.leaves.len() {
+            return Err(Throw::CONTROL_MISMATCH);
+        }
+        let origs = colon.leaves.split_off(leaves);
+        for orig in origs {
+            self.resolve(orig, here)?;
+        }
+        Ok(())
+    }
+}
+
+primitives! {
+    fn if_(vm) {
+        let orig = vm.compile_branch(zero_branch_runtime as usize, None)?;
+        vm.push(orig as Cell)
+    }
+
+    fn else_(vm) {
+        let orig = vm.compile_jump(None)?;
+        vm.push(orig as Cell)?;
+        vm.cs_swap()?;
+        vm.then()
+    }
+
+    fn then(vm) {
+        vm.then()
+    }
+
+    fn ahead(vm) {
+        let orig = vm.compile_jump(None)?;
+        vm.push(orig as Cell)
+    }
+
+    fn begin(vm) {
+        vm.colon_mut()?;
+        let dest = vm.dict.here();
+        vm.push(dest as Cell)
+    }
+
+    fn until(vm) {
+        let dest = vm.pop_addr()?;
+        let dest = vm.check_dest(dest)?;
+        vm.compile_branch(zero_branch_runtime as usize, Some(dest))?;
+        Ok(())
+    }
+
+    fn again(vm) {
+        let dest = vm.pop_addr()?;
+        let dest = vm.check_dest(dest)?;
+        vm.compile_jump(Some(dest))?;
+        Ok(())
+    }
+
+    fn while_(vm) {
+        let orig = vm.compile_branch(zero_branch_runtime as usize, None)?;
+        vm.push(orig as Cell)?;
+        vm.cs_swap()
+    }
+
+    fn repeat(vm) {
+        let dest = vm.pop_addr()?;
+        let dest = vm.check_dest(dest)?;
+        vm.compile_jump(Some(dest))?;
+        vm.then()
+    }
+
+    fn do_(vm) {
+        let leaves = vm.colon_mut()?.leaves.len();
+        vm.compile_native_call(do_runtime as usize)?;
+        let dest = vm.dict.here();
+        vm.push(leaves as Cell)?;
+        vm.push(dest as Cell)
+    }
+
+    fn question_do(vm) {
+        let leaves = vm.colon_mut()?.leaves.len();
+        let orig = vm.compile_branch(question_do_runtime as usize, None)?;
+        vm.colon_mut()?.leaves.push(orig);
+        let dest = vm.dict.here();
+        vm.push(leaves as Cell)?;
+        vm.push(dest as Cell)
+    }
+
+    fn loop_(vm) {
+        vm.end_loop(loop_runtime as usize)
+    }
+
+    fn plus_loop(vm) {
+        vm.end_loop(plus_loop_runtime as usize)
+    }
+
+    fn leave(vm) {
+        vm.compile_native_call(unloop as usize)?;
+        let orig = vm.compile_jump(None)?;
+        vm.colon_mut()?.leaves.push(orig);
+        Ok(())
+    }
+
+    fn unloop(vm) {
+        vm.ret.require(2).map_err(|_| Throw::LOOP_PARAMETERS_UNAVAILABLE)?;
+        vm.ret.pop()?;
+        vm.ret.pop()?;
+        Ok(())
+    }
+
+    fn i(vm) {
+        let index = vm.ret.peek(0).map_err(|_| Throw::LOOP_PARAMETERS_UNAVAILABLE)?;
+        vm.push(index)
+    }
+
+    fn j(vm) {
+        let index = vm.ret.peek(2).map_err(|_| Throw::LOOP_PARAMETERS_UNAVAILABLE)?;
+        vm.push(index)
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    ("IF", COMPILER, if_),
+    ("ELSE", COMPILER, else_),
+    ("THEN", COMPILER, then),
+    ("AHEAD", COMPILER, ahead),
+    ("BEGIN", COMPILER, begin),
+    ("UNTIL", COMPILER, until),
+    ("AGAIN", COMPILER, again),
+    ("WHILE", COMPILER, while_),
+    ("REPEAT", COMPILER, repeat),
+    ("DO", COMPILER, do_),
+    ("?DO", COMPILER, question_do),
+    ("LOOP", COMPILER, loop_),
+    ("+LOOP", COMPILER, plus_loop),
+    ("LEAVE", COMPILER, leave),
+    ("UNLOOP", COMPILE_ONLY, unloop),
+    ("I", COMPILE_ONLY, i),
+    ("J", COMPILE_ONLY, j),
+];
@@ -0,0 +1,371 @@
+use alloc::{
+    alloc::{alloc_zeroed, Layout},
+    vec::Vec,
+};
+use bitflags::bitflags;
+use core::{mem, ptr, slice};
+
+use super::{codegen, Cell, Throw};
+
+/// The size of the data space, which holds word headers, compiled code, and
+/// everything allotted by Forth programs.
+pub const DATA_SPACE_SIZE: usize = 1 << 20;
+
+/// The longest name a word can have. The standard requires at least 31.
+pub const NAME_MAX: usize = 31;
+
+bitflags! {
+    pub struct Flags: u8 {
+        const IMMEDIATE = 1 << 0;
+        /// Set on a definition while it is being compiled, so that it can't
+        /// be found until `;` completes it.
+        const HIDDEN = 1 << 1;
+        const COMPILE_ONLY = 1 << 2;
+        /// Set on the words of the `ASSEMBLER` vocabulary, which can only
+        /// be found while it is being searched.
+        const ASSEMBLER = 1 << 3;
+    }
+}
+
+/// A word's entry point, as stored in its code field.
+pub type Entry = extern "C" fn(&mut super::Vm) -> isize;
+
+/// The header of a word in the dictionary. Headers live in the data space and
+/// are linked together from the most recent definition backwards.
+#[repr(C)]
+pub struct Header {
+    link: *const Header,
+    /// The address of the word's native code.
+    code: usize,
+    /// The data field address for words made by `CREATE`, or zero.
+    param: usize,
+    flags: Flags,
+    name_len: u8,
+    name: [u8; NAME_MAX],
+}
+
+/// An execution token is the address of the word's header.
+pub type Xt = *const Header;
+
+impl Header {
+    #[inline]
+    pub fn name(&self) -> &str {
+        let bytes = &self.name[..self.name_len as usize];
+        // Names are only ever copied whole from a `&str`, in
+        // `create_header`, or from an image of headers made that way.
+        unsafe { core::str::from_utf8_unchecked(bytes) }
+    }
+
+    #[inline]
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    #[inline]
+    pub fn is_immediate(&self) -> bool {
+        self.flags.contains(Flags::IMMEDIATE)
+    }
+
+    #[inline]
+    pub fn code(&self) -> usize {
+        self.code
+    }
+
+    #[inline]
+    pub fn entry(&self) -> Entry {
+        unsafe { mem::transmute(self.code) }
+    }
+
+    #[inline]
+    pub fn param(&self) -> usize {
+        self.param
+    }
+
+    #[inline]
+    pub fn link(&self) -> Option<&Header> {
+        unsafe { self.link.as_ref() }
+    }
+}
+
+/// A place in the data space that refers to code or data by address. These
+/// are recorded as code is compiled, so that a saved image of the data space
+/// can be loaded at a different address (see `image`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reloc {
+    /// A `bl` instruction.
+    Call(usize),
+    /// A `codegen::load_constant` sequence.
+    Constant(usize),
+}
+
+impl Reloc {
+    #[inline]
+    pub fn addr(self) -> usize {
+        match self {
+            Reloc::Call(addr) | Reloc::Constant(addr) => addr,
+        }
+    }
+}
+
+pub struct Dictionary {
+    start: usize,
+    end: usize,
+    here: usize,
+    latest: *mut Header,
+    relocs: Vec<Reloc>,
+    /// Whether the `ASSEMBLER` vocabulary is searched.
+    assembler: bool,
+}
+
+impl Dictionary {
+    pub fn new() -> Dictionary {
+        let layout = Layout::from_size_align(DATA_SPACE_SIZE, 4096)
+            .expect("Failed to determine layout for the Forth data space");
+        let start = unsafe { alloc_zeroed(layout) } as usize;
+        assert!(start != 0, "Failed to allocate the Forth data space");
+        Dictionary {
+            start,
+            end: start + DATA_SPACE_SIZE,
+            here: start,
+            latest: ptr::null_mut(),
+            relocs: Vec::new(),
+            assembler: false,
+        }
+    }
+
+    #[inline]
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    #[inline]
+    pub fn here(&self) -> usize {
+        self.here
+    }
+
+    #[inline]
+    pub fn unused(&self) -> usize {
+        self.end - self.here
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    /// Reserve `bytes` of data space, or release it if `bytes` is negative.
+    pub fn allot(&mut self, bytes: isize) -> Result<(), Throw> {
+        let new_here = (self.here as isize).wrapping_add(bytes) as usize;
+        if new_here > self.end || new_here < self.start {
+            return Err(Throw::DICTIONARY_OVERFLOW);
+        }
+        self.here = new_here;
+        Ok(())
+    }
+
+    /// Align `HERE` to a multiple of `align`, which must be a power of two.
+    pub fn align_to(&mut self, align: usize) -> Result<(), Throw> {
+        let aligned = (self.here + align - 1) & !(align - 1);
+        self.allot((aligned - self.here) as isize)
+    }
+
+    #[inline]
+    pub fn align(&mut self) -> Result<(), Throw> {
+        self.align_to(mem::size_of::<Cell>())
+    }
+
+    pub fn comma(&mut self, value: Cell) -> Result<(), Throw> {
+        let addr = self.here;
+        self.allot(mem::size_of::<Cell>() as isize)?;
+        unsafe { (addr as *mut Cell).write_unaligned(value) };
+        Ok(())
+    }
+
+    pub fn c_comma(&mut self, value: u8) -> Result<(), Throw> {
+        let addr = self.here;
+        self.allot(1)?;
+        unsafe { (addr as *mut u8).write(value) };
+        Ok(())
+    }
+
+    /// Append one instruction to the data space. `HERE` must already be
+    /// instruction aligned.
+    pub fn emit(&mut self, instr: u32) -> Result<usize, Throw> {
+        debug_assert_eq!(self.here % codegen::INSTR_SIZE, 0);
+        let addr = self.here;
+        self.allot(codegen::INSTR_SIZE as isize)?;
+        unsafe { (addr as *mut u32).write(instr) };
+        Ok(addr)
+    }
+
+    pub fn emit_all(&mut self, instrs: &[u32]) -> Result<usize, Throw> {
+        let addr = self.here;
+        for instr in instrs {
+            self.emit(*instr)?;
+        }
+        Ok(addr)
+    }
+
+    /// Overwrite a previously emitted instruction.
+    #[inline]
+    pub fn patch(&mut self, addr: usize, instr: u32) {
+        self.patch_all(addr, &[instr]);
+    }
+
+    /// Overwrite a run of previously emitted instructions.
+    pub fn patch_all(&mut self, addr: usize, instrs: &[u32]) {
+        let end = addr + instrs.len() * codegen::INSTR_SIZE;
+        debug_assert!(self.contains(addr) && end <= self.here);
+        for (index, instr) in instrs.iter().enumerate() {
+            unsafe { (addr as *mut u32).add(index).write(*instr) };
+        }
+        codegen::sync_icache(addr, end);
+    }
+
+    /// Note that the code at `reloc` refers to an address.
+    #[inline]
+    pub fn record(&mut self, reloc: Reloc) {
+        self.relocs.push(reloc);
+    }
+
+    #[inline]
+    pub fn relocs(&self) -> &[Reloc] {
+        &self.relocs
+    }
+
+    /// Lay down a new header with the given code field and make it the latest
+    /// definition. Returns the new word's execution token.
+    pub fn create_header(&mut self, name: &str, flags: Flags, code: usize) -> Result<Xt, Throw> {
+        if name.is_empty() {
+            return Err(Throw::ZERO_LENGTH_NAME);
+        }
+        if name.len() > NAME_MAX {
+            return Err(Throw::NAME_TOO_LONG);
+        }
+        self.align()?;
+        let addr = self.here;
+        self.allot(mem::size_of::<Header>() as isize)?;
+        let mut name_buf = [0; NAME_MAX];
+        name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        let header = addr as *mut Header;
+        unsafe {
+            header.write(Header {
+                link: self.latest,
+                code,
+                param: 0,
+                flags,
+                name_len: name.len() as u8,
+                name: name_buf,
+            });
+        }
+        self.latest = header;
+        Ok(header)
+    }
+
+    #[inline]
+    pub fn latest(&self) -> Option<&Header> {
+        unsafe { self.latest.as_ref() }
+    }
+
+    #[inline]
+    pub fn latest_mut(&mut self) -> Option<&mut Header> {
+        unsafe { self.latest.as_mut() }
+    }
+
+    pub fn set_flags(&mut self, flags: Flags, on: bool) {
+        if let Some(header) = self.latest_mut() {
+            header.flags.set(flags, on);
+        }
+    }
+
+    pub fn set_code(&mut self, xt: Xt, code: usize) {
+        unsafe { (*(xt as *mut Header)).code = code };
+    }
+
+    pub fn set_param(&mut self, xt: Xt, param: usize) {
+        unsafe { (*(xt as *mut Header)).param = param };
+    }
+
+    /// Remove `xt` and every word defined after it, releasing their data
+    /// space.
+    pub fn forget(&mut self, xt: Xt) {
+        debug_assert!(self.contains(xt as usize));
+        self.here = xt as usize;
+        self.latest = unsafe { (*xt).link as *mut Header };
+        let here = self.here;
+        self.relocs.retain(|reloc| reloc.addr() < here);
+    }
+
+    /// Replace the whole data space with `data`, which was saved from a data
+    /// space at `old_start`, and fix up the headers in it. `latest` is the
+    /// saved latest definition and `relocs` the saved relocations, both
+    /// already moved to this data space; the code that `relocs` refers to is
+    /// left for the caller to adjust.
+    pub fn restore(&mut self, data: &[u8], old_start: usize, latest: usize, relocs: Vec<Reloc>) {
+        debug_assert!(data.len() <= self.end - self.start);
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.start as *mut u8, data.len());
+            ptr::write_bytes(
+                (self.start + data.len()) as *mut u8,
+                0,
+                self.here.saturating_sub(self.start + data.len()),
+            );
+        }
+        self.here = self.start + data.len();
+        self.latest = latest as *mut Header;
+        self.relocs = relocs;
+        let start = self.start;
+        let old_end = old_start + (self.end - start);
+        let relocate = |addr: usize| {
+            if addr >= old_start && addr < old_end {
+                addr - old_start + start
+            } else {
+                addr
+            }
+        };
+        // Hidden words are still on the chain, so this visits every header.
+        let mut next = self.latest;
+        while let Some(header) = unsafe { next.as_mut() } {
+            header.link = relocate(header.link as usize) as *const Header;
+            header.code = relocate(header.code);
+            if header.param != 0 {
+                header.param = relocate(header.param);
+            }
+            next = header.link as *mut Header;
+        }
+    }
+
+    /// Iterate over the visible words, most recent first.
+    pub fn iter(&self) -> impl Iterator<Item = &Header> {
+        let mut next = self.latest();
+        let hidden = if self.assembler {
+            Flags::HIDDEN
+        } else {
+            Flags::HIDDEN | Flags::ASSEMBLER
+        };
+        core::iter::from_fn(move || {
+            let header = next?;
+            next = header.link();
+            Some(header)
+        })
+        .filter(move |header| !header.flags.intersects(hidden))
+    }
+
+    /// Add the `ASSEMBLER` vocabulary to the search order, or take it out.
+    #[inline]
+    pub fn search_assembler(&mut self, search: bool) {
+        self.assembler = search;
+    }
+
+    /// Look up a word by name, ignoring case.
+    pub fn find(&self, name: &str) -> Option<&Header> {
+        self.iter()
+            .find(|header| header.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Bytes in the data space between `addr` and `HERE`.
+    pub fn slice_from(&self, addr: usize) -> &[u8] {
+        debug_assert!(self.contains(addr) || addr == self.end);
+        unsafe { slice::from_raw_parts(addr as *const u8, self.here - addr) }
+    }
+}
@@ -0,0 +1,426 @@
+//! The line discipline for the terminal: reading keys from the serial port
+//! and letting the line be edited before the interpreter sees it.
+//!
+//! The editor understands the keys a VT100 style terminal sends:
+//!
+//! * Backspace or Delete erase the character before the cursor.
+//! * Left and Right move the cursor, and Home/End or Ctrl-A/Ctrl-E move it
+//!   to the start or end of the line.
+//! * Ctrl-U erases everything before the cursor, and Ctrl-W the word before
+//!   it.
+//! * Up and Down step through the lines entered before.
+//! * Tab completes the word before the cursor from the dictionary. If it
+//!   could be several words and there's nothing more in common, they are
+//!   listed.
+//!
+//! Changes are echoed to both the serial port and the framebuffer console
+//! using only printable characters and backspaces, which both of them treat
+//! as moving the cursor left.
+
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::dictionary::Dictionary;
+use crate::rpi::{console::Console, uart::UART};
+
+/// The number of lines kept in the history.
+const HISTORY_LENGTH: usize = 32;
+
+const CTRL_A: u8 = 0x01;
+const CTRL_E: u8 = 0x05;
+const CTRL_U: u8 = 0x15;
+const CTRL_W: u8 = 0x17;
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = 0x09;
+const ESCAPE: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Key {
+    Char(u8),
+    Enter,
+    Backspace,
+    /// Erase the character under the cursor.
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Tab,
+    KillLine,
+    KillWord,
+}
+
+/// Wait for a byte from the serial port, calling `idle` until one arrives.
+fn read_byte(uart: &mut UART, idle: &mut dyn FnMut()) -> u8 {
+    loop {
+        match uart.try_read_byte() {
+            Some(byte) => return byte,
+            None => idle(),
+        }
+    }
+}
+
+/// Read a key, decoding the escape sequences for the cursor keys. Returns
+/// `None` for anything the editor doesn't use.
+fn read_key(uart: &mut UART, idle: &mut dyn FnMut()) -> Option<Key> {
+    let key = match read_byte(uart, idle) {
+        b'\r' | b'\n' => Key::Enter,
+        BACKSPACE | DELETE => Key::Backspace,
+        TAB => Key::Tab,
+        CTRL_A => Key::Home,
+        CTRL_E => Key::End,
+        CTRL_U => Key::KillLine,
+        CTRL_W => Key::KillWord,
+        ESCAPE => return read_escape(uart, idle),
+        byte @ b' '..=b'~' => Key::Char(byte),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Decode the rest of an `ESC [` or `ESC O` sequence.
+fn read_escape(uart: &mut UART, idle: &mut dyn FnMut()) -> Option<Key> {
+    match read_byte(uart, idle) {
+        b'[' | b'O' => (),
+        _ => return None,
+    }
+    let mut param = 0;
+    loop {
+        let key = match read_byte(uart, idle) {
+            digit @ b'0'..=b'9' => {
+                param = param * 10 + (digit - b'0') as u32;
+                continue;
+            }
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            b'~' => match param {
+                1 | 7 => Key::Home,
+                3 => Key::Delete,
+                4 | 8 => Key::End,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        return Some(key);
+    }
+}
+
+/// Writes to the serial port, and to the framebuffer console if there is
+/// one.
+struct Echo {
+    uart: UART,
+    console: Option<Console>,
+}
+
+impl Echo {
+    fn new() -> Echo {
+        Echo {
+            uart: UART::new(),
+            console: Console::new(),
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.uart.write_byte(byte);
+        if let Some(console) = &mut self.console {
+            let _ = console.write_char(byte as char);
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.byte(*byte);
+        }
+    }
+
+    fn repeat(&mut self, byte: u8, count: usize) {
+        for _ in 0..count {
+            self.byte(byte);
+        }
+    }
+}
+
+/// The lines entered most recently, oldest first once it wraps around.
+struct History {
+    lines: Vec<Vec<u8>>,
+    /// Where the next line goes once `lines` is full.
+    next: usize,
+}
+
+impl History {
+    const fn new() -> History {
+        History {
+            lines: Vec::new(),
+            next: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The line entered `age` lines ago, counting from one.
+    fn get(&self, age: usize) -> &[u8] {
+        debug_assert!(age >= 1 && age <= self.len());
+        let index = (self.next + self.len() - age) % self.len();
+        &self.lines[index]
+    }
+
+    fn push(&mut self, line: &[u8]) {
+        if line.is_empty() || (self.len() > 0 && self.get(1) == line) {
+            return;
+        }
+        if self.lines.len() < HISTORY_LENGTH {
+            self.lines.push(line.to_vec());
+            self.next = self.lines.len() % HISTORY_LENGTH;
+        } else {
+            self.lines[self.next] = line.to_vec();
+            self.next = (self.next + 1) % HISTORY_LENGTH;
+        }
+    }
+}
+
+/// A line being edited, and how much of it has been echoed.
+struct Line {
+    bytes: Vec<u8>,
+    /// The longest the line may be.
+    max: usize,
+    cursor: usize,
+    /// Where the terminal's cursor is.
+    shown: usize,
+    echo: Echo,
+}
+
+impl Line {
+    /// Move the terminal's cursor to `pos`.
+    fn move_to(&mut self, pos: usize) {
+        if pos < self.shown {
+            self.echo.repeat(BACKSPACE, self.shown - pos);
+        } else {
+            self.echo.bytes(&self.bytes[self.shown..pos]);
+        }
+        self.shown = pos;
+    }
+
+    /// Echo the line from `from` onwards after it has changed, blanking out
+    /// the rest of the old line if it was `old_len` long, and put the cursor
+    /// back where it belongs.
+    fn repaint(&mut self, from: usize, old_len: usize) {
+        let from = from.min(self.shown);
+        self.move_to(from);
+        self.echo.bytes(&self.bytes[from..]);
+        let blanks = old_len.saturating_sub(self.bytes.len());
+        self.echo.repeat(b' ', blanks);
+        self.shown = self.bytes.len() + blanks;
+        self.move_to(self.cursor);
+    }
+
+    fn insert(&mut self, bytes: &[u8]) {
+        let room = self.max - self.bytes.len();
+        let bytes = &bytes[..bytes.len().min(room)];
+        let at = self.cursor;
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.bytes.insert(at + offset, *byte);
+        }
+        self.cursor += bytes.len();
+        self.repaint(at, self.bytes.len());
+    }
+
+    /// Erase the characters between `start` and `end`.
+    fn erase(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        let old_len = self.bytes.len();
+        self.bytes.drain(start..end);
+        self.cursor = start;
+        self.repaint(start, old_len);
+    }
+
+    /// Replace the whole line, as when stepping through the history.
+    fn replace(&mut self, bytes: &[u8]) {
+        let old_len = self.bytes.len();
+        self.bytes.clear();
+        self.bytes
+            .extend_from_slice(&bytes[..bytes.len().min(self.max)]);
+        self.cursor = self.bytes.len();
+        self.repaint(0, old_len);
+    }
+
+    /// Where the word before the cursor starts.
+    fn word_start(&self) -> usize {
+        let before = &self.bytes[..self.cursor];
+        let end = before.iter().rposition(|b| *b != b' ').map_or(0, |i| i + 1);
+        before[..end]
+            .iter()
+            .rposition(|b| *b == b' ')
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Complete the name before the cursor from the dictionary.
+    fn complete(&mut self, dict: &Dictionary) {
+        let start = self.word_start();
+        if start == self.cursor {
+            return;
+        }
+        let prefix = &self.bytes[start..self.cursor];
+        let prefix_len = prefix.len();
+        let mut matches: Vec<&[u8]> = Vec::new();
+        for header in dict.iter() {
+            let name = header.name().as_bytes();
+            if name.len() >= prefix.len()
+                && name[..prefix.len()].eq_ignore_ascii_case(prefix)
+                && !matches.iter().any(|m| m.eq_ignore_ascii_case(name))
+            {
+                matches.push(name);
+            }
+        }
+        let first = match matches.first() {
+            Some(first) => *first,
+            None => return,
+        };
+        let common = matches.iter().fold(first.len(), |len, name| {
+            first[..len]
+                .iter()
+                .zip(name.iter())
+                .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+                .count()
+        });
+        if matches.len() == 1 {
+            self.complete_with(start, first);
+            self.insert(b" ");
+        } else if common > prefix_len {
+            self.complete_with(start, &first[..common]);
+        } else {
+            self.list(&matches);
+        }
+    }
+
+    /// Replace the word from `start` to the cursor with `name`, so that it's
+    /// spelled as it was defined.
+    fn complete_with(&mut self, start: usize, name: &[u8]) {
+        if self.bytes.len() - (self.cursor - start) + name.len() > self.max {
+            return;
+        }
+        let old_len = self.bytes.len();
+        self.bytes.splice(start..self.cursor, name.iter().cloned());
+        self.cursor = start + name.len();
+        self.repaint(start, old_len);
+    }
+
+    /// List the possible completions under the line, then show the line
+    /// again.
+    fn list(&mut self, names: &[&[u8]]) {
+        self.move_to(self.bytes.len());
+        self.echo.byte(b'\n');
+        for name in names.iter().rev() {
+            self.echo.bytes(name);
+            self.echo.byte(b' ');
+        }
+        self.echo.byte(b'\n');
+        self.shown = 0;
+        self.move_to(self.bytes.len());
+        self.move_to(self.cursor);
+    }
+}
+
+/// The state kept between lines: the history of lines entered.
+pub struct Editor {
+    history: History,
+}
+
+impl Editor {
+    pub const fn new() -> Editor {
+        Editor {
+            history: History::new(),
+        }
+    }
+
+    /// Read and edit a line from the serial port into `buffer`, using `dict`
+    /// to complete names and calling `idle` while waiting for keys. Returns
+    /// the length of the line.
+    ///
+    /// `idle` lets the other tasks run, which can change the dictionary, so
+    /// `dict` is only borrowed when a name is completed.
+    pub fn read_line(
+        &mut self,
+        buffer: &mut [u8],
+        dict: *const Dictionary,
+        idle: &mut dyn FnMut(),
+    ) -> usize {
+        let mut uart = UART::new();
+        let mut line = Line {
+            bytes: Vec::with_capacity(buffer.len()),
+            max: buffer.len(),
+            cursor: 0,
+            shown: 0,
+            echo: Echo::new(),
+        };
+        // How many lines back in the history the line came from, and what
+        // was being typed before stepping back into the history.
+        let mut age = 0;
+        let mut draft = Vec::new();
+        loop {
+            let key = match read_key(&mut uart, idle) {
+                Some(key) => key,
+                None => continue,
+            };
+            match key {
+                Key::Char(byte) => line.insert(&[byte]),
+                Key::Enter => break,
+                Key::Backspace if line.cursor > 0 => line.erase(line.cursor - 1, line.cursor),
+                Key::Delete if line.cursor < line.bytes.len() => {
+                    line.erase(line.cursor, line.cursor + 1)
+                }
+                Key::Left if line.cursor > 0 => {
+                    line.cursor -= 1;
+                    line.move_to(line.cursor);
+                }
+                Key::Right if line.cursor < line.bytes.len() => {
+                    line.cursor += 1;
+                    line.move_to(line.cursor);
+                }
+                Key::Home => {
+                    line.cursor = 0;
+                    line.move_to(0);
+                }
+                Key::End => {
+                    line.cursor = line.bytes.len();
+                    line.move_to(line.cursor);
+                }
+                Key::KillLine => line.erase(0, line.cursor),
+                Key::KillWord => line.erase(line.word_start(), line.cursor),
+                Key::Up if age < self.history.len() => {
+                    if age == 0 {
+                        draft = line.bytes.clone();
+                    }
+                    age += 1;
+                    line.replace(self.history.get(age));
+                }
+                Key::Down if age > 0 => {
+                    age -= 1;
+                    if age == 0 {
+                        line.replace(&draft);
+                    } else {
+                        line.replace(self.history.get(age));
+                    }
+                }
+                Key::Tab => line.complete(unsafe { &*dict }),
+                _ => (),
+            }
+        }
+        let len = line.bytes.len();
+        line.move_to(len);
+        line.echo.byte(b' ');
+        self.history.push(&line.bytes);
+        buffer[..len].copy_from_slice(&line.bytes);
+        len
+    }
+}
@@ -0,0 +1,85 @@
+//! Including Forth source from files on the SD card's boot partition, which
+//! is read with the FAT driver in `crate::fat`. A file is read whole, then
+//! interpreted a line at a time, and files can include other files. An
+//! exception raised while including a file is reported with the file name
+//! and line number.
+//!
+//! If the boot partition has an `autoexec.fs`, it is included at boot before
+//! the console starts.
+
+use alloc::{string::String, vec::Vec};
+use core::{slice, str};
+
+use super::{
+    dictionary::{Entry, Flags},
+    words::NONE,
+    Throw, Vm,
+};
+use crate::fat::{self, Volume};
+
+/// The file included at boot, if it exists.
+const AUTOEXEC: &str = "autoexec.fs";
+
+fn read_file(path: &str) -> Result<Vec<u8>, fat::Error> {
+    Volume::open()?.read_file(path)
+}
+
+impl From<fat::Error> for Throw {
+    fn from(error: fat::Error) -> Throw {
+        match error {
+            fat::Error::NotFound | fat::Error::WrongKind => Throw::NON_EXISTENT_FILE,
+            _ => Throw::FILE_IO,
+        }
+    }
+}
+
+impl Vm {
+    /// Interpret the file at `path` on the boot partition.
+    pub fn included(&mut self, path: &str) -> Result<(), Throw> {
+        let text = read_file(path)?;
+        self.interpret_file(path, &text)
+    }
+
+    /// Include `autoexec.fs` if there is one, reporting anything it throws.
+    /// Not having an SD card or a boot partition isn't an error.
+    pub fn autoexec(&mut self) {
+        let text = match read_file(AUTOEXEC) {
+            Ok(text) => text,
+            Err(fat::Error::Card(_)) | Err(fat::Error::NoPartition) | Err(fat::Error::NotFound) => {
+                return
+            }
+            Err(error) => {
+                println!("Can't read {}: {}", AUTOEXEC, error);
+                return;
+            }
+        };
+        if let Err(throw) = self.interpret_file(AUTOEXEC, &text) {
+            self.recover(throw, &mut crate::console::output());
+        }
+    }
+}
+
+primitives! {
+    /// `( c-addr u -- )`
+    fn included(vm) {
+        let len = vm.pop()?.max(0) as usize;
+        let addr = vm.pop()? as usize;
+        let bytes = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        let path = str::from_utf8(bytes).map_err(|_| Throw::NON_EXISTENT_FILE)?;
+        let path = String::from(path);
+        vm.included(&path)
+    }
+
+    /// `( "name" -- )`
+    fn include(vm) {
+        let path = vm.parse_name()?;
+        if path.is_empty() {
+            return Err(Throw::ZERO_LENGTH_NAME);
+        }
+        let path = String::from(path);
+        vm.included(&path)
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] =
+    &[("INCLUDED", NONE, included), ("INCLUDE", NONE, include)];
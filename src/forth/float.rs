@@ -0,0 +1,702 @@
+//! The floating-point and floating-point extension word sets. Floats are
+//! IEEE doubles, kept on a stack of their own as their bit patterns, and
+//! stored in memory as eight bytes with cell alignment.
+//!
+//! The functions come from `crate::math`. Printing and parsing go through
+//! `core`'s formatting and `str::parse`, which round correctly, so a float
+//! printed with enough digits reads back as the same float.
+
+use alloc::{format, string::String, vec::Vec};
+use core::{mem, slice, str};
+
+use super::{
+    dictionary::{Entry, Flags},
+    words::{COMPILER, NONE},
+    Cell, Throw, Vm, FALSE, TRUE,
+};
+use crate::math;
+
+pub const FLOAT_STACK_CELLS: usize = 256;
+
+const FLOAT: Cell = mem::size_of::<f64>() as Cell;
+const SFLOAT: Cell = mem::size_of::<f32>() as Cell;
+
+/// The most significant digits it's useful to print.
+const MAX_PRECISION: usize = 17;
+
+/// The number of significant digits `F.`, `FE.` and `FS.` show at first.
+pub const DEFAULT_PRECISION: usize = 15;
+
+pub(super) extern "C" fn fliteral_runtime(vm: &mut Vm, bits: u64) -> isize {
+    Throw::into_code(vm.fpush(f64::from_bits(bits)))
+}
+
+/// `digits` as a string, or `empty` if there aren't any.
+fn digits_or<'a>(digits: &'a [u8], empty: &'a str) -> &'a str {
+    match digits {
+        [] => empty,
+        digits => str::from_utf8(digits).unwrap(),
+    }
+}
+
+/// Convert text in Forth's float syntax. A `literal` is what the text
+/// interpreter takes for a float: digits, an optional fraction, and an
+/// exponent that starts with `E`. Otherwise the syntax is `>FLOAT`'s, where
+/// there needn't be an exponent, and it can start with `D` or just a sign.
+pub fn parse_float(text: &[u8], literal: bool) -> Option<f64> {
+    let is_digit = |index: usize| text.get(index).map_or(false, u8::is_ascii_digit);
+    let digits_from = |mut index: usize| {
+        while is_digit(index) {
+            index += 1;
+        }
+        index
+    };
+    let mut index = 0;
+    let sign = match text.first() {
+        Some(b'-') => {
+            index += 1;
+            "-"
+        }
+        Some(b'+') => {
+            index += 1;
+            ""
+        }
+        _ => "",
+    };
+    let int_start = index;
+    index = digits_from(index);
+    let int = &text[int_start..index];
+    let mut frac: &[u8] = &[];
+    if text.get(index) == Some(&b'.') {
+        let frac_start = index + 1;
+        index = digits_from(frac_start);
+        frac = &text[frac_start..index];
+    }
+    if int.is_empty() && (literal || frac.is_empty()) {
+        return None;
+    }
+    let marker = text.get(index).map(|byte| byte.to_ascii_uppercase());
+    let mut exp_sign = "";
+    let has_exponent = match marker {
+        Some(b'E') => true,
+        Some(b'D') => !literal,
+        Some(b'+') | Some(b'-') if !literal => {
+            // The sign is the marker.
+            exp_sign = if text[index] == b'-' { "-" } else { "" };
+            true
+        }
+        _ => false,
+    };
+    if has_exponent {
+        index += 1;
+        if exp_sign.is_empty() {
+            match text.get(index) {
+                Some(b'-') => {
+                    exp_sign = "-";
+                    index += 1;
+                }
+                Some(b'+') => index += 1,
+                _ => (),
+            }
+        }
+    } else if literal {
+        return None;
+    }
+    let exp_start = index;
+    index = digits_from(index);
+    let exp = &text[exp_start..index];
+    if index != text.len() {
+        return None;
+    }
+    let normal = format!(
+        "{}{}.{}e{}{}",
+        sign,
+        digits_or(int, "0"),
+        digits_or(frac, "0"),
+        exp_sign,
+        digits_or(exp, "0")
+    );
+    normal.parse().ok()
+}
+
+/// The first `count` significant decimal digits of `|r|`, correctly
+/// rounded, and the power of ten of the first one. `r` must be finite.
+fn decimal_digits(r: f64, count: usize) -> (Vec<u8>, i32) {
+    let text = format!("{:.*e}", count.max(1) - 1, math::abs(r));
+    let marker = text.find('e').unwrap();
+    let digits = text[..marker].bytes().filter(|byte| *byte != b'.');
+    let exponent = text[marker + 1..].parse().unwrap();
+    (digits.take(count).collect(), exponent)
+}
+
+/// The digits `index` places after the first one, padding with zeros.
+fn digit_at(digits: &[u8], index: i32) -> u8 {
+    if index >= 0 && (index as usize) < digits.len() {
+        digits[index as usize]
+    } else {
+        b'0'
+    }
+}
+
+/// Format `r` with `precision` significant digits in the given style.
+/// Trailing zeros after the point are left off.
+fn format_float(r: f64, precision: usize, style: Style) -> String {
+    if r.is_nan() {
+        return String::from("NaN");
+    }
+    if r.is_infinite() {
+        return String::from(if r < 0.0 { "-inf" } else { "inf" });
+    }
+    let (digits, exponent) = decimal_digits(r, precision);
+    // How many digits go before the point, and the exponent shown.
+    let (before, shown) = match style {
+        Style::Fixed => (exponent + 1, None),
+        Style::Scientific => (1, Some(exponent)),
+        Style::Engineering => {
+            let shown = exponent - exponent.rem_euclid(3);
+            (exponent - shown + 1, Some(shown))
+        }
+    };
+    let mut out = String::new();
+    if r.is_sign_negative() {
+        out.push('-');
+    }
+    if before <= 0 {
+        out.push('0');
+    }
+    for index in 0..before {
+        out.push(digit_at(&digits, index) as char);
+    }
+    out.push('.');
+    let last = digits.iter().rposition(|digit| *digit != b'0');
+    let frac_end = last.map_or(0, |last| last as i32 + 1);
+    for index in 0..frac_end - before {
+        out.push(digit_at(&digits, before + index) as char);
+    }
+    if let Some(shown) = shown {
+        out.push_str(&format!("E{}", shown));
+    }
+    out
+}
+
+#[derive(Copy, Clone)]
+enum Style {
+    Fixed,
+    Scientific,
+    Engineering,
+}
+
+/// `r` as `SEE` shows it, which reads back as the same float.
+pub(super) fn float_literal(r: f64) -> String {
+    let mut text = format_float(r, MAX_PRECISION, Style::Scientific);
+    if !r.is_finite() {
+        text.push_str(" (not readable)");
+    }
+    text
+}
+
+impl Vm {
+    #[inline]
+    pub fn fpush(&mut self, value: f64) -> Result<(), Throw> {
+        self.floats.push(value.to_bits() as Cell)
+    }
+
+    #[inline]
+    pub fn fpop(&mut self) -> Result<f64, Throw> {
+        self.floats.pop().map(|bits| f64::from_bits(bits as u64))
+    }
+
+    pub fn compile_fliteral(&mut self, value: f64) -> Result<(), Throw> {
+        self.compile_native_call_with(fliteral_runtime as usize, value.to_bits() as Cell)
+    }
+
+    /// Apply `op` to the float on top of the stack.
+    fn funary(&mut self, op: impl FnOnce(f64) -> f64) -> Result<(), Throw> {
+        let r = self.fpop()?;
+        self.fpush(op(r))
+    }
+
+    /// Apply `op` to the top two floats, the deeper one first.
+    fn fbinary(&mut self, op: impl FnOnce(f64, f64) -> f64) -> Result<(), Throw> {
+        let r2 = self.fpop()?;
+        let r1 = self.fpop()?;
+        self.fpush(op(r1, r2))
+    }
+
+    fn fcompare(&mut self, op: impl FnOnce(f64, f64) -> bool) -> Result<(), Throw> {
+        let r2 = self.fpop()?;
+        let r1 = self.fpop()?;
+        self.push_flag(op(r1, r2))
+    }
+
+    fn fprint(&mut self, style: Style) -> Result<(), Throw> {
+        let r = self.fpop()?;
+        print!("{} ", format_float(r, self.precision, style));
+        Ok(())
+    }
+}
+
+/// Truncate `r` to an integer of at most `bits` bits.
+fn to_integer(r: f64, bits: u32) -> Result<i128, Throw> {
+    let limit = math::scalbn(1.0, bits as i32 - 1);
+    let r = math::trunc(r);
+    if r.is_nan() || r >= limit || r < -limit {
+        return Err(Throw::FLOATING_INVALID_ARGUMENT);
+    }
+    Ok(r as i128)
+}
+
+primitives! {
+    /// `( c-addr u -- true | false ) ( F: -- r | )`
+    fn to_float(vm) {
+        let len = vm.pop()?.max(0) as usize;
+        let addr = vm.pop()? as usize;
+        let text = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        let start = text.iter().position(|byte| *byte != b' ');
+        let end = text.iter().rposition(|byte| *byte != b' ').map_or(0, |end| end + 1);
+        // A blank string is zero.
+        let value = match start {
+            Some(start) => parse_float(&text[start..end], false),
+            None => Some(0.0),
+        };
+        match value {
+            Some(value) => {
+                vm.fpush(value)?;
+                vm.push(TRUE)
+            }
+            None => vm.push(FALSE),
+        }
+    }
+
+    fn d_to_f(vm) {
+        let high = vm.pop()?;
+        let low = vm.pop()?;
+        let value = (high as i128) << 64 | (low as usize as i128);
+        vm.fpush(value as f64)
+    }
+
+    fn f_to_d(vm) {
+        let value = to_integer(vm.fpop()?, 128)?;
+        vm.push(value as Cell)?;
+        vm.push((value >> 64) as Cell)
+    }
+
+    fn s_to_f(vm) {
+        let value = vm.pop()?;
+        vm.fpush(value as f64)
+    }
+
+    fn f_to_s(vm) {
+        let value = to_integer(vm.fpop()?, 64)?;
+        vm.push(value as Cell)
+    }
+
+    fn f_store(vm) {
+        let addr = vm.pop()? as *mut u64;
+        let r = vm.fpop()?;
+        unsafe { addr.write_volatile(r.to_bits()) };
+        Ok(())
+    }
+
+    fn f_fetch(vm) {
+        let addr = vm.pop()? as *const u64;
+        let bits = unsafe { addr.read_volatile() };
+        vm.fpush(f64::from_bits(bits))
+    }
+
+    fn sf_store(vm) {
+        let addr = vm.pop()? as *mut u32;
+        let r = vm.fpop()?;
+        unsafe { addr.write_volatile((r as f32).to_bits()) };
+        Ok(())
+    }
+
+    fn sf_fetch(vm) {
+        let addr = vm.pop()? as *const u32;
+        let bits = unsafe { addr.read_volatile() };
+        vm.fpush(f32::from_bits(bits) as f64)
+    }
+
+    fn f_plus(vm) {
+        vm.fbinary(|r1, r2| r1 + r2)
+    }
+
+    fn f_minus(vm) {
+        vm.fbinary(|r1, r2| r1 - r2)
+    }
+
+    fn f_star(vm) {
+        vm.fbinary(|r1, r2| r1 * r2)
+    }
+
+    fn f_slash(vm) {
+        vm.fbinary(|r1, r2| r1 / r2)
+    }
+
+    fn f_star_star(vm) {
+        vm.fbinary(math::pow)
+    }
+
+    fn f_zero_less(vm) {
+        let r = vm.fpop()?;
+        vm.push_flag(r < 0.0)
+    }
+
+    fn f_zero_equals(vm) {
+        let r = vm.fpop()?;
+        vm.push_flag(r == 0.0)
+    }
+
+    fn f_less(vm) {
+        vm.fcompare(|r1, r2| r1 < r2)
+    }
+
+    /// `( -- flag ) ( F: r1 r2 r3 -- )` Compare `r1` and `r2` exactly if
+    /// `r3` is zero, to within `r3` if it's positive, and to within `|r3|`
+    /// times the sum of their magnitudes if it's negative.
+    fn f_proximate(vm) {
+        let r3 = vm.fpop()?;
+        vm.fcompare(|r1, r2| {
+            if r3 > 0.0 {
+                math::abs(r1 - r2) < r3
+            } else if r3 == 0.0 {
+                r1.to_bits() == r2.to_bits()
+            } else {
+                math::abs(r1 - r2) < math::abs(r3) * (math::abs(r1) + math::abs(r2))
+            }
+        })
+    }
+
+    fn f_depth(vm) {
+        let depth = vm.floats.depth();
+        vm.push(depth as Cell)
+    }
+
+    fn f_drop(vm) {
+        vm.fpop()?;
+        Ok(())
+    }
+
+    fn f_dup(vm) {
+        let r = vm.floats.peek(0)?;
+        vm.floats.push(r)
+    }
+
+    fn f_over(vm) {
+        let r = vm.floats.peek(1)?;
+        vm.floats.push(r)
+    }
+
+    fn f_swap(vm) {
+        let r2 = vm.floats.pop()?;
+        let r1 = vm.floats.pop()?;
+        vm.floats.push(r2)?;
+        vm.floats.push(r1)
+    }
+
+    fn f_rot(vm) {
+        let r3 = vm.floats.pop()?;
+        let r2 = vm.floats.pop()?;
+        let r1 = vm.floats.pop()?;
+        vm.floats.push(r2)?;
+        vm.floats.push(r3)?;
+        vm.floats.push(r1)
+    }
+
+    fn f_negate(vm) {
+        vm.funary(|r| -r)
+    }
+
+    fn f_abs(vm) {
+        vm.funary(math::abs)
+    }
+
+    fn f_max(vm) {
+        vm.fbinary(|r1, r2| if r2 > r1 || r1.is_nan() { r2 } else { r1 })
+    }
+
+    fn f_min(vm) {
+        vm.fbinary(|r1, r2| if r2 < r1 || r1.is_nan() { r2 } else { r1 })
+    }
+
+    fn floor(vm) {
+        vm.funary(math::floor)
+    }
+
+    fn f_round(vm) {
+        vm.funary(math::round)
+    }
+
+    fn f_trunc(vm) {
+        vm.funary(math::trunc)
+    }
+
+    fn f_sqrt(vm) {
+        vm.funary(math::sqrt)
+    }
+
+    fn f_exp(vm) {
+        vm.funary(math::exp)
+    }
+
+    fn f_exp_m1(vm) {
+        vm.funary(math::exp_m1)
+    }
+
+    fn f_ln(vm) {
+        vm.funary(math::ln)
+    }
+
+    fn f_ln_p1(vm) {
+        vm.funary(math::ln_1p)
+    }
+
+    fn f_log(vm) {
+        vm.funary(math::log10)
+    }
+
+    fn f_alog(vm) {
+        vm.funary(|r| math::pow(10.0, r))
+    }
+
+    fn f_sin(vm) {
+        vm.funary(math::sin)
+    }
+
+    fn f_cos(vm) {
+        vm.funary(math::cos)
+    }
+
+    fn f_sin_cos(vm) {
+        let (sin, cos) = math::sin_cos(vm.fpop()?);
+        vm.fpush(sin)?;
+        vm.fpush(cos)
+    }
+
+    fn f_tan(vm) {
+        vm.funary(math::tan)
+    }
+
+    fn f_asin(vm) {
+        vm.funary(math::asin)
+    }
+
+    fn f_acos(vm) {
+        vm.funary(math::acos)
+    }
+
+    fn f_atan(vm) {
+        vm.funary(math::atan)
+    }
+
+    /// `( F: y x -- r )`
+    fn f_atan2(vm) {
+        vm.fbinary(math::atan2)
+    }
+
+    fn f_sinh(vm) {
+        vm.funary(math::sinh)
+    }
+
+    fn f_cosh(vm) {
+        vm.funary(math::cosh)
+    }
+
+    fn f_tanh(vm) {
+        vm.funary(math::tanh)
+    }
+
+    fn f_asinh(vm) {
+        vm.funary(math::asinh)
+    }
+
+    fn f_acosh(vm) {
+        vm.funary(math::acosh)
+    }
+
+    fn f_atanh(vm) {
+        vm.funary(math::atanh)
+    }
+
+    fn f_align(vm) {
+        vm.dict.align_to(FLOAT as usize)
+    }
+
+    fn f_aligned(vm) {
+        let addr = vm.pop()?;
+        vm.push((addr + FLOAT - 1) & !(FLOAT - 1))
+    }
+
+    fn float_plus(vm) {
+        let addr = vm.pop()?;
+        vm.push(addr.wrapping_add(FLOAT))
+    }
+
+    fn floats(vm) {
+        let count = vm.pop()?;
+        vm.push(count.wrapping_mul(FLOAT))
+    }
+
+    fn sf_align(vm) {
+        vm.dict.align_to(SFLOAT as usize)
+    }
+
+    fn sf_aligned(vm) {
+        let addr = vm.pop()?;
+        vm.push((addr + SFLOAT - 1) & !(SFLOAT - 1))
+    }
+
+    fn sfloat_plus(vm) {
+        let addr = vm.pop()?;
+        vm.push(addr.wrapping_add(SFLOAT))
+    }
+
+    fn sfloats(vm) {
+        let count = vm.pop()?;
+        vm.push(count.wrapping_mul(SFLOAT))
+    }
+
+    fn f_variable(vm) {
+        let name = vm.parse_new_name()?;
+        vm.create(&name)?;
+        vm.dict.comma(0)
+    }
+
+    fn f_constant(vm) {
+        let name = vm.parse_new_name()?;
+        let r = vm.fpop()?;
+        vm.constant_with(&name, r.to_bits() as usize, fliteral_runtime as usize)?;
+        Ok(())
+    }
+
+    fn f_literal(vm) {
+        let r = vm.fpop()?;
+        vm.compile_fliteral(r)
+    }
+
+    fn f_dot(vm) {
+        vm.fprint(Style::Fixed)
+    }
+
+    fn f_e_dot(vm) {
+        vm.fprint(Style::Engineering)
+    }
+
+    fn f_s_dot(vm) {
+        vm.fprint(Style::Scientific)
+    }
+
+    /// `( c-addr u -- n flag1 flag2 ) ( F: r -- )` Store the first `u`
+    /// significant digits of `r` at `c-addr`. `n` is the decimal exponent
+    /// for a point before the first digit, `flag1` whether `r` is negative,
+    /// and `flag2` whether it's finite.
+    fn represent(vm) {
+        let len = vm.pop()?.max(0) as usize;
+        let addr = vm.pop()? as usize;
+        let r = vm.fpop()?;
+        let buffer = unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) };
+        if !r.is_finite() {
+            let text = if r.is_nan() { "NaN" } else { "inf" };
+            for (out, byte) in buffer.iter_mut().zip(text.bytes().chain(core::iter::repeat(b' '))) {
+                *out = byte;
+            }
+            vm.push(0)?;
+            vm.push_flag(r.is_sign_negative())?;
+            return vm.push(FALSE);
+        }
+        let (digits, exponent) = decimal_digits(r, len);
+        buffer.copy_from_slice(&digits);
+        vm.push(exponent as Cell + 1)?;
+        vm.push_flag(r.is_sign_negative())?;
+        vm.push(TRUE)
+    }
+
+    fn precision(vm) {
+        let precision = vm.precision;
+        vm.push(precision as Cell)
+    }
+
+    fn set_precision(vm) {
+        let precision = vm.pop()?;
+        vm.precision = (precision.max(1) as usize).min(MAX_PRECISION);
+        Ok(())
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    (">FLOAT", NONE, to_float),
+    ("D>F", NONE, d_to_f),
+    ("F>D", NONE, f_to_d),
+    ("S>F", NONE, s_to_f),
+    ("F>S", NONE, f_to_s),
+    ("F!", NONE, f_store),
+    ("F@", NONE, f_fetch),
+    ("DF!", NONE, f_store),
+    ("DF@", NONE, f_fetch),
+    ("SF!", NONE, sf_store),
+    ("SF@", NONE, sf_fetch),
+    ("F+", NONE, f_plus),
+    ("F-", NONE, f_minus),
+    ("F*", NONE, f_star),
+    ("F/", NONE, f_slash),
+    ("F**", NONE, f_star_star),
+    ("F0<", NONE, f_zero_less),
+    ("F0=", NONE, f_zero_equals),
+    ("F<", NONE, f_less),
+    ("F~", NONE, f_proximate),
+    ("FDEPTH", NONE, f_depth),
+    ("FDROP", NONE, f_drop),
+    ("FDUP", NONE, f_dup),
+    ("FOVER", NONE, f_over),
+    ("FSWAP", NONE, f_swap),
+    ("FROT", NONE, f_rot),
+    ("FNEGATE", NONE, f_negate),
+    ("FABS", NONE, f_abs),
+    ("FMAX", NONE, f_max),
+    ("FMIN", NONE, f_min),
+    ("FLOOR", NONE, floor),
+    ("FROUND", NONE, f_round),
+    ("FTRUNC", NONE, f_trunc),
+    ("FSQRT", NONE, f_sqrt),
+    ("FEXP", NONE, f_exp),
+    ("FEXPM1", NONE, f_exp_m1),
+    ("FLN", NONE, f_ln),
+    ("FLNP1", NONE, f_ln_p1),
+    ("FLOG", NONE, f_log),
+    ("FALOG", NONE, f_alog),
+    ("FSIN", NONE, f_sin),
+    ("FCOS", NONE, f_cos),
+    ("FSINCOS", NONE, f_sin_cos),
+    ("FTAN", NONE, f_tan),
+    ("FASIN", NONE, f_asin),
+    ("FACOS", NONE, f_acos),
+    ("FATAN", NONE, f_atan),
+    ("FATAN2", NONE, f_atan2),
+    ("FSINH", NONE, f_sinh),
+    ("FCOSH", NONE, f_cosh),
+    ("FTANH", NONE, f_tanh),
+    ("FASINH", NONE, f_asinh),
+    ("FACOSH", NONE, f_acosh),
+    ("FATANH", NONE, f_atanh),
+    ("FALIGN", NONE, f_align),
+    ("FALIGNED", NONE, f_aligned),
+    ("FLOAT+", NONE, float_plus),
+    ("FLOATS", NONE, floats),
+    ("DFALIGN", NONE, f_align),
+    ("DFALIGNED", NONE, f_aligned),
+    ("DFLOAT+", NONE, float_plus),
+    ("DFLOATS", NONE, floats),
+    ("SFALIGN", NONE, sf_align),
+    ("SFALIGNED", NONE, sf_aligned),
+    ("SFLOAT+", NONE, sfloat_plus),
+    ("SFLOATS", NONE, sfloats),
+    ("FVARIABLE", NONE, f_variable),
+    ("FCONSTANT", NONE, f_constant),
+    ("FLITERAL", COMPILER, f_literal),
+    ("F.", NONE, f_dot),
+    ("FE.", NONE, f_e_dot),
+    ("FS.", NONE, f_s_dot),
+    ("REPRESENT", NONE, represent),
+    ("PRECISION", NONE, precision),
+    ("SET-PRECISION", NONE, set_precision),
+];
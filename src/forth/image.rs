@@ -0,0 +1,291 @@
+//! Saving the dictionary to a file on the host, and loading it back, over
+//! semihosting. This lets a session in QEMU be checkpointed with
+//! `S" session.img" SAVE-IMAGE` and picked up again after a reboot with
+//! `S" session.img" LOAD-IMAGE`.
+//!
+//! An image holds the used part of the data space, the relocations recorded
+//! while compiling it, and `BASE`. It can be loaded into a data space at a
+//! different address: the relocations find every instruction that refers to
+//! the data space or, by a PC-relative call, to the kernel. Addresses that
+//! programs stored in their own data with `,` or `!` are not adjusted.
+//!
+//! Compiled code calls into the kernel, so an image is only valid for the
+//! kernel build that saved it. Images record a checksum of the kernel's code,
+//! and `LOAD-IMAGE` refuses images saved by a different build.
+
+use alloc::vec::Vec;
+use core::{mem, ptr, slice};
+
+use super::{
+    codegen::{self, INSTR_SIZE},
+    dictionary::{Entry, Flags, Reloc, DATA_SPACE_SIZE},
+    words::NONE,
+    Cell, Throw, Vm,
+};
+use crate::rpi::semihosting::{self, OpenMode};
+
+const MAGIC: [u8; 8] = *b"RPiForth";
+
+/// The version of the image format. Bump this whenever the layout of images,
+/// headers or compiled code changes.
+const VERSION: u32 = 1;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ImageHeader {
+    magic: [u8; 8],
+    version: u32,
+    reloc_count: u32,
+    /// The checksum of the kernel that saved the image.
+    kernel: u64,
+    /// The address of the data space when the image was saved.
+    start: u64,
+    /// The number of bytes of data space that follow the header.
+    len: u64,
+    /// The address of the latest definition.
+    latest: u64,
+    base: i64,
+}
+
+// Relocations are stored as the offset into the data space, shifted left one
+// bit, with the low bit set for a constant.
+fn encode_reloc(reloc: Reloc, start: usize) -> u64 {
+    match reloc {
+        Reloc::Call(addr) => ((addr - start) as u64) << 1,
+        Reloc::Constant(addr) => ((addr - start) as u64) << 1 | 1,
+    }
+}
+
+fn decode_reloc(word: u64, start: usize) -> Reloc {
+    let addr = start + (word >> 1) as usize;
+    if word & 1 == 0 {
+        Reloc::Call(addr)
+    } else {
+        Reloc::Constant(addr)
+    }
+}
+
+extern "C" {
+    type MARKER;
+
+    #[link_name = "__start"]
+    static TEXT_START: MARKER;
+    #[link_name = "__text_end"]
+    static TEXT_END: MARKER;
+}
+
+/// An FNV-1a hash of the kernel's code.
+fn kernel_checksum() -> u64 {
+    let start = unsafe { &TEXT_START as *const MARKER as usize };
+    let end = unsafe { &TEXT_END as *const MARKER as usize };
+    let text = unsafe { slice::from_raw_parts(start as *const u8, end - start) };
+    text.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+/// An image that has been read and relocated, waiting to replace the
+/// dictionary once the code that loaded it has unwound (see
+/// `Throw::LOAD_IMAGE`).
+pub struct Image {
+    data: Vec<u8>,
+    old_start: usize,
+    latest: usize,
+    relocs: Vec<Reloc>,
+    base: Cell,
+}
+
+impl Image {
+    /// Check a saved image and relocate it for a data space at `start`.
+    fn parse(file: &[u8], start: usize) -> Result<Image, Throw> {
+        let header_len = mem::size_of::<ImageHeader>();
+        if file.len() < header_len {
+            return Err(Throw::INVALID_IMAGE);
+        }
+        let header = unsafe { ptr::read_unaligned(file.as_ptr() as *const ImageHeader) };
+        if header.magic != MAGIC || header.version != VERSION {
+            return Err(Throw::INVALID_IMAGE);
+        }
+        if header.kernel != kernel_checksum() {
+            return Err(Throw::STALE_IMAGE);
+        }
+        let old_start = header.start as usize;
+        let len = header.len as usize;
+        let relocs_len = header.reloc_count as usize * mem::size_of::<u64>();
+        let latest = (header.latest as usize).wrapping_sub(old_start);
+        if len > DATA_SPACE_SIZE || header_len + len + relocs_len != file.len() || latest >= len {
+            return Err(Throw::INVALID_IMAGE);
+        }
+        let mut image = Image {
+            data: file[header_len..header_len + len].to_vec(),
+            old_start,
+            latest: start + latest,
+            relocs: Vec::with_capacity(header.reloc_count as usize),
+            base: header.base as Cell,
+        };
+        for chunk in file[header_len + len..].chunks(mem::size_of::<u64>()) {
+            let word = unsafe { ptr::read_unaligned(chunk.as_ptr() as *const u64) };
+            let reloc = decode_reloc(word, old_start);
+            image.relocate(reloc, start)?;
+            image.relocs.push(decode_reloc(word, start));
+        }
+        Ok(image)
+    }
+
+    /// Adjust the code that `reloc` refers to for a data space at `start`.
+    fn relocate(&mut self, reloc: Reloc, start: usize) -> Result<(), Throw> {
+        let old_start = self.old_start;
+        let in_data_space = |addr: usize| addr >= old_start && addr < old_start + DATA_SPACE_SIZE;
+        let offset = reloc.addr().wrapping_sub(old_start);
+        let len = match reloc {
+            Reloc::Call(_) => 1,
+            Reloc::Constant(_) => 4,
+        };
+        if offset % INSTR_SIZE != 0 || offset + len * INSTR_SIZE > self.data.len() {
+            return Err(Throw::INVALID_IMAGE);
+        }
+        let mut instrs = [0; 4];
+        for (index, instr) in instrs[..len].iter_mut().enumerate() {
+            let addr = &self.data[offset + index * INSTR_SIZE] as *const u8;
+            *instr = unsafe { ptr::read_unaligned(addr as *const u32) };
+        }
+        match reloc {
+            // Calls within the data space move along with it.
+            Reloc::Call(addr) => {
+                let target = codegen::decode_branch(addr, instrs[0]).ok_or(Throw::INVALID_IMAGE)?;
+                if !in_data_space(target) {
+                    instrs[0] =
+                        codegen::bl(start + offset, target).ok_or(Throw::BRANCH_OUT_OF_RANGE)?;
+                }
+            }
+            Reloc::Constant(_) => {
+                let (rd, value) = codegen::decode_constant(&instrs).ok_or(Throw::INVALID_IMAGE)?;
+                let value = value as usize;
+                if in_data_space(value) {
+                    let value = value - old_start + start;
+                    instrs = codegen::load_constant(rd, value as u64);
+                }
+            }
+        }
+        for (index, instr) in instrs[..len].iter().enumerate() {
+            let addr = &mut self.data[offset + index * INSTR_SIZE] as *mut u8;
+            unsafe { ptr::write_unaligned(addr as *mut u32, *instr) };
+        }
+        Ok(())
+    }
+}
+
+impl Vm {
+    fn save_image(&self, filename: &[u8]) -> Result<(), Throw> {
+        let start = self.dict.start();
+        let data = self.dict.slice_from(start);
+        let latest = self
+            .dict
+            .latest()
+            .map_or(0, |header| header as *const _ as usize);
+        let header = ImageHeader {
+            magic: MAGIC,
+            version: VERSION,
+            reloc_count: self.dict.relocs().len() as u32,
+            kernel: kernel_checksum(),
+            start: start as u64,
+            len: data.len() as u64,
+            latest: latest as u64,
+            base: self.user.base as i64,
+        };
+        let relocs: Vec<u64> = self
+            .dict
+            .relocs()
+            .iter()
+            .map(|reloc| encode_reloc(*reloc, start))
+            .collect();
+        let header_bytes = unsafe {
+            slice::from_raw_parts(
+                &header as *const ImageHeader as *const u8,
+                mem::size_of::<ImageHeader>(),
+            )
+        };
+        let relocs_bytes = unsafe {
+            slice::from_raw_parts(
+                relocs.as_ptr() as *const u8,
+                relocs.len() * mem::size_of::<u64>(),
+            )
+        };
+        let handle = semihosting::sys_open(filename, OpenMode::WB).ok_or(Throw::FILE_IO)?;
+        let unwritten = semihosting::sys_write(handle, header_bytes)
+            + semihosting::sys_write(handle, data)
+            + semihosting::sys_write(handle, relocs_bytes);
+        let closed = semihosting::sys_close(handle);
+        if unwritten != 0 || closed.is_err() {
+            return Err(Throw::FILE_IO);
+        }
+        Ok(())
+    }
+
+    fn read_image(&self, filename: &[u8]) -> Result<Image, Throw> {
+        let handle =
+            semihosting::sys_open(filename, OpenMode::RB).ok_or(Throw::NON_EXISTENT_FILE)?;
+        let mut file = Vec::new();
+        let result = semihosting::sys_flen(handle)
+            .ok_or(Throw::FILE_IO)
+            .and_then(|len| {
+                file.resize(len, 0);
+                match semihosting::sys_read(handle, &mut file) {
+                    0 => Ok(()),
+                    _ => Err(Throw::FILE_IO),
+                }
+            });
+        let _ = semihosting::sys_close(handle);
+        result?;
+        Image::parse(&file, self.dict.start())
+    }
+
+    /// Replace the dictionary with `image`. This must only be called with
+    /// no compiled code running, since that code is about to be overwritten.
+    pub fn install_image(&mut self, image: Image) {
+        self.dict
+            .restore(&image.data, image.old_start, image.latest, image.relocs);
+        codegen::sync_icache(self.dict.start(), self.dict.here());
+        // None of the tasks' words survive.
+        self.forget_tasks(self.dict.start());
+        self.user.base = image.base;
+    }
+
+    /// Pop a file name, returning a NUL-terminated copy of it.
+    fn pop_filename(&mut self) -> Result<Vec<u8>, Throw> {
+        let len = self.pop()? as usize;
+        let addr = self.pop()? as usize;
+        let name = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        if name.is_empty() || name.contains(&0) {
+            return Err(Throw::NON_EXISTENT_FILE);
+        }
+        let mut filename = Vec::with_capacity(len + 1);
+        filename.extend_from_slice(name);
+        filename.push(0);
+        Ok(filename)
+    }
+}
+
+primitives! {
+    /// `( c-addr u -- )` Save the dictionary to the host file named by the
+    /// string.
+    fn save_image(vm) {
+        let filename = vm.pop_filename()?;
+        vm.save_image(&filename)
+    }
+
+    /// `( c-addr u -- )` Replace the dictionary with the image in the host
+    /// file named by the string, then go back to the outer interpreter as
+    /// `ABORT` does.
+    fn load_image(vm) {
+        let filename = vm.pop_filename()?;
+        let image = vm.read_image(&filename)?;
+        vm.pending_image = Some(image);
+        Err(Throw::LOAD_IMAGE)
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    ("SAVE-IMAGE", NONE, save_image),
+    ("LOAD-IMAGE", NONE, load_image),
+];
@@ -0,0 +1,428 @@
+use alloc::{format, string::String};
+use core::{fmt::Write, slice};
+
+use super::{float, Cell, Flags, Header, Throw, Vm};
+
+pub const LINE_LENGTH: usize = 128;
+
+/// Where the interpreter is currently reading from.
+#[derive(Copy, Clone)]
+enum Source {
+    /// The terminal input buffer, filled from the serial port.
+    Terminal,
+    /// A string passed to `EVALUATE`.
+    String { addr: usize, len: usize },
+    /// A copy of a block being loaded by `LOAD`. `BLK` holds its number.
+    Block { addr: usize, len: usize },
+    /// A line of a file being included by `INCLUDED`.
+    File { addr: usize, len: usize },
+}
+
+/// The most files that can be included inside one another.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Where the exception being reported was raised.
+pub struct ErrorSite {
+    pub word: String,
+    /// The file and line number, if it was raised while including a file.
+    pub file: Option<(String, usize)>,
+}
+
+/// The input source and the buffers that parsing words return. The parse
+/// position `>IN` lives in the user area.
+pub struct Input {
+    tib: [u8; LINE_LENGTH],
+    tib_len: usize,
+    source: Source,
+    /// The counted string returned by `WORD`.
+    word: [u8; LINE_LENGTH + 1],
+    /// Strings made by `S"` while interpreting. There are two so that a pair
+    /// of them can be compared.
+    strings: [[u8; LINE_LENGTH]; 2],
+    next_string: usize,
+    /// The number of files being included.
+    include_depth: usize,
+    error: Option<ErrorSite>,
+}
+
+impl Input {
+    pub const fn new() -> Input {
+        Input {
+            tib: [0; LINE_LENGTH],
+            tib_len: 0,
+            source: Source::Terminal,
+            word: [0; LINE_LENGTH + 1],
+            strings: [[0; LINE_LENGTH]; 2],
+            next_string: 0,
+            include_depth: 0,
+            error: None,
+        }
+    }
+
+    /// Go back to reading from the terminal.
+    pub fn reset(&mut self) {
+        self.source = Source::Terminal;
+        self.include_depth = 0;
+    }
+
+    /// The address and length of the current input source, as `SOURCE`
+    /// returns them.
+    pub fn source(&self) -> (usize, usize) {
+        match self.source {
+            Source::Terminal => (self.tib.as_ptr() as usize, self.tib_len),
+            Source::String { addr, len }
+            | Source::Block { addr, len }
+            | Source::File { addr, len } => (addr, len),
+        }
+    }
+
+    /// `SOURCE-ID`: zero for the terminal, or -1 while evaluating a string.
+    /// It isn't meaningful while loading a block, and is zero then. There
+    /// are no file identifiers, so files are identified by how deeply they
+    /// are nested, starting from one.
+    pub fn source_id(&self) -> Cell {
+        match self.source {
+            Source::Terminal | Source::Block { .. } => 0,
+            Source::String { .. } => -1,
+            Source::File { .. } => self.include_depth as Cell,
+        }
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        match self.source {
+            Source::Terminal => &self.tib[..self.tib_len],
+            Source::String { addr, len }
+            | Source::Block { addr, len }
+            | Source::File { addr, len } => unsafe {
+                slice::from_raw_parts(addr as *const u8, len)
+            },
+        }
+    }
+
+    /// Copy `len` bytes at `addr` into the next transient string buffer,
+    /// returning the address of the copy.
+    pub fn stash_string(&mut self, addr: usize, len: usize) -> Result<usize, Throw> {
+        if len > LINE_LENGTH {
+            return Err(Throw::PARSED_STRING_OVERFLOW);
+        }
+        let index = self.next_string;
+        self.next_string = (index + 1) % self.strings.len();
+        let buffer = &mut self.strings[index];
+        let bytes = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        buffer[..len].copy_from_slice(bytes);
+        Ok(buffer.as_ptr() as usize)
+    }
+
+    pub fn take_error(&mut self) -> Option<ErrorSite> {
+        self.error.take()
+    }
+}
+
+impl Vm {
+    /// Parse the next space-delimited name from the input, advancing `>IN`
+    /// past it and the space after it. Returns the name as it is in the
+    /// input, which is empty at the end of the line.
+    pub fn parse_name_bytes(&mut self) -> &[u8] {
+        let bytes = self.user.input.as_bytes();
+        let mut to_in = (self.user.to_in.max(0) as usize).min(bytes.len());
+        while to_in < bytes.len() && bytes[to_in] <= b' ' {
+            to_in += 1;
+        }
+        let start = to_in;
+        while to_in < bytes.len() && bytes[to_in] > b' ' {
+            to_in += 1;
+        }
+        let end = to_in;
+        self.user.to_in = (to_in + 1).min(bytes.len()) as Cell;
+        &bytes[start..end]
+    }
+
+    /// Parse the next name as `parse_name_bytes` does. Files and blocks can
+    /// hold any bytes, and a name that isn't UTF-8 throws rather than being
+    /// taken for the end of the line.
+    pub fn parse_name(&mut self) -> Result<&str, Throw> {
+        core::str::from_utf8(self.parse_name_bytes()).map_err(|_| Throw::INVALID_NAME)
+    }
+
+    /// Parse text up to `delimiter`, advancing `>IN` past the delimiter, and
+    /// return the address and length of the text in the input source.
+    pub fn parse(&mut self, delimiter: u8) -> (usize, usize) {
+        let (addr, len) = self.user.input.source();
+        let bytes = self.user.input.as_bytes();
+        let start = (self.user.to_in.max(0) as usize).min(len);
+        let end = bytes[start..]
+            .iter()
+            .position(|byte| *byte == delimiter)
+            .map_or(len, |offset| start + offset);
+        self.user.to_in = (end + 1).min(len) as Cell;
+        (addr + start, end - start)
+    }
+
+    /// Parse text delimited by `delimiter` as `WORD` does, skipping leading
+    /// delimiters, and return the address of it as a counted string. A space
+    /// delimiter matches any control character too.
+    pub fn word(&mut self, delimiter: u8) -> usize {
+        let is_delimiter = |byte: u8| byte == delimiter || (delimiter == b' ' && byte < b' ');
+        let (addr, len) = self.user.input.source();
+        let bytes = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        let mut to_in = (self.user.to_in.max(0) as usize).min(len);
+        while to_in < len && is_delimiter(bytes[to_in]) {
+            to_in += 1;
+        }
+        let start = to_in;
+        while to_in < len && !is_delimiter(bytes[to_in]) {
+            to_in += 1;
+        }
+        let count = (to_in - start).min(LINE_LENGTH);
+        let word = &mut self.user.input.word;
+        word[0] = count as u8;
+        word[1..=count].copy_from_slice(&bytes[start..start + count]);
+        self.user.to_in = (to_in + 1).min(len) as Cell;
+        word.as_ptr() as usize
+    }
+
+    /// Interpret or compile a single word, depending on `STATE`.
+    pub fn interpret_word(&mut self, word: &str) -> Result<(), Throw> {
+        let found = self
+            .dict
+            .find(word)
+            .map(|header| (header as *const Header, header.flags()));
+        match found {
+            Some((xt, flags)) => {
+                if self.compiling() && !flags.contains(Flags::IMMEDIATE) {
+                    self.compile_call(xt)
+                } else if !self.compiling() && flags.contains(Flags::COMPILE_ONLY) {
+                    Err(Throw::COMPILE_ONLY)
+                } else {
+                    self.execute(xt)
+                }
+            }
+            None => {
+                if let Some(value) = parse_number(word, self.user.base) {
+                    if self.compiling() {
+                        self.compile_literal(value)
+                    } else {
+                        self.push(value)
+                    }
+                } else if let Some(value) = self.parse_float_literal(word) {
+                    if self.compiling() {
+                        self.compile_fliteral(value)
+                    } else {
+                        self.fpush(value)
+                    }
+                } else {
+                    Err(Throw::UNDEFINED_WORD)
+                }
+            }
+        }
+    }
+
+    /// Float literals are only recognised when `BASE` is decimal.
+    fn parse_float_literal(&self, word: &str) -> Option<f64> {
+        if self.user.base != 10 {
+            return None;
+        }
+        float::parse_float(word.as_bytes(), true)
+    }
+
+    /// Interpret the rest of the current input source.
+    pub fn interpret(&mut self) -> Result<(), Throw> {
+        let mut word = String::new();
+        loop {
+            word.clear();
+            word.push_str(self.parse_name()?);
+            if word.is_empty() {
+                return Ok(());
+            }
+            if let Err(throw) = self.interpret_word(&word) {
+                // Keep the innermost word, if this is a nested `EVALUATE`.
+                if self.user.input.error.is_none() {
+                    self.user.input.error = Some(ErrorSite { word, file: None });
+                }
+                return Err(throw);
+            }
+        }
+    }
+
+    /// Interpret `source` with `BLK` set to `blk`, then restore the previous
+    /// input source.
+    fn interpret_source(&mut self, source: Source, blk: Cell) -> Result<(), Throw> {
+        let saved = (self.user.input.source, self.user.to_in, self.user.blk);
+        self.user.input.source = source;
+        self.user.to_in = 0;
+        self.user.blk = blk;
+        let result = self.interpret();
+        let (source, to_in, blk) = saved;
+        self.user.input.source = source;
+        self.user.to_in = to_in;
+        self.user.blk = blk;
+        result
+    }
+
+    /// Interpret `len` bytes at `addr`, then restore the previous input
+    /// source.
+    pub fn evaluate(&mut self, addr: usize, len: usize) -> Result<(), Throw> {
+        self.interpret_source(Source::String { addr, len }, 0)
+    }
+
+    /// Interpret the text of block number `block`, which has been copied to
+    /// `addr`, then restore the previous input source.
+    pub fn interpret_block(&mut self, block: Cell, addr: usize, len: usize) -> Result<(), Throw> {
+        self.interpret_source(Source::Block { addr, len }, block)
+    }
+
+    /// Interpret `text`, the contents of the file `name`, a line at a time,
+    /// then restore the previous input source.
+    pub fn interpret_file(&mut self, name: &str, text: &[u8]) -> Result<(), Throw> {
+        if self.user.input.include_depth >= MAX_INCLUDE_DEPTH {
+            return Err(Throw::INCLUDE_NESTING);
+        }
+        self.user.input.include_depth += 1;
+        let mut result = Ok(());
+        for (index, line) in text.split(|byte| *byte == b'\n').enumerate() {
+            let line = match line.last() {
+                Some(b'\r') => &line[..line.len() - 1],
+                _ => line,
+            };
+            let source = Source::File {
+                addr: line.as_ptr() as usize,
+                len: line.len(),
+            };
+            result = self.interpret_source(source, 0);
+            if result.is_err() {
+                // Report the innermost file, if this is a nested include.
+                if let Some(error) = &mut self.user.input.error {
+                    if error.file.is_none() {
+                        error.file = Some((String::from(name), index + 1));
+                    }
+                }
+                break;
+            }
+        }
+        self.user.input.include_depth -= 1;
+        result
+    }
+
+    /// Read a line from the serial port into the terminal input buffer,
+    /// letting the other tasks run while waiting for keys.
+    fn accept_terminal(&mut self) {
+        // The buffer is part of the user area, which is swapped out while
+        // the other tasks run, so the line is read into a copy.
+        let mut line = [0; LINE_LENGTH];
+        let Vm {
+            editor,
+            dict,
+            tasks,
+            user,
+            data,
+            ret,
+            floats,
+            ..
+        } = self;
+        let idle = &mut || tasks.pause(user, data, ret, floats);
+        let len = editor.read_line(&mut line, &*dict, idle);
+        let input = &mut self.user.input;
+        input.tib[..len].copy_from_slice(&line[..len]);
+        input.tib_len = len;
+    }
+
+    /// Report an exception that nothing caught, and reset the machine to
+    /// continue from the console.
+    pub fn recover(&mut self, throw: Throw, out: &mut impl Write) {
+        let (word, file) = match self.user.input.take_error() {
+            Some(ErrorSite { word, file }) => (word, file),
+            None => (String::new(), None),
+        };
+        let at = file
+            .map(|(name, line)| format!("{}:{}: ", name, line))
+            .unwrap_or_default();
+        match throw {
+            Throw::ABORT => self.abort(),
+            Throw::ABORT_QUOTE => {
+                if let Some((addr, len)) = self.user.abort_message {
+                    let bytes = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+                    let message = core::str::from_utf8(bytes).unwrap_or("?");
+                    let _ = writeln!(out, "{}{}", at, message);
+                }
+                self.abort();
+            }
+            Throw::QUIT => self.reset(),
+            #[cfg(feature = "semihosting")]
+            Throw::LOAD_IMAGE => {
+                self.abort();
+                if let Some(image) = self.pending_image.take() {
+                    self.install_image(image);
+                }
+            }
+            Throw::UNDEFINED_WORD => {
+                let _ = writeln!(out, "{}{} ?", at, word);
+                self.abort();
+            }
+            _ => {
+                let _ = writeln!(out, "{}{} ? {}", at, word, throw);
+                self.abort();
+            }
+        }
+    }
+}
+
+/// Convert `text` to a number using `base`. Leading `#`, `$` and `%` select
+/// decimal, hexadecimal and binary respectively, and `'c'` is a character
+/// literal.
+pub fn parse_number(text: &str, base: Cell) -> Option<Cell> {
+    let bytes = text.as_bytes();
+    if bytes.len() == 3 && bytes[0] == b'\'' && bytes[2] == b'\'' {
+        return Some(bytes[1] as Cell);
+    }
+    let (base, bytes) = match bytes.first() {
+        Some(b'#') => (10, &bytes[1..]),
+        Some(b'$') => (16, &bytes[1..]),
+        Some(b'%') => (2, &bytes[1..]),
+        _ => (base, bytes),
+    };
+    let (negative, bytes) = match bytes.first() {
+        Some(b'-') => (true, &bytes[1..]),
+        _ => (false, bytes),
+    };
+    if bytes.is_empty() || base < 2 || base > 36 {
+        return None;
+    }
+    let mut value: Cell = 0;
+    for byte in bytes {
+        let digit = digit_value(*byte)?;
+        if digit >= base {
+            return None;
+        }
+        value = value.wrapping_mul(base).wrapping_add(digit);
+    }
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+#[inline]
+pub fn digit_value(byte: u8) -> Option<Cell> {
+    match byte {
+        b'0'..=b'9' => Some((byte - b'0') as Cell),
+        b'a'..=b'z' => Some((byte - b'a') as Cell + 10),
+        b'A'..=b'Z' => Some((byte - b'A') as Cell + 10),
+        _ => None,
+    }
+}
+
+/// The outer interpreter: read a line, interpret it, and report the result.
+/// Errors are reported and the system returns to the prompt.
+pub fn quit(vm: &mut Vm) -> ! {
+    loop {
+        vm.accept_terminal();
+        vm.user.to_in = 0;
+        match vm.interpret() {
+            Ok(()) if vm.compiling() => println!(" compiled"),
+            Ok(()) => println!(" ok"),
+            Err(throw) => vm.recover(throw, &mut crate::console::output()),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Sending VideoCore property tags from the prompt, for querying clocks,
+//! temperatures and the board revision without writing a message type in
+//! Rust first.
+//!
+//! For example, to read the board revision (tag `$10002`, a four byte
+//! response) into `BUF`:
+//!
+//! ```forth
+//! CREATE BUF 4 ALLOT
+//! $10002 BUF 0 4 MAILBOX-PROPERTY .  BUF @ $FFFFFFFF AND HEX .
+//! ```
+
+use core::slice;
+
+use super::{
+    dictionary::{Entry, Flags},
+    words::NONE,
+    Cell, Throw, Vm, TRUE,
+};
+use crate::{
+    executor,
+    rpi::mailbox::{PropertyMessage, PropertyTagList, MAX_RAW_VALUE_SIZE},
+};
+
+primitives! {
+    /// `( tag req-addr req-len resp-len -- status )` Send a single property
+    /// tag with `req-len` bytes of request from `req-addr`, and copy up to
+    /// `resp-len` bytes of the response back over the request. `status` is
+    /// the length of the response the firmware gave, or -1 if the message
+    /// failed.
+    fn mailbox_property(vm) {
+        let resp_len = vm.pop()? as usize;
+        let req_len = vm.pop()? as usize;
+        let addr = vm.pop()? as usize;
+        let tag = vm.pop()? as u32;
+        if req_len > MAX_RAW_VALUE_SIZE || resp_len > MAX_RAW_VALUE_SIZE {
+            return Err(Throw::INVALID_NUMERIC_ARGUMENT);
+        }
+        let request = unsafe { slice::from_raw_parts(addr as *const u8, req_len) };
+        let message = PropertyMessage::raw(tag, request, req_len.max(resp_len))
+            .ok_or(Throw::INVALID_NUMERIC_ARGUMENT)?;
+        let mut message = message.prepare();
+        let answered = match executor::block_on(message.send()) {
+            Some(tag) => tag.response_len(),
+            None => None,
+        };
+        match answered {
+            Some(len) => {
+                let response = unsafe { slice::from_raw_parts_mut(addr as *mut u8, resp_len) };
+                let copied = len.min(resp_len);
+                response[..copied].copy_from_slice(&message.value().as_bytes()[..copied]);
+                vm.push(len as Cell)
+            }
+            None => vm.push(TRUE),
+        }
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[("MAILBOX-PROPERTY", NONE, mailbox_property)];
@@ -0,0 +1,60 @@
+//! The memory-allocation word set, over the kernel heap. Blocks are
+//! allocated with the global allocator, and their layouts are remembered by
+//! `allocator::forth_allocate` so that `FREE` and `RESIZE` only need the
+//! address. The I/O results are the standard `THROW` codes for each word.
+
+use super::{
+    dictionary::{Entry, Flags},
+    words::NONE,
+    Cell, Throw, Vm,
+};
+use crate::allocator;
+
+primitives! {
+    /// `( u -- a-addr ior )`
+    fn allocate(vm) {
+        let size = vm.pop()? as usize;
+        let ptr = allocator::forth_allocate(size);
+        vm.push(ptr as Cell)?;
+        vm.push(if ptr.is_null() { Throw::ALLOCATE.code() } else { 0 })
+    }
+
+    /// `( a-addr -- ior )`
+    fn free(vm) {
+        let ptr = vm.pop()? as *mut u8;
+        let freed = allocator::forth_free(ptr);
+        vm.push(if freed { 0 } else { Throw::FREE.code() })
+    }
+
+    /// `( a-addr1 u -- a-addr2 ior )` On failure, `a-addr2` is `a-addr1`,
+    /// which is still allocated.
+    fn resize(vm) {
+        let size = vm.pop()? as usize;
+        let ptr = vm.pop()? as *mut u8;
+        let new_ptr = allocator::forth_resize(ptr, size);
+        if new_ptr.is_null() {
+            vm.push(ptr as Cell)?;
+            vm.push(Throw::RESIZE.code())
+        } else {
+            vm.push(new_ptr as Cell)?;
+            vm.push(0)
+        }
+    }
+
+    /// Show how much of the heap is in use, and how fragmented the rest is.
+    fn dot_heap(vm) {
+        let stats = allocator::heap_stats();
+        print!(
+            "heap {} used {} free {} largest free block {} ",
+            stats.size, stats.used, stats.free, stats.largest_free
+        );
+        Ok(())
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    ("ALLOCATE", NONE, allocate),
+    ("FREE", NONE, free),
+    ("RESIZE", NONE, resize),
+    (".HEAP", NONE, dot_heap),
+];
@@ -0,0 +1,195 @@
+//! Words for poking peripheral registers from the prompt. Addresses are
+//! offsets from `P_BASE`, and are checked against the device window that the
+//! early page tables map, so a typo throws an exception rather than taking a
+//! data abort.
+//!
+//! `MMIO@` and friends access 32-bit registers; the `MMIO-C` and `MMIO-W`
+//! words access 8- and 16-bit ones. `MMIO-SET` and `MMIO-CLR` are
+//! read-modify-write operations with a mask.
+
+use core::mem;
+
+use super::{
+    dictionary::{Entry, Flags},
+    words::NONE,
+    Cell, Throw, Vm,
+};
+use crate::rpi::{
+    mmio::{self, P_BASE},
+    mmu::{DEVICE_MEMORY_END, DEVICE_MEMORY_START},
+};
+
+/// A register width that the MMIO words can access.
+trait Register: Copy {
+    const SIZE: usize = mem::size_of::<Self>();
+
+    unsafe fn read(addr: usize) -> Self;
+    unsafe fn write(addr: usize, value: Self);
+    fn from_cell(value: Cell) -> Self;
+    fn into_cell(self) -> Cell;
+}
+
+macro_rules! register {
+    ($ty:ty, $read:path, $write:path) => {
+        impl Register for $ty {
+            #[inline]
+            unsafe fn read(addr: usize) -> $ty {
+                $read(addr)
+            }
+
+            #[inline]
+            unsafe fn write(addr: usize, value: $ty) {
+                $write(addr, value)
+            }
+
+            #[inline]
+            fn from_cell(value: Cell) -> $ty {
+                value as $ty
+            }
+
+            #[inline]
+            fn into_cell(self) -> Cell {
+                self as Cell
+            }
+        }
+    };
+}
+
+register!(u8, mmio::read_u8, mmio::write_u8);
+register!(u16, mmio::read_u16, mmio::write_u16);
+register!(u32, mmio::read, mmio::write);
+
+/// Turn an offset from `P_BASE` into the address of a `R` sized register,
+/// if the whole register is inside the device window and properly aligned.
+fn register_addr<R: Register>(offset: Cell) -> Result<usize, Throw> {
+    let addr = P_BASE.checked_add(offset as usize);
+    match addr {
+        Some(addr) if addr >= DEVICE_MEMORY_START && addr <= DEVICE_MEMORY_END - R::SIZE => {
+            if addr % R::SIZE != 0 {
+                Err(Throw::ADDRESS_ALIGNMENT)
+            } else {
+                Ok(addr)
+            }
+        }
+        _ => Err(Throw::INVALID_MEMORY_ADDRESS),
+    }
+}
+
+impl Vm {
+    /// `( offset -- x )`
+    fn mmio_fetch<R: Register>(&mut self) -> Result<(), Throw> {
+        let addr = register_addr::<R>(self.pop()?)?;
+        let value = unsafe { R::read(addr) };
+        self.push(value.into_cell())
+    }
+
+    /// `( x offset -- )`
+    fn mmio_store<R: Register>(&mut self) -> Result<(), Throw> {
+        let addr = register_addr::<R>(self.pop()?)?;
+        let value = R::from_cell(self.pop()?);
+        unsafe { R::write(addr, value) };
+        Ok(())
+    }
+
+    /// `( mask offset -- )`, replacing the register with `f(old, mask)`.
+    fn mmio_modify<R: Register>(&mut self, f: fn(Cell, Cell) -> Cell) -> Result<(), Throw> {
+        let addr = register_addr::<R>(self.pop()?)?;
+        let mask = self.pop()?;
+        unsafe {
+            let old = R::read(addr).into_cell();
+            R::write(addr, R::from_cell(f(old, mask)));
+        }
+        Ok(())
+    }
+}
+
+fn set(old: Cell, mask: Cell) -> Cell {
+    old | mask
+}
+
+fn clear(old: Cell, mask: Cell) -> Cell {
+    old & !mask
+}
+
+primitives! {
+    fn mmio_fetch(vm) {
+        vm.mmio_fetch::<u32>()
+    }
+
+    fn mmio_store(vm) {
+        vm.mmio_store::<u32>()
+    }
+
+    fn mmio_set(vm) {
+        vm.mmio_modify::<u32>(set)
+    }
+
+    fn mmio_clear(vm) {
+        vm.mmio_modify::<u32>(clear)
+    }
+
+    fn mmio_w_fetch(vm) {
+        vm.mmio_fetch::<u16>()
+    }
+
+    fn mmio_w_store(vm) {
+        vm.mmio_store::<u16>()
+    }
+
+    fn mmio_w_set(vm) {
+        vm.mmio_modify::<u16>(set)
+    }
+
+    fn mmio_w_clear(vm) {
+        vm.mmio_modify::<u16>(clear)
+    }
+
+    fn mmio_c_fetch(vm) {
+        vm.mmio_fetch::<u8>()
+    }
+
+    fn mmio_c_store(vm) {
+        vm.mmio_store::<u8>()
+    }
+
+    fn mmio_c_set(vm) {
+        vm.mmio_modify::<u8>(set)
+    }
+
+    fn mmio_c_clear(vm) {
+        vm.mmio_modify::<u8>(clear)
+    }
+
+    fn dmb(vm) {
+        mmio::dmb();
+        Ok(())
+    }
+
+    fn dsb(vm) {
+        mmio::dsb();
+        Ok(())
+    }
+
+    fn isb(vm) {
+        mmio::isb();
+        Ok(())
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    ("MMIO@", NONE, mmio_fetch),
+    ("MMIO!", NONE, mmio_store),
+    ("MMIO-SET", NONE, mmio_set),
+    ("MMIO-CLR", NONE, mmio_clear),
+    ("MMIO-W@", NONE, mmio_w_fetch),
+    ("MMIO-W!", NONE, mmio_w_store),
+    ("MMIO-WSET", NONE, mmio_w_set),
+    ("MMIO-WCLR", NONE, mmio_w_clear),
+    ("MMIO-C@", NONE, mmio_c_fetch),
+    ("MMIO-C!", NONE, mmio_c_store),
+    ("MMIO-CSET", NONE, mmio_c_set),
+    ("MMIO-CCLR", NONE, mmio_c_clear),
+    ("DMB", NONE, dmb),
+    ("DSB", NONE, dsb),
+    ("ISB", NONE, isb),
+];
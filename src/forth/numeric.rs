@@ -0,0 +1,258 @@
+//! Pictured numeric output, number conversion, and the words that work with
+//! double-cell numbers. A double cell is pushed low cell first, so the high
+//! cell is on top of the stack.
+
+use core::slice;
+
+use super::{
+    dictionary::{Entry, Flags},
+    interpreter::digit_value,
+    words::NONE,
+    Cell, Throw, Vm,
+};
+
+/// The size of the pictured numeric output buffer: enough for a double cell
+/// in binary, plus a sign and some text.
+pub const HOLD_SIZE: usize = 2 * 64 + 8;
+
+/// The buffer that `<#` ... `#>` builds a number in, from the right.
+pub struct Hold {
+    buffer: [u8; HOLD_SIZE],
+    start: usize,
+}
+
+impl Hold {
+    pub const fn new() -> Hold {
+        Hold {
+            buffer: [0; HOLD_SIZE],
+            start: HOLD_SIZE,
+        }
+    }
+
+    fn begin(&mut self) {
+        self.start = HOLD_SIZE;
+    }
+
+    fn hold(&mut self, byte: u8) -> Result<(), Throw> {
+        if self.start == 0 {
+            return Err(Throw::PICTURED_OUTPUT_OVERFLOW);
+        }
+        self.start -= 1;
+        self.buffer[self.start] = byte;
+        Ok(())
+    }
+
+    fn finish(&self) -> (usize, usize) {
+        let addr = self.buffer[self.start..].as_ptr() as usize;
+        (addr, HOLD_SIZE - self.start)
+    }
+}
+
+#[inline]
+fn to_double(low: Cell, high: Cell) -> i128 {
+    ((high as i128) << 64) | (low as usize as i128)
+}
+
+impl Vm {
+    pub fn pop_double(&mut self) -> Result<i128, Throw> {
+        let high = self.pop()?;
+        let low = self.pop()?;
+        Ok(to_double(low, high))
+    }
+
+    pub fn push_double(&mut self, value: i128) -> Result<(), Throw> {
+        self.push(value as Cell)?;
+        self.push((value >> 64) as Cell)
+    }
+
+    fn base(&self) -> Result<u128, Throw> {
+        match self.user.base {
+            2..=36 => Ok(self.user.base as u128),
+            _ => Err(Throw::INVALID_NUMERIC_ARGUMENT),
+        }
+    }
+
+    /// Divide the double on the stack by `BASE`, holding the remainder as a
+    /// digit.
+    fn hold_digit(&mut self) -> Result<(), Throw> {
+        let base = self.base()?;
+        let value = self.pop_double()? as u128;
+        let digit = (value % base) as u8;
+        self.user.hold.hold(if digit < 10 {
+            b'0' + digit
+        } else {
+            b'A' + digit - 10
+        })?;
+        self.push_double((value / base) as i128)
+    }
+}
+
+/// Quotient and remainder of `dividend / divisor`, rounded towards negative
+/// infinity if `floored`, and towards zero otherwise.
+fn divide(dividend: i128, divisor: Cell, floored: bool) -> Result<(Cell, Cell), Throw> {
+    if divisor == 0 {
+        return Err(Throw::DIVISION_BY_ZERO);
+    }
+    let divisor = divisor as i128;
+    let mut quotient = dividend.wrapping_div(divisor);
+    let mut remainder = dividend.wrapping_rem(divisor);
+    if floored && remainder != 0 && (remainder < 0) != (divisor < 0) {
+        quotient -= 1;
+        remainder += divisor;
+    }
+    if quotient < Cell::min_value() as i128 || quotient > Cell::max_value() as i128 {
+        return Err(Throw::RESULT_OUT_OF_RANGE);
+    }
+    Ok((quotient as Cell, remainder as Cell))
+}
+
+primitives! {
+    fn less_number_sign(vm) {
+        vm.user.hold.begin();
+        Ok(())
+    }
+
+    fn number_sign(vm) {
+        vm.hold_digit()
+    }
+
+    fn number_sign_s(vm) {
+        loop {
+            vm.hold_digit()?;
+            if vm.data.peek(0)? == 0 && vm.data.peek(1)? == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn number_sign_greater(vm) {
+        vm.pop_double()?;
+        let (addr, len) = vm.user.hold.finish();
+        vm.push(addr as Cell)?;
+        vm.push(len as Cell)
+    }
+
+    fn hold(vm) {
+        let byte = vm.pop()? as u8;
+        vm.user.hold.hold(byte)
+    }
+
+    fn sign(vm) {
+        if vm.pop()? < 0 {
+            vm.user.hold.hold(b'-')?;
+        }
+        Ok(())
+    }
+
+    fn to_number(vm) {
+        let len = vm.pop()? as usize;
+        let addr = vm.pop()? as usize;
+        let mut value = vm.pop_double()? as u128;
+        let base = vm.base()?;
+        let bytes = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        let mut used = 0;
+        for byte in bytes {
+            match digit_value(*byte) {
+                Some(digit) if (digit as u128) < base => {
+                    value = value.wrapping_mul(base).wrapping_add(digit as u128);
+                    used += 1;
+                }
+                _ => break,
+            }
+        }
+        vm.push_double(value as i128)?;
+        vm.push((addr + used) as Cell)?;
+        vm.push((len - used) as Cell)
+    }
+
+    fn s_to_d(vm) {
+        let value = vm.pop()?;
+        vm.push_double(value as i128)
+    }
+
+    fn m_star(vm) {
+        let b = vm.pop()? as i128;
+        let a = vm.pop()? as i128;
+        vm.push_double(a * b)
+    }
+
+    fn um_star(vm) {
+        let b = vm.pop()? as usize as u128;
+        let a = vm.pop()? as usize as u128;
+        vm.push_double((a * b) as i128)
+    }
+
+    fn um_slash_mod(vm) {
+        let divisor = vm.pop()? as usize as u128;
+        let dividend = vm.pop_double()? as u128;
+        if divisor == 0 {
+            return Err(Throw::DIVISION_BY_ZERO);
+        }
+        let quotient = dividend / divisor;
+        if quotient > usize::max_value() as u128 {
+            return Err(Throw::RESULT_OUT_OF_RANGE);
+        }
+        vm.push((dividend % divisor) as Cell)?;
+        vm.push(quotient as Cell)
+    }
+
+    fn fm_slash_mod(vm) {
+        let divisor = vm.pop()?;
+        let dividend = vm.pop_double()?;
+        let (quotient, remainder) = divide(dividend, divisor, true)?;
+        vm.push(remainder)?;
+        vm.push(quotient)
+    }
+
+    fn sm_slash_rem(vm) {
+        let divisor = vm.pop()?;
+        let dividend = vm.pop_double()?;
+        let (quotient, remainder) = divide(dividend, divisor, false)?;
+        vm.push(remainder)?;
+        vm.push(quotient)
+    }
+
+    fn slash_mod(vm) {
+        let divisor = vm.pop()?;
+        let dividend = vm.pop()? as i128;
+        let (quotient, remainder) = divide(dividend, divisor, false)?;
+        vm.push(remainder)?;
+        vm.push(quotient)
+    }
+
+    fn star_slash(vm) {
+        let divisor = vm.pop()?;
+        let b = vm.pop()? as i128;
+        let a = vm.pop()? as i128;
+        let (quotient, _) = divide(a * b, divisor, false)?;
+        vm.push(quotient)
+    }
+
+    fn star_slash_mod(vm) {
+        let divisor = vm.pop()?;
+        let b = vm.pop()? as i128;
+        let a = vm.pop()? as i128;
+        let (quotient, remainder) = divide(a * b, divisor, false)?;
+        vm.push(remainder)?;
+        vm.push(quotient)
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    ("<#", NONE, less_number_sign),
+    ("#", NONE, number_sign),
+    ("#S", NONE, number_sign_s),
+    ("#>", NONE, number_sign_greater),
+    ("HOLD", NONE, hold),
+    ("SIGN", NONE, sign),
+    (">NUMBER", NONE, to_number),
+    ("S>D", NONE, s_to_d),
+    ("M*", NONE, m_star),
+    ("UM*", NONE, um_star),
+    ("UM/MOD", NONE, um_slash_mod),
+    ("FM/MOD", NONE, fm_slash_mod),
+    ("SM/REM", NONE, sm_slash_rem),
+    ("/MOD", NONE, slash_mod),
+    ("*/", NONE, star_slash),
+    ("*/MOD", NONE, star_slash_mod),
+];
@@ -0,0 +1,46 @@
+//! Running machine code as a user program, at EL0 in an address space of its
+//! own, so that it can't touch the kernel or the dictionary (see
+//! `crate::process` for the system calls it can make). The code can come
+//! from the assembler:
+//!
+//! ```forth
+//! ASSEMBLER  ALIGN HERE
+//!     X0 42 MOV,  X8 0 MOV,  0 SVC,
+//! FORTH  HERE OVER - RUN-USER .
+//! ```
+//!
+//! While the program sleeps or waits for input, the other tasks run.
+
+use core::slice;
+
+use super::{
+    dictionary::{Entry, Flags},
+    words::NONE,
+    Cell, Throw, Vm,
+};
+use crate::process::{Process, Stop};
+
+primitives! {
+    /// `( addr u -- n )` Run the `u` bytes of code at `addr` as a user
+    /// program, and return the status it exits with. A fault in the program
+    /// is thrown.
+    fn run_user(vm) {
+        let len = vm.pop()? as usize;
+        let addr = vm.pop()? as usize;
+        let code = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        let mut process = Process::new(code).map_err(|_| Throw::ALLOCATE)?;
+        loop {
+            match process.run() {
+                Stop::Exited(status) => return vm.push(status as Cell),
+                Stop::Sleeping(duration) => vm.sleep(duration.as_millis() as u64),
+                Stop::Waiting => vm.pause(),
+                Stop::Faulted(fault) => {
+                    println!("User program faulted at {:#x}: {:?}", process.pc(), fault);
+                    return Err(Throw::from(fault));
+                }
+            }
+        }
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[("RUN-USER", NONE, run_user)];
@@ -0,0 +1,50 @@
+//! Run the Forth test suite in `forth/test` at boot instead of the console,
+//! and report the result by exiting QEMU through semihosting. Enabled by the
+//! `forth-tests` feature; `make test-rpi3` builds and runs it headless.
+
+use core::fmt::Write;
+
+use super::{Cell, Vm};
+use crate::rpi::uart::UART;
+
+/// The test files, in the order they are interpreted.
+const SUITE: &[(&str, &str)] = &[
+    ("tester.fr", include_str!("../../forth/test/tester.fr")),
+    ("core.fr", include_str!("../../forth/test/core.fr")),
+];
+
+/// The number of failed tests, as counted by `tester.fr`.
+fn error_count(vm: &Vm) -> Cell {
+    vm.dict
+        .find("#ERRORS")
+        .map(|header| unsafe { *(header.param() as *const Cell) })
+        .unwrap_or(0)
+}
+
+pub fn run() -> ! {
+    let mut vm = Vm::new();
+    let mut out = crate::console::output_prefer_semihosting();
+    let mut failures = 0;
+    for (file, source) in SUITE.iter() {
+        for (index, line) in source.lines().enumerate() {
+            let errors = error_count(&vm);
+            let result = vm.evaluate(line.as_ptr() as usize, line.len());
+            if let Err(throw) = result {
+                let _ = write!(out, "{}:{}: ", file, index + 1);
+                vm.recover(throw, &mut out);
+                failures += 1;
+            } else if error_count(&vm) != errors {
+                let _ = writeln!(out, "{}:{}: test failed: {}", file, index + 1, line);
+            }
+        }
+    }
+    failures += error_count(&vm);
+    UART::new().flush();
+    if failures == 0 {
+        let _ = writeln!(out, "Forth test suite passed");
+        qemu_exit::aarch64::exit_success()
+    } else {
+        let _ = writeln!(out, "Forth test suite failed: {} errors", failures);
+        qemu_exit::aarch64::exit_failure()
+    }
+}
@@ -0,0 +1,98 @@
+use alloc::{boxed::Box, vec};
+
+use super::{Cell, Throw};
+
+/// A fixed-size stack of cells. The storage comes from the kernel heap, and
+/// every access is bounds checked so that a runaway program raises a Forth
+/// exception instead of scribbling over whatever is next to the stack.
+pub struct Stack {
+    cells: Box<[Cell]>,
+    depth: usize,
+    overflow: Throw,
+    underflow: Throw,
+}
+
+impl Stack {
+    pub fn new(size: usize, overflow: Throw, underflow: Throw) -> Stack {
+        Stack {
+            cells: vec![0; size].into_boxed_slice(),
+            depth: 0,
+            overflow,
+            underflow,
+        }
+    }
+
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cells.len()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.depth = 0;
+    }
+
+    /// Force the stack to be `depth` cells deep. Used to restore the stacks
+    /// after an exception; if the stack has to grow, the new items are
+    /// whatever was left in memory.
+    #[inline]
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth.min(self.cells.len());
+    }
+
+    #[inline]
+    pub fn push(&mut self, value: Cell) -> Result<(), Throw> {
+        if self.depth >= self.cells.len() {
+            return Err(self.overflow);
+        }
+        self.cells[self.depth] = value;
+        self.depth += 1;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Result<Cell, Throw> {
+        if self.depth == 0 {
+            return Err(self.underflow);
+        }
+        self.depth -= 1;
+        Ok(self.cells[self.depth])
+    }
+
+    /// Read the item `index` cells below the top of the stack, where `0` is the
+    /// top item.
+    #[inline]
+    pub fn peek(&self, index: usize) -> Result<Cell, Throw> {
+        if index >= self.depth {
+            return Err(self.underflow);
+        }
+        Ok(self.cells[self.depth - 1 - index])
+    }
+
+    #[inline]
+    pub fn peek_mut(&mut self, index: usize) -> Result<&mut Cell, Throw> {
+        if index >= self.depth {
+            return Err(self.underflow);
+        }
+        Ok(&mut self.cells[self.depth - 1 - index])
+    }
+
+    /// Check that there are at least `count` items on the stack.
+    #[inline]
+    pub fn require(&self, count: usize) -> Result<(), Throw> {
+        if self.depth < count {
+            return Err(self.underflow);
+        }
+        Ok(())
+    }
+
+    /// The items on the stack, from bottom to top.
+    pub fn as_slice(&self) -> &[Cell] {
+        &self.cells[..self.depth]
+    }
+}
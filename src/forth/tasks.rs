@@ -0,0 +1,382 @@
+//! A round-robin, cooperative multitasker in the classic Forth style.
+//!
+//! Every task has a native stack for the code it runs, and data, return and
+//! float stacks and a user area of its own. The user area holds the task's
+//! variables, its input buffers, its pictured numeric output and the
+//! definition it's compiling. All tasks share one `Vm`: the state of the
+//! running task lives in the `Vm` itself, and the others' is kept in their
+//! `Task`. `PAUSE` swaps the state of the next task that's ready into
+//! the `Vm` and switches to its native stack (see `switch_context` in
+//! `context.S`). Nothing is preempted, so a task runs until it calls `PAUSE`,
+//! `SLEEP` or `STOP`. The console is task zero, and pauses while it waits
+//! for keys.
+//!
+//! ```forth
+//! TASK: TICKER
+//! : TICK  TICKER ACTIVATE  BEGIN  ." tick "  1000 SLEEP  AGAIN ;
+//! ```
+//!
+//! `ACTIVATE` returns from the definition it's used in, and the rest of the
+//! definition runs in the task, which stops when it reaches the end.
+//! Sleeping is timed by the millisecond tick of the system timer.
+//!
+//! Every task reaches the `Vm` through the same raw pointer, which
+//! `activate` gives `task_main`, and the console through its own `&mut Vm`.
+//! A task that pauses may still hold borrows of parts of the `Vm`, which the
+//! other tasks then change through theirs. That's sound because of what
+//! those borrows can be:
+//!
+//! - The running task's user area and stacks, passed to `Tasks::pause`.
+//!   They're swapped out before the switch and back in before it returns,
+//!   so the task finds them as it left them.
+//! - The line editor, which `ACCEPT` lends to `Editor::read_line`. Only the
+//!   console may use it, so no other task touches it meanwhile.
+//!
+//! Anything else, such as the dictionary, has to be reached afresh after
+//! `pause` returns, rather than through a borrow held across it.
+
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::mem;
+
+use super::{
+    dictionary::{Entry, Flags},
+    stack::Stack,
+    words::{COMPILER, NONE},
+    Cell, Throw, User, Vm, FALSE,
+};
+use crate::{executor, interrupts::faults::FaultFrames, rpi::timer::ticks};
+
+/// The size in bytes of each task's native stack.
+const NATIVE_STACK_SIZE: usize = 64 * 1024;
+
+/// The depth of each task's data and return stacks.
+const TASK_STACK_CELLS: usize = 256;
+
+/// The callee-saved registers of a task that isn't running. The layout is
+/// shared with `context.S`.
+#[repr(C)]
+struct Context {
+    /// `x19`-`x30`, then `sp`.
+    regs: [usize; 13],
+    /// `d8`-`d15`.
+    fp_regs: [u64; 8],
+}
+
+extern "C" {
+    fn switch_context(save: *mut Context, load: *const Context);
+    fn task_start();
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Status {
+    Ready,
+    /// Waiting for `ticks()` to reach the given value.
+    Sleeping(u64),
+    Stopped,
+}
+
+struct Task {
+    name: String,
+    /// The data field of the word made by `TASK:`, which is how Forth
+    /// programs refer to the task. Zero for the console.
+    addr: usize,
+    status: Status,
+    /// The code the task runs when it starts, as passed to `ACTIVATE`.
+    entry: usize,
+    context: Context,
+    native_stack: Box<[u128]>,
+    user: User,
+    data: Stack,
+    ret: Stack,
+    floats: Stack,
+    faults: FaultFrames,
+}
+
+impl Task {
+    fn new(name: String, addr: usize, stack_cells: usize, native_stack_size: usize) -> Task {
+        Task {
+            name,
+            addr,
+            status: Status::Stopped,
+            entry: 0,
+            context: Context {
+                regs: [0; 13],
+                fp_regs: [0; 8],
+            },
+            native_stack: vec![0; native_stack_size / mem::size_of::<u128>()].into_boxed_slice(),
+            user: User::new(10),
+            data: Stack::new(stack_cells, Throw::STACK_OVERFLOW, Throw::STACK_UNDERFLOW),
+            ret: Stack::new(
+                stack_cells,
+                Throw::RETURN_STACK_OVERFLOW,
+                Throw::RETURN_STACK_UNDERFLOW,
+            ),
+            floats: Stack::new(
+                stack_cells,
+                Throw::FLOATING_STACK_OVERFLOW,
+                Throw::FLOATING_STACK_UNDERFLOW,
+            ),
+            faults: FaultFrames::new(),
+        }
+    }
+}
+
+pub struct Tasks {
+    tasks: Vec<Task>,
+    /// The index of the running task.
+    current: usize,
+}
+
+impl Tasks {
+    pub fn new() -> Tasks {
+        // The console runs on the boot stack, and its state starts out in
+        // the `Vm`, so it needs neither.
+        let mut console = Task::new(String::from("console"), 0, 0, 0);
+        console.status = Status::Ready;
+        Tasks {
+            tasks: vec![console],
+            current: 0,
+        }
+    }
+
+    fn find(&self, addr: usize) -> Result<usize, Throw> {
+        self.tasks
+            .iter()
+            .position(|task| task.addr == addr && addr != 0)
+            .ok_or(Throw::INVALID_TASK)
+    }
+
+    /// Keep the console, the running task, and the other tasks that `keep`
+    /// accepts, following the running one to where it ends up.
+    fn retain(&mut self, mut keep: impl FnMut(&Task) -> bool) {
+        let running = self.current;
+        let mut index = 0;
+        let mut current = 0;
+        self.tasks.retain(|task| {
+            let kept = index == 0 || index == running || keep(task);
+            if kept && index < running {
+                current += 1;
+            }
+            index += 1;
+            kept
+        });
+        self.current = current;
+    }
+
+    /// Drop the tasks whose words were at or above `here`, now that the
+    /// dictionary has been cut back to it, so that a word defined there
+    /// later can't be taken for one of them. The running task's stack is in
+    /// use, so it's stopped and can't be found, and `pause` drops it once
+    /// it's been switched away from.
+    fn forget_above(&mut self, here: usize) {
+        self.retain(|task| task.addr != 0 && task.addr < here);
+        let task = &mut self.tasks[self.current];
+        if task.addr >= here {
+            task.addr = 0;
+            task.status = Status::Stopped;
+        }
+    }
+
+    /// Whether the running task is the console.
+    pub fn is_console(&self) -> bool {
+        self.current == 0
+    }
+
+    #[inline]
+    fn set_status(&mut self, status: Status) {
+        let task = &mut self.tasks[self.current];
+        // A forgotten task stays stopped.
+        if self.current == 0 || task.addr != 0 {
+            task.status = status;
+        }
+    }
+
+    /// The next task after the running one that's ready to run, waking it
+    /// up if it has slept long enough. The running task is tried last.
+    fn next_ready(&mut self) -> Option<usize> {
+        let now = ticks();
+        let count = self.tasks.len();
+        for step in 1..=count {
+            let index = (self.current + step) % count;
+            let task = &mut self.tasks[index];
+            if let Status::Sleeping(until) = task.status {
+                if until <= now {
+                    task.status = Status::Ready;
+                }
+            }
+            if task.status == Status::Ready {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    /// Let the other tasks run until the running one is ready again.
+    /// `user` and the stacks are the running task's state, from the `Vm`.
+    pub fn pause(
+        &mut self,
+        user: &mut User,
+        data: &mut Stack,
+        ret: &mut Stack,
+        floats: &mut Stack,
+    ) {
+        // Drop any task that `forget_above` stopped while it was running.
+        self.retain(|task| task.addr != 0);
+        let from = self.current;
+        let to = loop {
+            // The kernel's own tasks run whenever a Forth one pauses.
+            executor::run_ready();
+            match self.next_ready() {
+                Some(to) => break to,
+                // Everything is asleep, so wait for the next tick.
+                None => unsafe { asm!("wfi" :::: "volatile") },
+            }
+        };
+        if to == from {
+            return;
+        }
+        // Put this task's state away, then bring in the next one's.
+        for index in [from, to].iter() {
+            let task = &mut self.tasks[*index];
+            mem::swap(user, &mut task.user);
+            mem::swap(data, &mut task.data);
+            mem::swap(ret, &mut task.ret);
+            mem::swap(floats, &mut task.floats);
+            task.faults.swap();
+        }
+        self.current = to;
+        let save = &mut self.tasks[from].context as *mut Context;
+        let load = &self.tasks[to].context as *const Context;
+        unsafe { switch_context(save, load) };
+    }
+}
+
+/// The first thing a task runs, on its own stack. `vm` is the pointer that
+/// `activate` left, which the other tasks share (see the module docs).
+extern "C" fn task_main(vm: &mut Vm) -> ! {
+    let current = vm.tasks.current;
+    let entry: Entry = unsafe { mem::transmute(vm.tasks.tasks[current].entry) };
+    if let Err(throw) = vm.call(entry) {
+        println!("{} ? {}", vm.tasks.tasks[current].name, throw);
+    }
+    vm.tasks.set_status(Status::Stopped);
+    vm.pause();
+    unreachable!("A stopped task was resumed");
+}
+
+/// Start the task on the data stack running `entry`, the code after
+/// `ACTIVATE`.
+pub(super) extern "C" fn activate_runtime(vm: &mut Vm, entry: usize) -> isize {
+    Throw::into_code(vm.activate(entry))
+}
+
+impl Vm {
+    /// Let the other tasks run for at least `ms` milliseconds.
+    pub fn sleep(&mut self, ms: u64) {
+        self.tasks.set_status(Status::Sleeping(ticks() + ms));
+        self.pause();
+    }
+
+    pub fn pause(&mut self) {
+        let Vm {
+            tasks,
+            user,
+            data,
+            ret,
+            floats,
+            ..
+        } = self;
+        tasks.pause(user, data, ret, floats);
+    }
+
+    /// Forget the tasks defined at or above `addr`, after the dictionary has
+    /// been cut back to it.
+    pub(super) fn forget_tasks(&mut self, addr: usize) {
+        self.tasks.forget_above(addr);
+    }
+
+    fn activate(&mut self, entry: usize) -> Result<(), Throw> {
+        let addr = self.pop()? as usize;
+        let index = self.tasks.find(addr)?;
+        if index == self.tasks.current {
+            // Its stack is in use.
+            return Err(Throw::INVALID_TASK);
+        }
+        let vm = self as *mut Vm as usize;
+        let base = self.user.base;
+        let task = &mut self.tasks.tasks[index];
+        // Anything the task was doing before is abandoned.
+        task.entry = entry;
+        task.status = Status::Ready;
+        task.user = User::new(base);
+        task.data.clear();
+        task.ret.clear();
+        task.floats.clear();
+        task.faults = FaultFrames::new();
+        let stack_top =
+            task.native_stack.as_ptr() as usize + task.native_stack.len() * mem::size_of::<u128>();
+        // `switch_context` returns into `task_start`, which calls `task_main`
+        // with the `Vm`. A zero frame pointer ends backtraces.
+        let mut regs = [0; 13];
+        regs[0] = vm;
+        regs[1] = task_main as usize;
+        regs[10] = 0;
+        regs[11] = task_start as usize;
+        regs[12] = stack_top;
+        task.context = Context {
+            regs,
+            fp_regs: [0; 8],
+        };
+        Ok(())
+    }
+}
+
+primitives! {
+    fn pause(vm) {
+        vm.pause();
+        Ok(())
+    }
+
+    /// `( "name" -- )` Define a task. The word pushes the task's address
+    /// for `ACTIVATE`.
+    fn task_colon(vm) {
+        let name = vm.parse_new_name()?;
+        vm.create(&name)?;
+        let addr = vm.dict.here();
+        vm.dict.comma(vm.tasks.tasks.len() as Cell)?;
+        let task = Task::new(name, addr, TASK_STACK_CELLS, NATIVE_STACK_SIZE);
+        vm.tasks.tasks.push(task);
+        Ok(())
+    }
+
+    /// `( task -- )` Return from the current definition, and run the rest of
+    /// it in `task`.
+    fn activate(vm) {
+        vm.begin_detached_code(activate_runtime as usize)
+    }
+
+    /// Stop the running task for good, or until it's activated again.
+    fn stop(vm) {
+        if vm.tasks.current == 0 {
+            return Err(Throw::INVALID_TASK);
+        }
+        vm.tasks.set_status(Status::Stopped);
+        vm.pause();
+        Ok(())
+    }
+
+    /// `( ms -- )` Let the other tasks run for at least `ms` milliseconds.
+    fn sleep(vm) {
+        let ms = vm.pop()?.max(0) as u64;
+        vm.sleep(ms);
+        Ok(())
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[
+    ("PAUSE", NONE, pause),
+    ("TASK:", NONE, task_colon),
+    ("ACTIVATE", COMPILER, activate),
+    ("STOP", NONE, stop),
+    ("SLEEP", NONE, sleep),
+];
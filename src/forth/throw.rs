@@ -0,0 +1,163 @@
+use core::fmt;
+
+use crate::interrupts::faults::Fault;
+
+/// A Forth exception code, as passed to `THROW`. Negative values are reserved
+/// by the standard; positive values are free for programs to use.
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Throw(pub isize);
+
+impl Throw {
+    pub const ABORT: Throw = Throw(-1);
+    pub const ABORT_QUOTE: Throw = Throw(-2);
+    pub const STACK_OVERFLOW: Throw = Throw(-3);
+    pub const STACK_UNDERFLOW: Throw = Throw(-4);
+    pub const RETURN_STACK_OVERFLOW: Throw = Throw(-5);
+    pub const RETURN_STACK_UNDERFLOW: Throw = Throw(-6);
+    pub const DICTIONARY_OVERFLOW: Throw = Throw(-8);
+    pub const INVALID_MEMORY_ADDRESS: Throw = Throw(-9);
+    pub const DIVISION_BY_ZERO: Throw = Throw(-10);
+    pub const RESULT_OUT_OF_RANGE: Throw = Throw(-11);
+    pub const UNDEFINED_WORD: Throw = Throw(-13);
+    pub const COMPILE_ONLY: Throw = Throw(-14);
+    pub const ZERO_LENGTH_NAME: Throw = Throw(-16);
+    pub const PICTURED_OUTPUT_OVERFLOW: Throw = Throw(-17);
+    pub const PARSED_STRING_OVERFLOW: Throw = Throw(-18);
+    pub const NAME_TOO_LONG: Throw = Throw(-19);
+    pub const CONTROL_MISMATCH: Throw = Throw(-22);
+    pub const ADDRESS_ALIGNMENT: Throw = Throw(-23);
+    pub const INVALID_NUMERIC_ARGUMENT: Throw = Throw(-24);
+    pub const LOOP_PARAMETERS_UNAVAILABLE: Throw = Throw(-26);
+    pub const NESTED_COMPILATION: Throw = Throw(-29);
+    pub const NOT_CREATED: Throw = Throw(-31);
+    pub const BLOCK_READ: Throw = Throw(-33);
+    pub const BLOCK_WRITE: Throw = Throw(-34);
+    pub const INVALID_BLOCK_NUMBER: Throw = Throw(-35);
+    pub const FILE_IO: Throw = Throw(-37);
+    pub const NON_EXISTENT_FILE: Throw = Throw(-38);
+    pub const FLOATING_STACK_OVERFLOW: Throw = Throw(-44);
+    pub const FLOATING_STACK_UNDERFLOW: Throw = Throw(-45);
+    pub const FLOATING_INVALID_ARGUMENT: Throw = Throw(-46);
+    pub const QUIT: Throw = Throw(-56);
+    pub const ALLOCATE: Throw = Throw(-59);
+    pub const FREE: Throw = Throw(-60);
+    pub const RESIZE: Throw = Throw(-61);
+
+    // System-specific codes.
+    pub const BRANCH_OUT_OF_RANGE: Throw = Throw(-256);
+    pub const INVALID_IMAGE: Throw = Throw(-257);
+    pub const STALE_IMAGE: Throw = Throw(-258);
+    /// Thrown by `LOAD-IMAGE` to unwind to the outer interpreter, which then
+    /// replaces the dictionary.
+    pub const LOAD_IMAGE: Throw = Throw(-259);
+    pub const INVALID_OPERAND: Throw = Throw(-260);
+    pub const BREAKPOINT: Throw = Throw(-261);
+    pub const INVALID_TASK: Throw = Throw(-262);
+    pub const INCLUDE_NESTING: Throw = Throw(-263);
+    pub const UNEXPECTED_EXCEPTION: Throw = Throw(-264);
+    pub const INVALID_NAME: Throw = Throw(-265);
+
+    pub fn code(self) -> isize {
+        self.0
+    }
+
+    /// Convert the result of a primitive into the code returned from native
+    /// code, where zero means no exception.
+    #[inline]
+    pub fn into_code(result: Result<(), Throw>) -> isize {
+        match result {
+            Ok(()) => 0,
+            Err(Throw(code)) => code,
+        }
+    }
+
+    #[inline]
+    pub fn from_code(code: isize) -> Result<(), Throw> {
+        if code == 0 {
+            Ok(())
+        } else {
+            Err(Throw(code))
+        }
+    }
+
+    pub fn description(self) -> Option<&'static str> {
+        let desc = match self.0 {
+            -1 => "aborted",
+            -2 => "aborted",
+            -3 => "stack overflow",
+            -4 => "stack underflow",
+            -5 => "return stack overflow",
+            -6 => "return stack underflow",
+            -8 => "dictionary overflow",
+            -9 => "invalid memory address",
+            -10 => "division by zero",
+            -11 => "result out of range",
+            -13 => "undefined word",
+            -14 => "interpreting a compile-only word",
+            -16 => "attempt to use zero-length string as a name",
+            -17 => "pictured numeric output string overflow",
+            -18 => "parsed string overflow",
+            -19 => "definition name too long",
+            -22 => "control structure mismatch",
+            -23 => "address alignment exception",
+            -24 => "invalid numeric argument",
+            -26 => "loop parameters unavailable",
+            -29 => "compiler nesting",
+            -31 => ">BODY used on non-CREATEd definition",
+            -33 => "block read exception",
+            -34 => "block write exception",
+            -35 => "invalid block number",
+            -37 => "file I/O exception",
+            -38 => "non-existent file",
+            -44 => "floating-point stack overflow",
+            -45 => "floating-point stack underflow",
+            -46 => "floating-point invalid argument",
+            -56 => "QUIT",
+            -59 => "ALLOCATE",
+            -60 => "FREE",
+            -61 => "RESIZE",
+            -256 => "branch out of range",
+            -257 => "not a Forth image, or an unsupported version",
+            -258 => "image was saved by a different kernel build",
+            -259 => "image loaded",
+            -260 => "invalid assembler operand",
+            -261 => "breakpoint",
+            -262 => "invalid task",
+            -263 => "files included too deeply",
+            -264 => "unexpected exception",
+            -265 => "name isn't valid UTF-8",
+            _ => return None,
+        };
+        Some(desc)
+    }
+}
+
+impl From<Fault> for Throw {
+    fn from(fault: Fault) -> Throw {
+        match fault {
+            Fault::Abort { .. } => Throw::INVALID_MEMORY_ADDRESS,
+            Fault::Alignment { .. } => Throw::ADDRESS_ALIGNMENT,
+            Fault::Breakpoint { .. } => Throw::BREAKPOINT,
+            Fault::Unexpected { .. } => Throw::UNEXPECTED_EXCEPTION,
+        }
+    }
+}
+
+impl fmt::Debug for Throw {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.description() {
+            Some(desc) => write!(f, "Throw({}, {:?})", self.0, desc),
+            None => write!(f, "Throw({})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Throw {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.description() {
+            Some(desc) => write!(f, "{}", desc),
+            None => write!(f, "exception #{}", self.0),
+        }
+    }
+}
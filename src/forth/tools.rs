@@ -0,0 +1,485 @@
+//! Words for looking inside the system: `SEE` turns a word's code back into
+//! something close to its source, and `DUMP` shows memory in hex and as
+//! characters.
+//!
+//! `SEE` recognizes the sequences that `Vm::compile_call` and friends lay
+//! down (see `codegen`), and prints one item per line, numbered by its byte
+//! offset from the word's entry point. Branches show the offset they go to.
+//! Anything else, such as the body of a `CODE` word, is shown as raw
+//! instructions.
+
+use core::{
+    fmt::{self, Write},
+    mem,
+};
+
+use super::{
+    codegen::{self, INSTR_SIZE, X0, X1, X16},
+    compiler::{compile_call_runtime, create_runtime, does_runtime, literal_runtime},
+    control,
+    dictionary::{Dictionary, Entry, Flags, Header, Xt},
+    float::{fliteral_runtime, float_literal},
+    tasks::activate_runtime,
+    words::{abort_quote_runtime, format_number, s_quote_runtime, NONE},
+    Cell, Throw, Vm,
+};
+use crate::rpi::mmu::{self, PAGE_SIZE};
+
+const CELL: usize = mem::size_of::<Cell>();
+
+/// The number of bytes `DUMP` shows on each line.
+const DUMP_LINE: usize = 16;
+
+/// One piece of compiled code.
+enum Item {
+    /// A call to native code, with the argument it passes in `x1` if any.
+    Call {
+        target: usize,
+        arg: Option<usize>,
+    },
+    /// A call to a routine that decides whether to branch to `dest`.
+    Branch {
+        runtime: usize,
+        dest: usize,
+    },
+    Jump(usize),
+    /// A string compiled into the definition and passed to `runtime`.
+    String {
+        runtime: usize,
+        addr: usize,
+        len: usize,
+    },
+    /// A return from the definition.
+    Exit,
+    /// An instruction that isn't part of anything above.
+    Raw(u32),
+}
+
+/// Reads back the code of a definition, starting at `pc`.
+struct Decoder {
+    pc: usize,
+    /// The definition's shared exit sequence.
+    exit: usize,
+    /// Where the definition's code must end.
+    limit: usize,
+}
+
+impl Decoder {
+    #[inline]
+    fn addr(&self, index: usize) -> usize {
+        self.pc + index * INSTR_SIZE
+    }
+
+    /// The instruction `index` instructions after `pc`.
+    fn instr(&self, index: usize) -> Option<u32> {
+        let addr = self.addr(index);
+        if addr + INSTR_SIZE > self.limit {
+            return None;
+        }
+        Some(unsafe { (addr as *const u32).read() })
+    }
+
+    /// Whether the instruction at `index` is `expected`, if there is one.
+    #[inline]
+    fn is(&self, index: usize, expected: Option<u32>) -> bool {
+        expected.is_some() && self.instr(index) == expected
+    }
+
+    fn starts_with(&self, instrs: &[u32]) -> bool {
+        (0..instrs.len()).all(|index| self.is(index, Some(instrs[index])))
+    }
+
+    /// The value loaded into `rd` by a `codegen::load_constant` at `index`.
+    fn constant(&self, index: usize, rd: u32) -> Option<usize> {
+        let instrs = [
+            self.instr(index)?,
+            self.instr(index + 1)?,
+            self.instr(index + 2)?,
+            self.instr(index + 3)?,
+        ];
+        match codegen::decode_constant(&instrs) {
+            Some((reg, value)) if reg == rd => Some(value as usize),
+            _ => None,
+        }
+    }
+
+    /// Whether `index` starts a `movz x0, #0; b exit`.
+    fn is_exit(&self, index: usize) -> bool {
+        self.is(index, Some(codegen::movz(X0, 0, 0)))
+            && self.is(index + 1, codegen::b(self.addr(index + 1), self.exit))
+    }
+
+    /// Decode the setup and call at `index` that `Vm::compile_native_call`
+    /// or `Vm::compile_native_call_with` lays down, without the check after
+    /// it. Returns the target, the argument and the number of instructions.
+    fn call(&self, index: usize) -> Option<(usize, Option<usize>, usize)> {
+        if self.instr(index)? != codegen::MOV_X0_VM {
+            return None;
+        }
+        let mut len = 1;
+        let arg = self.constant(index + len, X1);
+        if arg.is_some() {
+            len += 4;
+        }
+        let target = match self.constant(index + len, X16) {
+            Some(target) => {
+                if self.instr(index + len + 4)? != codegen::blr(X16) {
+                    return None;
+                }
+                len += 5;
+                target
+            }
+            None => {
+                let instr = self.instr(index + len)?;
+                if instr & 0xFC00_0000 != 0x9400_0000 {
+                    return None;
+                }
+                let target = codegen::decode_branch(self.addr(index + len), instr)?;
+                len += 1;
+                target
+            }
+        };
+        Some((target, arg, len))
+    }
+
+    /// Decode the item at `pc`, returning it and its length in instructions.
+    fn decode(&self) -> Option<(Item, usize)> {
+        let first = self.instr(0)?;
+        if let Some((target, arg, len)) = self.call(0) {
+            if self.is(len, codegen::cbnz(X0, self.addr(len), self.exit)) {
+                return Some((Item::Call { target, arg }, len + 1));
+            }
+            let branch = self.instr(len + 1);
+            if self.is(len, codegen::tbnz_negative(X0, self.addr(len), self.exit))
+                && branch.map_or(false, |instr| instr & 0xFF00_001F == 0xB500_0000)
+            {
+                let dest = codegen::decode_conditional(self.addr(len + 1), branch.unwrap())?;
+                let item = Item::Branch {
+                    runtime: target,
+                    dest,
+                };
+                return Some((item, len + 2));
+            }
+        }
+        if self.is_exit(0) {
+            return Some((Item::Exit, 2));
+        }
+        if first & 0xFC00_0000 == 0x1400_0000 {
+            let dest = codegen::decode_branch(self.pc, first)?;
+            return self.string(dest).or(Some((Item::Jump(dest), 1)));
+        }
+        Some((Item::Raw(first), 1))
+    }
+
+    /// Decode a string laid down by `Vm::compile_string`, which starts with
+    /// a branch over it to `dest`, together with the call it's passed to.
+    fn string(&self, dest: usize) -> Option<(Item, usize)> {
+        let len_addr = (self.addr(1) + CELL - 1) & !(CELL - 1);
+        let addr = len_addr + CELL;
+        if dest < addr || dest >= self.limit {
+            return None;
+        }
+        let len = unsafe { (len_addr as *const usize).read() };
+        if len > dest - addr || (addr + len + INSTR_SIZE - 1) & !(INSTR_SIZE - 1) != dest {
+            return None;
+        }
+        let skip = (dest - self.pc) / INSTR_SIZE;
+        let (runtime, arg, call_len) = self.call(skip)?;
+        let check = skip + call_len;
+        if arg != Some(addr) || !self.is(check, codegen::cbnz(X0, self.addr(check), self.exit)) {
+            return None;
+        }
+        Some((Item::String { runtime, addr, len }, check + 1))
+    }
+}
+
+/// Where the code starting at `addr` must end: at the next header after it,
+/// or at `HERE`.
+fn code_limit(dict: &Dictionary, addr: usize) -> usize {
+    // Hidden words are still on the chain, so this sees every header.
+    let mut limit = dict.here();
+    let mut next = dict.latest();
+    while let Some(header) = next {
+        let at = header as *const Header as usize;
+        if at > addr {
+            limit = limit.min(at);
+        }
+        next = header.link();
+    }
+    limit
+}
+
+impl Vm {
+    /// The name of the word whose code is at `code`.
+    fn name_of(&self, code: usize) -> Option<&str> {
+        self.dict
+            .iter()
+            .find(|header| header.code() == code)
+            .map(Header::name)
+    }
+
+    fn write_call(&self, out: &mut impl Write, target: usize, arg: Option<usize>) -> fmt::Result {
+        match (self.name_of(target), arg) {
+            (Some(name), None) => write!(out, "{}", name),
+            (Some(name), Some(arg)) => write!(out, "{} ( x1 = {:#x} )", name, arg),
+            (None, None) => write!(out, "CALL {:#x}", target),
+            (None, Some(arg)) => write!(out, "CALL {:#x} ( x1 = {:#x} )", target, arg),
+        }
+    }
+
+    /// Show the body of the colon definition or `DOES>` code whose prologue
+    /// is at `code`, up to the end of the definition.
+    fn decompile(&self, out: &mut impl Write, code: usize) -> fmt::Result {
+        let entry = code + codegen::PROLOGUE.len() * INSTR_SIZE;
+        let mut decoder = Decoder {
+            pc: entry,
+            exit: code - codegen::EPILOGUE.len() * INSTR_SIZE,
+            limit: code_limit(&self.dict, code),
+        };
+        // The furthest forward branch so far. An exit before it is an `EXIT`,
+        // and the first one after it ends the definition.
+        let mut furthest = entry;
+        let mut buf = [0; 72];
+        while let Some((item, len)) = decoder.decode() {
+            let offset = decoder.pc - entry;
+            let at_end = decoder.pc >= furthest;
+            decoder.pc += len * INSTR_SIZE;
+            match item {
+                // `DOES>` code starts by pushing the data field address that
+                // the stub passes it.
+                Item::Call { target, arg: None } if target == literal_runtime as usize => {
+                    continue;
+                }
+                _ => write!(out, "{:>5} ", offset)?,
+            }
+            match item {
+                Item::Call {
+                    target,
+                    arg: Some(value),
+                } if target == literal_runtime as usize => {
+                    write!(
+                        out,
+                        "{}",
+                        format_number(value as Cell, self.user.base, &mut buf)
+                    )?;
+                }
+                Item::Call {
+                    target,
+                    arg: Some(bits),
+                } if target == fliteral_runtime as usize => {
+                    write!(out, "{}", float_literal(f64::from_bits(bits as u64)))?;
+                }
+                Item::Call {
+                    target,
+                    arg: Some(xt),
+                } if target == compile_call_runtime as usize => {
+                    let name = unsafe { (*(xt as Xt)).name() };
+                    write!(out, "POSTPONE {}", name)?;
+                }
+                Item::Call {
+                    target,
+                    arg: Some(does),
+                } if target == does_runtime as usize || target == activate_runtime as usize => {
+                    // The rest of the defining word is skipped, and the code
+                    // that the defined words or the task run starts with a
+                    // prologue of its own.
+                    if target == does_runtime as usize {
+                        write!(out, "DOES>")?;
+                    } else {
+                        write!(out, "ACTIVATE")?;
+                    }
+                    decoder.pc = does + codegen::PROLOGUE.len() * INSTR_SIZE;
+                    decoder.exit = does - codegen::EPILOGUE.len() * INSTR_SIZE;
+                    furthest = decoder.pc;
+                }
+                Item::Call { target, arg } => match control::runtime_name(target) {
+                    Some(name) => write!(out, "{}", name)?,
+                    None => self.write_call(out, target, arg)?,
+                },
+                Item::Branch { runtime, dest } => {
+                    match control::runtime_name(runtime) {
+                        Some(name) => write!(out, "{}", name)?,
+                        None => self.write_call(out, runtime, None)?,
+                    }
+                    write!(out, " -> {}", dest.wrapping_sub(entry) as isize)?;
+                    furthest = furthest.max(dest);
+                }
+                Item::Jump(dest) => {
+                    write!(out, "BRANCH -> {}", dest.wrapping_sub(entry) as isize)?;
+                    furthest = furthest.max(dest);
+                }
+                Item::String { runtime, addr, len } => {
+                    if runtime == s_quote_runtime as usize {
+                        write!(out, "S\" ")?;
+                    } else if runtime == abort_quote_runtime as usize {
+                        write!(out, "ABORT\" ")?;
+                    } else {
+                        self.write_call(out, runtime, None)?;
+                        write!(out, " \" ")?;
+                    }
+                    for offset in 0..len {
+                        out.write_char(unsafe { *((addr + offset) as *const u8) } as char)?;
+                    }
+                    write!(out, "\"")?;
+                }
+                Item::Exit if at_end => return writeln!(out, ";"),
+                Item::Exit => write!(out, "EXIT")?,
+                Item::Raw(instr) => write!(out, "{:08X}", instr)?,
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Show the instructions of a `CODE` word whose prologue is at `code`.
+    fn see_code(&self, out: &mut impl Write, code: usize) -> fmt::Result {
+        let entry = code + codegen::CODE_PROLOGUE.len() * INSTR_SIZE;
+        let mut decoder = Decoder {
+            pc: entry,
+            exit: code - codegen::CODE_EPILOGUE.len() * INSTR_SIZE,
+            limit: code_limit(&self.dict, code),
+        };
+        let mut furthest = entry;
+        while let Some(instr) = decoder.instr(0) {
+            if decoder.pc >= furthest && decoder.is_exit(0) {
+                return writeln!(out, "END-CODE");
+            }
+            let dest = if instr & 0xFC00_0000 == 0x1400_0000 {
+                codegen::decode_branch(decoder.pc, instr)
+            } else {
+                codegen::decode_conditional(decoder.pc, instr)
+            };
+            if let Some(dest) = dest.filter(|dest| *dest < decoder.limit) {
+                furthest = furthest.max(dest);
+            }
+            writeln!(out, "{:>5} {:08X}", decoder.pc - entry, instr)?;
+            decoder.pc += INSTR_SIZE;
+        }
+        Ok(())
+    }
+
+    fn see(&self, header: &Header) -> fmt::Result {
+        let mut out = crate::console::output();
+        let name = header.name();
+        let code = header.code();
+        let decoder = Decoder {
+            pc: code,
+            exit: 0,
+            limit: code_limit(&self.dict, code),
+        };
+        let stub = decoder.constant(0, X1).and_then(|arg| {
+            let target = decoder.constant(codegen::STUB_TARGET, X16)?;
+            if decoder.is(codegen::STUB_TARGET + 4, Some(codegen::br(X16))) {
+                Some((arg, target))
+            } else {
+                None
+            }
+        });
+        if !self.dict.contains(code) {
+            writeln!(out, "{} is a primitive at {:#x}", name, code)?;
+        } else if let Some((arg, target)) = stub {
+            if header.param() != 0 {
+                writeln!(out, "CREATE {}", name)?;
+                if target != create_runtime as usize {
+                    writeln!(out, "DOES>")?;
+                    self.decompile(&mut out, target)?;
+                }
+            } else if target == literal_runtime as usize {
+                let mut buf = [0; 72];
+                let value = format_number(arg as Cell, self.user.base, &mut buf);
+                writeln!(out, "{} CONSTANT {}", value, name)?;
+            } else if target == fliteral_runtime as usize {
+                let value = float_literal(f64::from_bits(arg as u64));
+                writeln!(out, "{} FCONSTANT {}", value, name)?;
+            } else {
+                writeln!(out, "{} calls {:#x} with {:#x}", name, target, arg)?;
+            }
+        } else if decoder.starts_with(&codegen::CODE_PROLOGUE) {
+            writeln!(out, "CODE {}", name)?;
+            self.see_code(&mut out, code)?;
+        } else if decoder.starts_with(&codegen::PROLOGUE) {
+            writeln!(out, ": {}", name)?;
+            self.decompile(&mut out, code)?;
+        } else {
+            writeln!(out, "{} has unrecognized code at {:#x}", name, code)?;
+        }
+        if header.is_immediate() {
+            writeln!(out, "IMMEDIATE")?;
+        }
+        Ok(())
+    }
+}
+
+/// Show `len` bytes from `addr`. Pages are checked with the MMU before being
+/// read, so that unmapped memory shows as `??` instead of faulting.
+fn dump_memory(addr: usize, len: usize) -> fmt::Result {
+    let mut out = crate::console::output();
+    // The page that was checked last, and whether it's mapped.
+    let mut checked: Option<(usize, bool)> = None;
+    let mut read = |addr: usize| {
+        let page = addr & !(PAGE_SIZE - 1);
+        let mapped = match checked {
+            Some((checked_page, mapped)) if checked_page == page => mapped,
+            _ => {
+                let mapped = mmu::translate(page).is_some();
+                checked = Some((page, mapped));
+                mapped
+            }
+        };
+        if mapped {
+            Some(unsafe { (addr as *const u8).read_volatile() })
+        } else {
+            None
+        }
+    };
+    for start in (0..len).step_by(DUMP_LINE) {
+        let line = addr.wrapping_add(start);
+        let count = (len - start).min(DUMP_LINE);
+        let mut text = [b' '; DUMP_LINE];
+        write!(out, "{:016X} ", line)?;
+        for index in 0..DUMP_LINE {
+            if index == DUMP_LINE / 2 {
+                write!(out, " ")?;
+            }
+            if index >= count {
+                write!(out, "   ")?;
+                continue;
+            }
+            match read(line.wrapping_add(index)) {
+                Some(byte) => {
+                    write!(out, "{:02X} ", byte)?;
+                    text[index] = if byte.is_ascii_graphic() || byte == b' ' {
+                        byte
+                    } else {
+                        b'.'
+                    };
+                }
+                None => write!(out, "?? ")?,
+            }
+        }
+        // Only printable ASCII went into `text`.
+        let text = unsafe { core::str::from_utf8_unchecked(&text[..count]) };
+        writeln!(out, " |{}|", text)?;
+    }
+    Ok(())
+}
+
+primitives! {
+    /// `( "name" -- )` Show the definition of a word.
+    fn see(vm) {
+        let xt = vm.parse_xt()?;
+        let _ = vm.see(unsafe { &*xt });
+        Ok(())
+    }
+
+    /// `( addr u -- )` Show `u` bytes of memory from `addr` in hex and as
+    /// characters.
+    fn dump(vm) {
+        let len = vm.pop()?.max(0) as usize;
+        let addr = vm.pop()? as usize;
+        let _ = dump_memory(addr, len);
+        Ok(())
+    }
+}
+
+pub const WORDS: &[(&str, Flags, Entry)] = &[("SEE", NONE, see), ("DUMP", NONE, dump)];
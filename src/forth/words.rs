@@ -0,0 +1,1158 @@
+use alloc::string::String;
+use core::{fmt::Write, mem, ptr, slice};
+
+use super::{
+    assembler, block,
+    compiler::compile_call_runtime,
+    control,
+    dictionary::{Entry, Flags, Header, Xt},
+    files, float, mailbox, memory, mmio,
+    numeric::{self, HOLD_SIZE},
+    process, tasks, tools, Cell, Throw, Vm, DATA_STACK_CELLS, FALSE, RETURN_STACK_CELLS, TRUE,
+};
+use crate::rpi::uart::UART;
+
+const CELL: Cell = mem::size_of::<Cell>() as Cell;
+
+/// Format `value` in `base` into `buf` as an unsigned number, returning the
+/// used tail of the buffer.
+pub fn format_unsigned(mut value: usize, base: Cell, buf: &mut [u8; 72]) -> &str {
+    let base = if base < 2 || base > 36 {
+        10
+    } else {
+        base as usize
+    };
+    let mut index = buf.len();
+    loop {
+        let digit = (value % base) as u8;
+        value /= base;
+        index -= 1;
+        buf[index] = if digit < 10 {
+            b'0' + digit
+        } else {
+            b'A' + digit - 10
+        };
+        if value == 0 {
+            break;
+        }
+    }
+    core::str::from_utf8(&buf[index..]).unwrap()
+}
+
+/// Format `value` in `base` into `buf`, returning the used tail of the buffer.
+pub fn format_number(value: Cell, base: Cell, buf: &mut [u8; 72]) -> &str {
+    let magnitude = if value < 0 {
+        (value as usize).wrapping_neg()
+    } else {
+        value as usize
+    };
+    let len = format_unsigned(magnitude, base, buf).len();
+    let mut index = buf.len() - len;
+    if value < 0 {
+        index -= 1;
+        buf[index] = b'-';
+    }
+    core::str::from_utf8(&buf[index..]).unwrap()
+}
+
+/// Write raw bytes to the console, one character per byte.
+pub fn type_bytes(bytes: &[u8]) {
+    let mut out = crate::console::output();
+    for byte in bytes {
+        let _ = out.write_char(*byte as char);
+    }
+}
+
+/// The bytes of the string at `addr`, `len` bytes long.
+///
+/// # Safety
+///
+/// The string is whatever a Forth program says it is, so this is only as safe
+/// as the program.
+#[inline]
+unsafe fn bytes<'a>(addr: Cell, len: Cell) -> &'a [u8] {
+    slice::from_raw_parts(addr as *const u8, len.max(0) as usize)
+}
+
+/// The string after a counted string's length byte.
+#[inline]
+unsafe fn counted<'a>(addr: Cell) -> &'a [u8] {
+    let len = *(addr as *const u8);
+    slice::from_raw_parts((addr as *const u8).add(1), len as usize)
+}
+
+/// Push the string that follows `S"` in a definition, as laid down by
+/// `Vm::compile_string`.
+pub(super) extern "C" fn s_quote_runtime(vm: &mut Vm, addr: usize) -> isize {
+    let len = unsafe { *(addr as *const Cell).offset(-1) };
+    let result = vm.push(addr as Cell).and_then(|()| vm.push(len));
+    Throw::into_code(result)
+}
+
+pub(super) extern "C" fn abort_quote_runtime(vm: &mut Vm, addr: usize) -> isize {
+    let result = vm.pop().and_then(|flag| {
+        if flag == FALSE {
+            return Ok(());
+        }
+        let len = unsafe { *(addr as *const Cell).offset(-1) };
+        vm.user.abort_message = Some((addr, len as usize));
+        Err(Throw::ABORT_QUOTE)
+    });
+    Throw::into_code(result)
+}
+
+impl Vm {
+    /// Parse a name and look it up.
+    pub(super) fn parse_xt(&mut self) -> Result<Xt, Throw> {
+        let name = self.parse_name()?;
+        if name.is_empty() {
+            return Err(Throw::ZERO_LENGTH_NAME);
+        }
+        let name = String::from(name);
+        let header = self.dict.find(&name).ok_or(Throw::UNDEFINED_WORD)?;
+        Ok(header as *const Header)
+    }
+
+    /// Parse a name for a new definition.
+    pub(super) fn parse_new_name(&mut self) -> Result<String, Throw> {
+        let name = self.parse_name()?;
+        if name.is_empty() {
+            return Err(Throw::ZERO_LENGTH_NAME);
+        }
+        Ok(String::from(name))
+    }
+
+    fn first_char(&mut self) -> Result<Cell, Throw> {
+        let name = self.parse_name()?;
+        let byte = name.bytes().next().ok_or(Throw::ZERO_LENGTH_NAME)?;
+        Ok(byte as Cell)
+    }
+
+    /// Parse a string ending in `"`. Inside a definition, compile it along
+    /// with a call to `runtime`, which receives its address. Otherwise copy
+    /// it to a transient buffer and push its address and length.
+    fn string_literal(&mut self, runtime: usize) -> Result<(), Throw> {
+        let (addr, len) = self.parse(b'"');
+        if self.compiling() {
+            let copy = self.compile_string(addr, len)?;
+            self.compile_native_call_with(runtime, copy as Cell)
+        } else {
+            let copy = self.user.input.stash_string(addr, len)?;
+            self.push(copy as Cell)?;
+            self.push(len as Cell)
+        }
+    }
+}
+
+primitives! {
+    fn add(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(a.wrapping_add(b))
+    }
+
+    fn sub(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(a.wrapping_sub(b))
+    }
+
+    fn mul(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(a.wrapping_mul(b))
+    }
+
+    fn div(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        if b == 0 {
+            return Err(Throw::DIVISION_BY_ZERO);
+        }
+        vm.push(a.wrapping_div(b))
+    }
+
+    fn modulo(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        if b == 0 {
+            return Err(Throw::DIVISION_BY_ZERO);
+        }
+        vm.push(a.wrapping_rem(b))
+    }
+
+    fn negate(vm) {
+        let a = vm.pop()?;
+        vm.push(a.wrapping_neg())
+    }
+
+    fn abs(vm) {
+        let a = vm.pop()?;
+        vm.push(a.wrapping_abs())
+    }
+
+    fn one_plus(vm) {
+        let a = vm.pop()?;
+        vm.push(a.wrapping_add(1))
+    }
+
+    fn one_minus(vm) {
+        let a = vm.pop()?;
+        vm.push(a.wrapping_sub(1))
+    }
+
+    fn two_star(vm) {
+        let a = vm.pop()?;
+        vm.push(a << 1)
+    }
+
+    fn two_slash(vm) {
+        let a = vm.pop()?;
+        vm.push(a >> 1)
+    }
+
+    fn and(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(a & b)
+    }
+
+    fn or(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(a | b)
+    }
+
+    fn xor(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(a ^ b)
+    }
+
+    fn invert(vm) {
+        let a = vm.pop()?;
+        vm.push(!a)
+    }
+
+    fn lshift(vm) {
+        let count = vm.pop()? as usize;
+        let a = vm.pop()? as usize;
+        vm.push(a.checked_shl(count as u32).unwrap_or(0) as Cell)
+    }
+
+    fn rshift(vm) {
+        let count = vm.pop()? as usize;
+        let a = vm.pop()? as usize;
+        vm.push(a.checked_shr(count as u32).unwrap_or(0) as Cell)
+    }
+
+    fn max(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(a.max(b))
+    }
+
+    fn min(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(a.min(b))
+    }
+
+    fn zero_less(vm) {
+        let a = vm.pop()?;
+        vm.push_flag(a < 0)
+    }
+
+    fn zero_equals(vm) {
+        let a = vm.pop()?;
+        vm.push_flag(a == 0)
+    }
+
+    fn zero_not_equals(vm) {
+        let a = vm.pop()?;
+        vm.push_flag(a != 0)
+    }
+
+    fn zero_greater(vm) {
+        let a = vm.pop()?;
+        vm.push_flag(a > 0)
+    }
+
+    fn equals(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push_flag(a == b)
+    }
+
+    fn not_equals(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push_flag(a != b)
+    }
+
+    fn less_than(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push_flag(a < b)
+    }
+
+    fn greater_than(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push_flag(a > b)
+    }
+
+    fn u_less_than(vm) {
+        let b = vm.pop()? as usize;
+        let a = vm.pop()? as usize;
+        vm.push_flag(a < b)
+    }
+
+    fn u_greater_than(vm) {
+        let b = vm.pop()? as usize;
+        let a = vm.pop()? as usize;
+        vm.push_flag(a > b)
+    }
+
+    fn dup(vm) {
+        let a = vm.data.peek(0)?;
+        vm.push(a)
+    }
+
+    fn question_dup(vm) {
+        let a = vm.data.peek(0)?;
+        if a != 0 {
+            vm.push(a)?;
+        }
+        Ok(())
+    }
+
+    fn drop(vm) {
+        vm.pop().map(|_| ())
+    }
+
+    fn swap(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(b)?;
+        vm.push(a)
+    }
+
+    fn over(vm) {
+        let a = vm.data.peek(1)?;
+        vm.push(a)
+    }
+
+    fn rot(vm) {
+        let c = vm.pop()?;
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(b)?;
+        vm.push(c)?;
+        vm.push(a)
+    }
+
+    fn nip(vm) {
+        let b = vm.pop()?;
+        vm.pop()?;
+        vm.push(b)
+    }
+
+    fn tuck(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(b)?;
+        vm.push(a)?;
+        vm.push(b)
+    }
+
+    fn pick(vm) {
+        let index = vm.pop()?;
+        let a = vm.data.peek(index as usize)?;
+        vm.push(a)
+    }
+
+    fn roll(vm) {
+        let index = vm.pop()? as usize;
+        vm.data.require(index + 1)?;
+        let a = vm.data.peek(index)?;
+        for i in (0..index).rev() {
+            let b = vm.data.peek(i)?;
+            *vm.data.peek_mut(i + 1)? = b;
+        }
+        *vm.data.peek_mut(0)? = a;
+        Ok(())
+    }
+
+    fn two_drop(vm) {
+        vm.pop()?;
+        vm.pop()?;
+        Ok(())
+    }
+
+    fn two_dup(vm) {
+        let b = vm.data.peek(0)?;
+        let a = vm.data.peek(1)?;
+        vm.push(a)?;
+        vm.push(b)
+    }
+
+    fn two_swap(vm) {
+        let d = vm.pop()?;
+        let c = vm.pop()?;
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.push(c)?;
+        vm.push(d)?;
+        vm.push(a)?;
+        vm.push(b)
+    }
+
+    fn two_over(vm) {
+        let b = vm.data.peek(2)?;
+        let a = vm.data.peek(3)?;
+        vm.push(a)?;
+        vm.push(b)
+    }
+
+    fn depth(vm) {
+        let depth = vm.data.depth() as Cell;
+        vm.push(depth)
+    }
+
+    fn to_r(vm) {
+        let a = vm.pop()?;
+        vm.ret.push(a)
+    }
+
+    fn r_from(vm) {
+        let a = vm.ret.pop()?;
+        vm.push(a)
+    }
+
+    fn r_fetch(vm) {
+        let a = vm.ret.peek(0)?;
+        vm.push(a)
+    }
+
+    fn two_to_r(vm) {
+        let b = vm.pop()?;
+        let a = vm.pop()?;
+        vm.ret.push(a)?;
+        vm.ret.push(b)
+    }
+
+    fn two_r_from(vm) {
+        let b = vm.ret.pop()?;
+        let a = vm.ret.pop()?;
+        vm.push(a)?;
+        vm.push(b)
+    }
+
+    fn two_r_fetch(vm) {
+        let b = vm.ret.peek(0)?;
+        let a = vm.ret.peek(1)?;
+        vm.push(a)?;
+        vm.push(b)
+    }
+
+    fn fetch(vm) {
+        let addr = vm.pop()?;
+        let value = unsafe { (addr as *const Cell).read_volatile() };
+        vm.push(value)
+    }
+
+    fn store(vm) {
+        let addr = vm.pop()?;
+        let value = vm.pop()?;
+        unsafe { (addr as *mut Cell).write_volatile(value) };
+        Ok(())
+    }
+
+    fn c_fetch(vm) {
+        let addr = vm.pop()?;
+        let value = unsafe { (addr as *const u8).read_volatile() };
+        vm.push(value as Cell)
+    }
+
+    fn c_store(vm) {
+        let addr = vm.pop()?;
+        let value = vm.pop()?;
+        unsafe { (addr as *mut u8).write_volatile(value as u8) };
+        Ok(())
+    }
+
+    fn plus_store(vm) {
+        let addr = vm.pop()? as *mut Cell;
+        let value = vm.pop()?;
+        unsafe { addr.write_volatile(addr.read_volatile().wrapping_add(value)) };
+        Ok(())
+    }
+
+    fn two_fetch(vm) {
+        let addr = vm.pop()? as *const Cell;
+        let (low, high) = unsafe { (addr.add(1).read_volatile(), addr.read_volatile()) };
+        vm.push(low)?;
+        vm.push(high)
+    }
+
+    fn two_store(vm) {
+        let addr = vm.pop()? as *mut Cell;
+        let high = vm.pop()?;
+        let low = vm.pop()?;
+        unsafe {
+            addr.write_volatile(high);
+            addr.add(1).write_volatile(low);
+        }
+        Ok(())
+    }
+
+    fn comma(vm) {
+        let value = vm.pop()?;
+        vm.dict.comma(value)
+    }
+
+    fn c_comma(vm) {
+        let value = vm.pop()?;
+        vm.dict.c_comma(value as u8)
+    }
+
+    fn allot(vm) {
+        let bytes = vm.pop()?;
+        vm.dict.allot(bytes)?;
+        if bytes < 0 {
+            vm.forget_tasks(vm.dict.here());
+        }
+        Ok(())
+    }
+
+    fn here(vm) {
+        let here = vm.dict.here() as Cell;
+        vm.push(here)
+    }
+
+    fn unused(vm) {
+        let unused = vm.dict.unused() as Cell;
+        vm.push(unused)
+    }
+
+    fn align(vm) {
+        vm.dict.align()
+    }
+
+    fn aligned(vm) {
+        let addr = vm.pop()?;
+        vm.push((addr + CELL - 1) & !(CELL - 1))
+    }
+
+    fn cell_plus(vm) {
+        let addr = vm.pop()?;
+        vm.push(addr.wrapping_add(CELL))
+    }
+
+    fn cells(vm) {
+        let count = vm.pop()?;
+        vm.push(count.wrapping_mul(CELL))
+    }
+
+    fn char_plus(vm) {
+        let addr = vm.pop()?;
+        vm.push(addr.wrapping_add(1))
+    }
+
+    fn chars(vm) {
+        Ok(())
+    }
+
+    fn fill(vm) {
+        let byte = vm.pop()? as u8;
+        let len = vm.pop()?;
+        let addr = vm.pop()?;
+        if len > 0 {
+            unsafe { ptr::write_bytes(addr as *mut u8, byte, len as usize) };
+        }
+        Ok(())
+    }
+
+    fn move_(vm) {
+        let len = vm.pop()?;
+        let to = vm.pop()?;
+        let from = vm.pop()?;
+        if len > 0 {
+            unsafe { ptr::copy(from as *const u8, to as *mut u8, len as usize) };
+        }
+        Ok(())
+    }
+
+    fn count(vm) {
+        let addr = vm.pop()?;
+        let len = unsafe { *(addr as *const u8) };
+        vm.push(addr + 1)?;
+        vm.push(len as Cell)
+    }
+
+    fn dot(vm) {
+        let value = vm.pop()?;
+        let mut buf = [0; 72];
+        print!("{} ", format_number(value, vm.user.base, &mut buf));
+        Ok(())
+    }
+
+    fn u_dot(vm) {
+        let value = vm.pop()? as usize;
+        let mut buf = [0; 72];
+        print!("{} ", format_unsigned(value, vm.user.base, &mut buf));
+        Ok(())
+    }
+
+    fn dot_s(vm) {
+        let mut buf = [0; 72];
+        print!("<{}> ", vm.data.depth());
+        for value in vm.data.as_slice() {
+            print!("{} ", format_number(*value, vm.user.base, &mut buf));
+        }
+        Ok(())
+    }
+
+    fn cr(vm) {
+        println!();
+        Ok(())
+    }
+
+    fn emit(vm) {
+        let c = vm.pop()? as u8;
+        type_bytes(&[c]);
+        Ok(())
+    }
+
+    fn type_(vm) {
+        let len = vm.pop()?;
+        let addr = vm.pop()?;
+        type_bytes(unsafe { bytes(addr, len) });
+        Ok(())
+    }
+
+    fn space(vm) {
+        print!(" ");
+        Ok(())
+    }
+
+    fn spaces(vm) {
+        let count = vm.pop()?;
+        for _ in 0..count {
+            print!(" ");
+        }
+        Ok(())
+    }
+
+    fn bl(vm) {
+        vm.push(b' ' as Cell)
+    }
+
+    fn key(vm) {
+        // Let the other tasks run while there's no key, as `ACCEPT` does.
+        let mut uart = UART::new();
+        let byte = loop {
+            match uart.try_read_byte() {
+                Some(byte) => break byte,
+                None => vm.pause(),
+            }
+        };
+        vm.push(byte as Cell)
+    }
+
+    /// `( -- fifo buffer )` How often the console's UART has lost received
+    /// bytes since boot, because its FIFO or the buffer behind it was full.
+    fn uart_overruns(vm) {
+        let overruns = UART::new().overruns();
+        vm.push(overruns.fifo as Cell)?;
+        vm.push(overruns.buffer as Cell)
+    }
+
+    /// Only the console has the line editor.
+    fn accept(vm) {
+        if !vm.tasks.is_console() {
+            return Err(Throw::INVALID_TASK);
+        }
+        let max = vm.pop()?;
+        let addr = vm.pop()?;
+        let buffer = unsafe { slice::from_raw_parts_mut(addr as *mut u8, max.max(0) as usize) };
+        let Vm {
+            editor,
+            dict,
+            tasks,
+            user,
+            data,
+            ret,
+            floats,
+            ..
+        } = vm;
+        let len = editor.read_line(buffer, &*dict, &mut || tasks.pause(user, data, ret, floats));
+        vm.push(len as Cell)
+    }
+
+    fn base(vm) {
+        let addr = &vm.user.base as *const Cell as Cell;
+        vm.push(addr)
+    }
+
+    fn state(vm) {
+        let addr = &vm.user.state as *const Cell as Cell;
+        vm.push(addr)
+    }
+
+    fn to_in(vm) {
+        let addr = &vm.user.to_in as *const Cell as Cell;
+        vm.push(addr)
+    }
+
+    fn decimal(vm) {
+        vm.user.base = 10;
+        Ok(())
+    }
+
+    fn hex(vm) {
+        vm.user.base = 16;
+        Ok(())
+    }
+
+    fn source(vm) {
+        let (addr, len) = vm.user.input.source();
+        vm.push(addr as Cell)?;
+        vm.push(len as Cell)
+    }
+
+    fn source_id(vm) {
+        let id = vm.user.input.source_id();
+        vm.push(id)
+    }
+
+    fn true_(vm) {
+        vm.push(TRUE)
+    }
+
+    fn false_(vm) {
+        vm.push(FALSE)
+    }
+
+    fn execute(vm) {
+        let xt = vm.pop()? as Xt;
+        vm.execute(xt)
+    }
+
+    fn evaluate(vm) {
+        let len = vm.pop()?;
+        let addr = vm.pop()?;
+        vm.evaluate(addr as usize, len.max(0) as usize)
+    }
+
+    fn tick(vm) {
+        let xt = vm.parse_xt()?;
+        vm.push(xt as Cell)
+    }
+
+    fn bracket_tick(vm) {
+        let xt = vm.parse_xt()?;
+        vm.compile_literal(xt as Cell)
+    }
+
+    fn find(vm) {
+        let addr = vm.pop()?;
+        let name = core::str::from_utf8(unsafe { counted(addr) }).unwrap_or("");
+        let found = vm
+            .dict
+            .find(name)
+            .map(|header| (header as *const Header, header.is_immediate()));
+        match found {
+            Some((xt, immediate)) => {
+                vm.push(xt as Cell)?;
+                vm.push(if immediate { 1 } else { -1 })
+            }
+            None => {
+                vm.push(addr)?;
+                vm.push(0)
+            }
+        }
+    }
+
+    fn colon(vm) {
+        let name = vm.parse_new_name()?;
+        vm.begin_colon(&name).map(|_| ())
+    }
+
+    fn semicolon(vm) {
+        vm.end_colon()
+    }
+
+    fn exit(vm) {
+        vm.compile_exit()
+    }
+
+    fn recurse(vm) {
+        let xt = vm.user.colon.as_ref().map(|colon| colon.xt).ok_or(Throw::COMPILE_ONLY)?;
+        vm.compile_call(xt)
+    }
+
+    fn immediate(vm) {
+        vm.dict.set_flags(Flags::IMMEDIATE, true);
+        Ok(())
+    }
+
+    fn create(vm) {
+        let name = vm.parse_new_name()?;
+        vm.create(&name).map(|_| ())
+    }
+
+    fn does(vm) {
+        vm.begin_does()
+    }
+
+    fn to_body(vm) {
+        let xt = vm.pop()? as Xt;
+        let param = unsafe { (*xt).param() };
+        if param == 0 {
+            return Err(Throw::NOT_CREATED);
+        }
+        vm.push(param as Cell)
+    }
+
+    fn variable(vm) {
+        let name = vm.parse_new_name()?;
+        vm.create(&name)?;
+        vm.dict.comma(0)
+    }
+
+    fn constant(vm) {
+        let name = vm.parse_new_name()?;
+        let value = vm.pop()?;
+        vm.constant(&name, value).map(|_| ())
+    }
+
+    fn left_bracket(vm) {
+        vm.user.state = FALSE;
+        Ok(())
+    }
+
+    fn right_bracket(vm) {
+        vm.user.state = TRUE;
+        Ok(())
+    }
+
+    fn literal(vm) {
+        let value = vm.pop()?;
+        vm.compile_literal(value)
+    }
+
+    fn postpone(vm) {
+        let xt = vm.parse_xt()?;
+        if unsafe { (*xt).is_immediate() } {
+            vm.compile_call(xt)
+        } else {
+            vm.compile_native_call_with(compile_call_runtime as usize, xt as Cell)
+        }
+    }
+
+    fn char(vm) {
+        let c = vm.first_char()?;
+        vm.push(c)
+    }
+
+    fn bracket_char(vm) {
+        let c = vm.first_char()?;
+        vm.compile_literal(c)
+    }
+
+    fn word(vm) {
+        let delimiter = vm.pop()? as u8;
+        let addr = vm.word(delimiter);
+        vm.push(addr as Cell)
+    }
+
+    fn parse(vm) {
+        let delimiter = vm.pop()? as u8;
+        let (addr, len) = vm.parse(delimiter);
+        vm.push(addr as Cell)?;
+        vm.push(len as Cell)
+    }
+
+    fn parse_name(vm) {
+        let (start, _) = vm.user.input.source();
+        let name = vm.parse_name_bytes();
+        let (addr, len) = (name.as_ptr() as usize, name.len());
+        // An empty name is at the end of the input, not wherever `""` lives.
+        let addr = if len == 0 { start + vm.user.to_in as usize } else { addr };
+        vm.push(addr as Cell)?;
+        vm.push(len as Cell)
+    }
+
+    fn paren(vm) {
+        vm.parse(b')');
+        Ok(())
+    }
+
+    fn backslash(vm) {
+        let (_, len) = vm.user.input.source();
+        vm.user.to_in = if vm.user.blk != 0 {
+            // Blocks are 16 lines of 64 characters.
+            let line = block::LINE_LENGTH as Cell;
+            ((vm.user.to_in + line - 1) / line * line).min(len as Cell)
+        } else {
+            len as Cell
+        };
+        Ok(())
+    }
+
+    fn dot_paren(vm) {
+        let (addr, len) = vm.parse(b')');
+        type_bytes(unsafe { bytes(addr as Cell, len as Cell) });
+        Ok(())
+    }
+
+    fn s_quote(vm) {
+        vm.string_literal(s_quote_runtime as usize)
+    }
+
+    fn dot_quote(vm) {
+        if vm.compiling() {
+            vm.string_literal(s_quote_runtime as usize)?;
+            vm.compile_native_call(type_ as usize)
+        } else {
+            let (addr, len) = vm.parse(b'"');
+            type_bytes(unsafe { bytes(addr as Cell, len as Cell) });
+            Ok(())
+        }
+    }
+
+    fn abort(vm) {
+        Err(Throw::ABORT)
+    }
+
+    fn abort_quote(vm) {
+        vm.string_literal(abort_quote_runtime as usize)
+    }
+
+    fn quit(vm) {
+        Err(Throw::QUIT)
+    }
+
+    fn catch(vm) {
+        let xt = vm.pop()? as Xt;
+        let data_depth = vm.data.depth();
+        let ret_depth = vm.ret.depth();
+        match vm.execute(xt) {
+            Ok(()) => vm.push(0),
+            Err(throw) => {
+                vm.data.set_depth(data_depth);
+                vm.ret.set_depth(ret_depth);
+                vm.user.input.take_error();
+                vm.push(throw.code())
+            }
+        }
+    }
+
+    fn throw(vm) {
+        let code = vm.pop()?;
+        Throw::from_code(code)
+    }
+
+    fn environment_query(vm) {
+        let len = vm.pop()?;
+        let addr = vm.pop()?;
+        let name = unsafe { bytes(addr, len) };
+        let max_n = Cell::max_value();
+        let value = match name {
+            b"/COUNTED-STRING" => 255,
+            b"/HOLD" => HOLD_SIZE as Cell,
+            b"ADDRESS-UNIT-BITS" => 8,
+            b"FLOORED" => FALSE,
+            b"MAX-CHAR" => 255,
+            b"MAX-N" => max_n,
+            b"MAX-U" => -1,
+            b"RETURN-STACK-CELLS" => RETURN_STACK_CELLS as Cell,
+            b"STACK-CELLS" => DATA_STACK_CELLS as Cell,
+            b"MAX-D" => {
+                vm.push(-1)?;
+                max_n
+            }
+            b"MAX-UD" => {
+                vm.push(-1)?;
+                -1
+            }
+            _ => return vm.push(FALSE),
+        };
+        vm.push(value)?;
+        vm.push(TRUE)
+    }
+
+    fn words(vm) {
+        let mut out = crate::console::output();
+        for header in vm.dict.iter() {
+            let _ = write!(out, "{} ", header.name());
+        }
+        Ok(())
+    }
+}
+
+pub const NONE: Flags = Flags::empty();
+pub const COMPILE_ONLY: Flags = Flags::COMPILE_ONLY;
+pub const IMMEDIATE: Flags = Flags::IMMEDIATE;
+/// Immediate words that only make sense inside a definition.
+pub const COMPILER: Flags =
+    Flags::from_bits_truncate(Flags::IMMEDIATE.bits() | Flags::COMPILE_ONLY.bits());
+/// Words in the `ASSEMBLER` vocabulary.
+pub const ASSEMBLER: Flags = Flags::ASSEMBLER;
+
+const PRIMITIVES: &[(&str, Flags, Entry)] = &[
+    ("+", NONE, add),
+    ("-", NONE, sub),
+    ("*", NONE, mul),
+    ("/", NONE, div),
+    ("MOD", NONE, modulo),
+    ("NEGATE", NONE, negate),
+    ("ABS", NONE, abs),
+    ("1+", NONE, one_plus),
+    ("1-", NONE, one_minus),
+    ("2*", NONE, two_star),
+    ("2/", NONE, two_slash),
+    ("AND", NONE, and),
+    ("OR", NONE, or),
+    ("XOR", NONE, xor),
+    ("INVERT", NONE, invert),
+    ("LSHIFT", NONE, lshift),
+    ("RSHIFT", NONE, rshift),
+    ("MAX", NONE, max),
+    ("MIN", NONE, min),
+    ("0<", NONE, zero_less),
+    ("0=", NONE, zero_equals),
+    ("0<>", NONE, zero_not_equals),
+    ("0>", NONE, zero_greater),
+    ("=", NONE, equals),
+    ("<>", NONE, not_equals),
+    ("<", NONE, less_than),
+    (">", NONE, greater_than),
+    ("U<", NONE, u_less_than),
+    ("U>", NONE, u_greater_than),
+    ("DUP", NONE, dup),
+    ("?DUP", NONE, question_dup),
+    ("DROP", NONE, drop),
+    ("SWAP", NONE, swap),
+    ("OVER", NONE, over),
+    ("ROT", NONE, rot),
+    ("NIP", NONE, nip),
+    ("TUCK", NONE, tuck),
+    ("PICK", NONE, pick),
+    ("ROLL", NONE, roll),
+    ("2DROP", NONE, two_drop),
+    ("2DUP", NONE, two_dup),
+    ("2SWAP", NONE, two_swap),
+    ("2OVER", NONE, two_over),
+    ("DEPTH", NONE, depth),
+    (">R", COMPILE_ONLY, to_r),
+    ("R>", COMPILE_ONLY, r_from),
+    ("R@", COMPILE_ONLY, r_fetch),
+    ("2>R", COMPILE_ONLY, two_to_r),
+    ("2R>", COMPILE_ONLY, two_r_from),
+    ("2R@", COMPILE_ONLY, two_r_fetch),
+    ("@", NONE, fetch),
+    ("!", NONE, store),
+    ("C@", NONE, c_fetch),
+    ("C!", NONE, c_store),
+    ("+!", NONE, plus_store),
+    ("2@", NONE, two_fetch),
+    ("2!", NONE, two_store),
+    (",", NONE, comma),
+    ("C,", NONE, c_comma),
+    ("ALLOT", NONE, allot),
+    ("HERE", NONE, here),
+    ("UNUSED", NONE, unused),
+    ("ALIGN", NONE, align),
+    ("ALIGNED", NONE, aligned),
+    ("CELL+", NONE, cell_plus),
+    ("CELLS", NONE, cells),
+    ("CHAR+", NONE, char_plus),
+    ("CHARS", NONE, chars),
+    ("FILL", NONE, fill),
+    ("MOVE", NONE, move_),
+    ("COUNT", NONE, count),
+    (".", NONE, dot),
+    ("U.", NONE, u_dot),
+    (".S", NONE, dot_s),
+    ("CR", NONE, cr),
+    ("EMIT", NONE, emit),
+    ("TYPE", NONE, type_),
+    ("SPACE", NONE, space),
+    ("SPACES", NONE, spaces),
+    ("BL", NONE, bl),
+    ("KEY", NONE, key),
+    ("UART-OVERRUNS", NONE, uart_overruns),
+    ("ACCEPT", NONE, accept),
+    ("BASE", NONE, base),
+    ("STATE", NONE, state),
+    (">IN", NONE, to_in),
+    ("DECIMAL", NONE, decimal),
+    ("HEX", NONE, hex),
+    ("SOURCE", NONE, source),
+    ("SOURCE-ID", NONE, source_id),
+    ("TRUE", NONE, true_),
+    ("FALSE", NONE, false_),
+    ("EXECUTE", NONE, execute),
+    ("EVALUATE", NONE, evaluate),
+    ("'", NONE, tick),
+    ("[']", COMPILER, bracket_tick),
+    ("FIND", NONE, find),
+    (":", NONE, colon),
+    (";", COMPILER, semicolon),
+    ("EXIT", COMPILER, exit),
+    ("RECURSE", COMPILER, recurse),
+    ("IMMEDIATE", NONE, immediate),
+    ("CREATE", NONE, create),
+    ("DOES>", COMPILER, does),
+    (">BODY", NONE, to_body),
+    ("VARIABLE", NONE, variable),
+    ("CONSTANT", NONE, constant),
+    ("[", COMPILER, left_bracket),
+    ("]", NONE, right_bracket),
+    ("LITERAL", COMPILER, literal),
+    ("POSTPONE", COMPILER, postpone),
+    ("CHAR", NONE, char),
+    ("[CHAR]", COMPILER, bracket_char),
+    ("WORD", NONE, word),
+    ("PARSE", NONE, parse),
+    ("PARSE-NAME", NONE, parse_name),
+    ("(", IMMEDIATE, paren),
+    ("\\", IMMEDIATE, backslash),
+    (".(", IMMEDIATE, dot_paren),
+    ("S\"", IMMEDIATE, s_quote),
+    (".\"", IMMEDIATE, dot_quote),
+    ("ABORT", NONE, abort),
+    ("ABORT\"", COMPILER, abort_quote),
+    ("QUIT", NONE, quit),
+    ("CATCH", NONE, catch),
+    ("THROW", NONE, throw),
+    ("ENVIRONMENT?", NONE, environment_query),
+    ("WORDS", NONE, words),
+];
+
+pub fn install(vm: &mut Vm) -> Result<(), Throw> {
+    let tables = [
+        PRIMITIVES,
+        assembler::WORDS,
+        control::WORDS,
+        numeric::WORDS,
+        mmio::WORDS,
+        mailbox::WORDS,
+        memory::WORDS,
+        block::WORDS,
+        files::WORDS,
+        float::WORDS,
+        tools::WORDS,
+        tasks::WORDS,
+        process::WORDS,
+        #[cfg(feature = "semihosting")]
+        super::image::WORDS,
+    ];
+    for (name, flags, entry) in tables.iter().flat_map(|table| table.iter()) {
+        vm.dict.create_header(name, *flags, *entry as usize)?;
+    }
+    assembler::install(vm)
+}
@@ -1,7 +1,14 @@
 use bitflags::bitflags;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
 use enum_repr::EnumRepr;
+use spin::{Mutex, MutexGuard};
 
+pub mod controller;
+pub mod faults;
 pub mod handlers;
 
 #[repr(C)]
@@ -39,12 +46,9 @@ pub fn exception_vector_init() {
     }
 }
 
-static INT_MASK_LEVEL_D: AtomicUsize = AtomicUsize::new(0);
-static INT_MASK_LEVEL_A: AtomicUsize = AtomicUsize::new(0);
-static INT_MASK_LEVEL_I: AtomicUsize = AtomicUsize::new(0);
-static INT_MASK_LEVEL_F: AtomicUsize = AtomicUsize::new(0);
-
 bitflags! {
+    /// Exceptions that can be masked, as the `daifset` and `daifclr`
+    /// instructions take them.
     pub struct ExceptionMask: usize {
         const D = 0b1000;
         const A = 0b0100;
@@ -53,47 +57,181 @@ bitflags! {
     }
 }
 
+/// Where the mask bits are in the `DAIF` register.
+const DAIF_SHIFT: usize = 6;
+
+#[inline]
+fn read_daif() -> usize {
+    let daif: usize;
+    unsafe { asm!("mrs $0, daif" : "=r"(daif) ::: "volatile") };
+    daif
+}
+
+#[inline]
+unsafe fn write_daif(daif: usize) {
+    asm!("msr daif, $0" :: "r"(daif) : "memory" : "volatile");
+}
+
+/// The exceptions that are masked on this core.
+pub fn masked_exceptions() -> ExceptionMask {
+    ExceptionMask::from_bits_truncate(read_daif() >> DAIF_SHIFT)
+}
+
+/// Masks some exceptions on this core for as long as it lives, then puts the
+/// mask back as it was, so guards nest. It's dropped on unwinding too.
+///
+/// It can't be sent to another core, whose mask it would restore.
+#[must_use]
+pub struct InterruptGuard {
+    saved: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl InterruptGuard {
+    pub fn new(mask: ExceptionMask) -> InterruptGuard {
+        let saved = read_daif();
+        unsafe { write_daif(saved | mask.bits() << DAIF_SHIFT) };
+        InterruptGuard {
+            saved,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Mask IRQs, which is what's needed to share data with an IRQ
+    /// handler.
+    pub fn irqs() -> InterruptGuard {
+        InterruptGuard::new(ExceptionMask::I)
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        unsafe { write_daif(self.saved) };
+    }
+}
+
+/// Call `f` with the exceptions in `mask` masked.
 #[inline]
-pub fn with_masked_interrupts<F, T, const MASK: ExceptionMask>(f: F) -> T
+pub fn with_masked_interrupts<F, T>(mask: ExceptionMask, f: F) -> T
 where
     F: FnOnce() -> T,
 {
-    for (flag, counter) in [
-        (ExceptionMask::D, &INT_MASK_LEVEL_D),
-        (ExceptionMask::A, &INT_MASK_LEVEL_A),
-        (ExceptionMask::I, &INT_MASK_LEVEL_I),
-        (ExceptionMask::F, &INT_MASK_LEVEL_F),
-    ]
-    .iter()
-    {
-        if MASK.contains(*flag) {
-            let prev_mask_level = counter.fetch_add(1, Ordering::SeqCst);
-            if prev_mask_level == 0 {
-                unsafe {
-                    asm!("msr daifclr, $0" :: "i"(flag));
-                }
-            }
+    let _guard = InterruptGuard::new(mask);
+    f()
+}
+
+/// A spin lock that masks IRQs while it's held, for data shared with IRQ
+/// handlers. An IRQ that came in while the lock was held on the same core
+/// would otherwise spin on it forever.
+pub struct IrqMutex<T> {
+    inner: Mutex<T>,
+}
+
+/// The lock of an `IrqMutex`. The fields are dropped in order, so the lock
+/// is released before IRQs are unmasked.
+pub struct IrqMutexGuard<'a, T> {
+    lock: MutexGuard<'a, T>,
+    _interrupts: InterruptGuard,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> IrqMutex<T> {
+        IrqMutex {
+            inner: Mutex::new(value),
         }
     }
-    let result = f();
-    for (flag, counter) in [
-        (ExceptionMask::D, &INT_MASK_LEVEL_D),
-        (ExceptionMask::A, &INT_MASK_LEVEL_A),
-        (ExceptionMask::I, &INT_MASK_LEVEL_I),
-        (ExceptionMask::F, &INT_MASK_LEVEL_F),
-    ]
-    .iter()
-    {
-        if MASK.contains(*flag) {
-            let prev_mask_level = counter.fetch_sub(1, Ordering::SeqCst);
-            if prev_mask_level == 1 {
-                unsafe {
-                    asm!("msr daifclr, $0" :: "i"(flag));
-                }
+
+    pub fn lock(&self) -> IrqMutexGuard<T> {
+        let interrupts = InterruptGuard::irqs();
+        IrqMutexGuard {
+            lock: self.inner.lock(),
+            _interrupts: interrupts,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<T>> {
+        let interrupts = InterruptGuard::irqs();
+        let lock = self.inner.try_lock()?;
+        Some(IrqMutexGuard {
+            lock,
+            _interrupts: interrupts,
+        })
+    }
+}
+
+impl<'a, T> Deref for IrqMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.lock
+    }
+}
+
+impl<'a, T> DerefMut for IrqMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.lock
+    }
+}
+
+/// The registers of the code that took an exception, as `kernel_entry` in
+/// `exceptions.S` saves them. Handlers get it by pointer, and what it holds
+/// when they return is what `kernel_exit` restores, so they can change where
+/// the code resumes or what is in its registers.
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct TrapFrame {
+    pub x: [usize; 31],
+    /// The stack pointer of the interrupted code. Changing it has no effect,
+    /// since the handler is using the same stack.
+    pub sp: usize,
+    /// Where the code resumes: the faulting instruction for a fault, or the
+    /// next one to run for an interrupt.
+    pub elr: usize,
+    pub spsr: usize,
+}
+
+impl TrapFrame {
+    /// Resume after the instruction that took the exception.
+    #[inline]
+    pub fn skip_instruction(&mut self) {
+        self.elr += 4;
+    }
+}
+
+/// The FP and SIMD registers of the code that took an exception, as
+/// `fp_entry` in `exceptions.S` saves them under its `TrapFrame`.
+#[repr(C, align(16))]
+#[derive(Clone)]
+pub struct FpFrame {
+    pub fpcr: u64,
+    pub fpsr: u64,
+    /// `q0`-`q31`, low half first.
+    pub q: [[u64; 2]; 32],
+}
+
+impl FpFrame {
+    pub const fn zero() -> FpFrame {
+        FpFrame {
+            fpcr: 0,
+            fpsr: 0,
+            q: [[0; 2]; 32],
+        }
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (row, regs) in self.x.chunks(4).enumerate() {
+            for (column, value) in regs.iter().enumerate() {
+                write!(f, "x{:<3}{:#018x}  ", row * 4 + column, value)?;
+            }
+            if regs.len() < 4 {
+                write!(f, "sp  {:#018x}", self.sp)?;
             }
+            writeln!(f)?;
         }
+        write!(f, "elr {:#018x}  spsr {:#010x}", self.elr, self.spsr)
     }
-    result
 }
 
 #[EnumRepr(type = "u16")]
@@ -0,0 +1,251 @@
+//! The BCM2837's interrupt controller, and the ARM local interrupt controller
+//! in front of it that the four cores share.
+//!
+//! The BCM2837 controller collects the GPU's 64 interrupt sources and the
+//! ARM peripherals' eight "basic" ones into a single GPU interrupt, which
+//! the local controller routes to one core. The local controller also has
+//! the sources that belong to each core: its generic timers, its mailboxes
+//! and the PMU, as well as the local timer.
+//!
+//! Handlers are plain functions, registered at runtime against an `Irq`.
+//! `dispatch` is called from the IRQ vector with interrupts masked, and
+//! calls the handler of every source that's pending. A handler must clear
+//! the condition in the peripheral that raised it. A source that is pending
+//! with no handler is reported and disabled, so it can't keep interrupting.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    println,
+    rpi::mmio::{self, P_BASE},
+    smp::cpu_id,
+};
+
+const IRQ_BASIC_PENDING: usize = P_BASE + 0x0000_B200;
+const IRQ_PENDING_1: usize = P_BASE + 0x0000_B204;
+const IRQ_PENDING_2: usize = P_BASE + 0x0000_B208;
+const ENABLE_IRQS_1: usize = P_BASE + 0x0000_B210;
+const ENABLE_IRQS_2: usize = P_BASE + 0x0000_B214;
+const ENABLE_BASIC_IRQS: usize = P_BASE + 0x0000_B218;
+const DISABLE_IRQS_1: usize = P_BASE + 0x0000_B21C;
+const DISABLE_IRQS_2: usize = P_BASE + 0x0000_B220;
+const DISABLE_BASIC_IRQS: usize = P_BASE + 0x0000_B224;
+
+/// Bits of `IRQ_BASIC_PENDING` that say to look in the other two pending
+/// registers. Some GPU sources only show up as a bit of their own in the
+/// basic register, not in these summary bits, so those count too.
+const BASIC_PENDING_1: u32 = 1 << 8 | 0b1_1111 << 10;
+const BASIC_PENDING_2: u32 = 1 << 9 | 0b111_1111 << 15;
+
+/// The ARM local interrupt controller. Unlike the peripherals, it isn't
+/// behind `P_BASE`.
+pub const LOCAL_BASE: usize = 0x4000_0000;
+
+const LOCAL_GPU_ROUTING: usize = LOCAL_BASE + 0x0C;
+const LOCAL_PMU_ROUTING_SET: usize = LOCAL_BASE + 0x10;
+const LOCAL_PMU_ROUTING_CLEAR: usize = LOCAL_BASE + 0x14;
+const LOCAL_TIMER_ROUTING: usize = LOCAL_BASE + 0x24;
+/// Per core, at a stride of four bytes.
+const LOCAL_TIMER_CONTROL: usize = LOCAL_BASE + 0x40;
+const LOCAL_MAILBOX_CONTROL: usize = LOCAL_BASE + 0x50;
+const LOCAL_IRQ_SOURCE: usize = LOCAL_BASE + 0x60;
+
+/// The number of GPU, basic and local sources.
+const GPU_IRQS: usize = 64;
+const BASIC_IRQS: usize = 8;
+const LOCAL_IRQS: usize = 12;
+
+/// An interrupt source.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Irq {
+    /// One of the GPU's sources, numbered as in `IRQ_PENDING_1` and `_2`.
+    Gpu(u8),
+    /// One of the ARM peripherals' sources in `IRQ_BASIC_PENDING`.
+    Basic(u8),
+    /// One of the local controller's sources, numbered as in a core's IRQ
+    /// source register. Enabling one enables it for the core that asks.
+    Local(u8),
+}
+
+impl Irq {
+    pub const SYSTEM_TIMER_0: Irq = Irq::Gpu(0);
+    pub const SYSTEM_TIMER_1: Irq = Irq::Gpu(1);
+    pub const SYSTEM_TIMER_2: Irq = Irq::Gpu(2);
+    pub const SYSTEM_TIMER_3: Irq = Irq::Gpu(3);
+    pub const USB: Irq = Irq::Gpu(9);
+    /// The mini UART and the SPI masters.
+    pub const AUX: Irq = Irq::Gpu(29);
+    pub const GPIO_0: Irq = Irq::Gpu(49);
+    pub const UART: Irq = Irq::Gpu(57);
+    pub const EMMC: Irq = Irq::Gpu(62);
+
+    pub const ARM_TIMER: Irq = Irq::Basic(0);
+    pub const ARM_MAILBOX: Irq = Irq::Basic(1);
+
+    /// The core's secure and non-secure physical, hypervisor and virtual
+    /// generic timers.
+    pub const CNTPS: Irq = Irq::Local(0);
+    pub const CNTPNS: Irq = Irq::Local(1);
+    pub const CNTHP: Irq = Irq::Local(2);
+    pub const CNTV: Irq = Irq::Local(3);
+    pub const PMU: Irq = Irq::Local(9);
+    pub const LOCAL_TIMER: Irq = Irq::Local(11);
+
+    /// Local mailbox `n`, 0-3, of the core.
+    pub const fn mailbox(n: u8) -> Irq {
+        Irq::Local(4 + n)
+    }
+
+    /// The index of this source's handler in `HANDLERS`.
+    fn index(self) -> usize {
+        match self {
+            Irq::Gpu(n) => n as usize,
+            Irq::Basic(n) => GPU_IRQS + n as usize,
+            Irq::Local(n) => GPU_IRQS + BASIC_IRQS + n as usize,
+        }
+    }
+
+    fn is_valid(self) -> bool {
+        match self {
+            Irq::Gpu(n) => (n as usize) < GPU_IRQS,
+            Irq::Basic(n) => (n as usize) < BASIC_IRQS,
+            // The GPU interrupt is dispatched here, and there's no way to
+            // enable the AXI one.
+            Irq::Local(n) => (n as usize) < LOCAL_IRQS && n != 8 && n != 10,
+        }
+    }
+}
+
+pub type Handler = fn();
+
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// The registered handlers, by `Irq::index`, as function pointers. Zero
+/// means there isn't one.
+static HANDLERS: [AtomicUsize; GPU_IRQS + BASIC_IRQS + LOCAL_IRQS] =
+    [NO_HANDLER; GPU_IRQS + BASIC_IRQS + LOCAL_IRQS];
+
+/// Write the bit for `n` to the first or second of a pair of registers that
+/// cover 32 sources each.
+unsafe fn write_bit(first: usize, second: usize, n: u8) {
+    if n < 32 {
+        mmio::write(first, 1 << n);
+    } else {
+        mmio::write(second, 1 << (n - 32));
+    }
+}
+
+/// Set or clear the bit for `n` in a read-modify-write register of the
+/// local controller.
+unsafe fn update_local(reg: usize, bit: u8, set: bool) {
+    let value = mmio::read(reg);
+    let value = if set {
+        value | 1 << bit
+    } else {
+        value & !(1 << bit)
+    };
+    mmio::write(reg, value);
+}
+
+/// Unmask `irq` in the interrupt controllers. It's also routed to this core
+/// if it's a local source that can only go to one core.
+pub fn enable(irq: Irq) {
+    assert!(irq.is_valid(), "No such interrupt source: {:?}", irq);
+    let core = cpu_id();
+    unsafe {
+        match irq {
+            Irq::Gpu(n) => write_bit(ENABLE_IRQS_1, ENABLE_IRQS_2, n),
+            Irq::Basic(n) => mmio::write(ENABLE_BASIC_IRQS, 1 << n),
+            Irq::Local(n @ 0..=3) => update_local(LOCAL_TIMER_CONTROL + 4 * core, n, true),
+            Irq::Local(n @ 4..=7) => update_local(LOCAL_MAILBOX_CONTROL + 4 * core, n - 4, true),
+            Irq::Local(9) => mmio::write(LOCAL_PMU_ROUTING_SET, 1 << core),
+            Irq::Local(11) => mmio::write(LOCAL_TIMER_ROUTING, core as u32),
+            Irq::Local(_) => unreachable!(),
+        }
+    }
+}
+
+/// Mask `irq` in the interrupt controllers, for this core if it's a local
+/// source.
+pub fn disable(irq: Irq) {
+    assert!(irq.is_valid(), "No such interrupt source: {:?}", irq);
+    let core = cpu_id();
+    unsafe {
+        match irq {
+            Irq::Gpu(n) => write_bit(DISABLE_IRQS_1, DISABLE_IRQS_2, n),
+            Irq::Basic(n) => mmio::write(DISABLE_BASIC_IRQS, 1 << n),
+            Irq::Local(n @ 0..=3) => update_local(LOCAL_TIMER_CONTROL + 4 * core, n, false),
+            Irq::Local(n @ 4..=7) => update_local(LOCAL_MAILBOX_CONTROL + 4 * core, n - 4, false),
+            Irq::Local(9) => mmio::write(LOCAL_PMU_ROUTING_CLEAR, 1 << core),
+            // The local timer always goes to some core; its own control
+            // register is what turns it off.
+            Irq::Local(11) => (),
+            Irq::Local(_) => unreachable!(),
+        }
+    }
+}
+
+/// Call `handler` whenever `irq` is pending, and enable it.
+pub fn register(irq: Irq, handler: Handler) {
+    assert!(irq.is_valid(), "No such interrupt source: {:?}", irq);
+    HANDLERS[irq.index()].store(handler as usize, Ordering::SeqCst);
+    enable(irq);
+}
+
+/// Disable `irq` and forget its handler.
+pub fn unregister(irq: Irq) {
+    disable(irq);
+    HANDLERS[irq.index()].store(0, Ordering::SeqCst);
+}
+
+/// Route the GPU's interrupt, which covers all the `Gpu` and `Basic`
+/// sources, to this core. It goes to core zero at reset.
+pub fn route_gpu_to_this_core() {
+    unsafe { mmio::write(LOCAL_GPU_ROUTING, cpu_id() as u32) };
+}
+
+fn handle(irq: Irq) {
+    let handler = HANDLERS[irq.index()].load(Ordering::SeqCst);
+    if handler == 0 {
+        println!("Unhandled interrupt {:?}; disabling it", irq);
+        disable(irq);
+    } else {
+        let handler: Handler = unsafe { core::mem::transmute(handler) };
+        handler();
+    }
+}
+
+/// Call `f` with the number of each bit set in `bits`.
+fn for_each_bit(mut bits: u32, mut f: impl FnMut(u8)) {
+    while bits != 0 {
+        let n = bits.trailing_zeros();
+        bits &= !(1 << n);
+        f(n as u8);
+    }
+}
+
+/// Call the handlers of all the pending sources that reach this core.
+pub fn dispatch() {
+    let core = cpu_id();
+    let local = unsafe { mmio::read(LOCAL_IRQ_SOURCE + 4 * core) };
+    let gpu_bit = 1 << 8;
+    // Only the sources that can be enabled, which leaves out the GPU's and
+    // the AXI one; `handle` can't disable the others.
+    let handled = (0..LOCAL_IRQS as u8)
+        .filter(|&n| Irq::Local(n).is_valid())
+        .fold(0, |bits, n| bits | 1 << n);
+    for_each_bit(local & handled, |n| handle(Irq::Local(n)));
+    if local & gpu_bit == 0 {
+        return;
+    }
+    let basic = unsafe { mmio::read(IRQ_BASIC_PENDING) };
+    for_each_bit(basic & 0xFF, |n| handle(Irq::Basic(n)));
+    if basic & BASIC_PENDING_1 != 0 {
+        let pending = unsafe { mmio::read(IRQ_PENDING_1) };
+        for_each_bit(pending, |n| handle(Irq::Gpu(n)));
+    }
+    if basic & BASIC_PENDING_2 != 0 {
+        let pending = unsafe { mmio::read(IRQ_PENDING_2) };
+        for_each_bit(pending, |n| handle(Irq::Gpu(32 + n)));
+    }
+}
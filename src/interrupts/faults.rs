@@ -0,0 +1,134 @@
+//! Recovering from synchronous exceptions taken by code that is allowed to
+//! make mistakes, such as words typed at the Forth prompt.
+//!
+//! `call_catching_faults` records the callee-saved registers, stack pointer
+//! and interrupt mask of its caller in a `FaultFrame` before making the
+//! call. If the callee takes a data abort, an alignment fault or hits a
+//! `BRK`, `handle_sync` points the exception return in its `TrapFrame` at
+//! `fault_frame_resume` (in `exceptions.S`), which restores those registers
+//! from the innermost frame and returns to its caller as though the callee
+//! had returned. Whatever the callee was in the middle of is abandoned
+//! without running destructors.
+//!
+//! That's only safe for the callee's own code. IRQ handlers, and code
+//! holding an `IrqMutex` or the heap's lock, run with IRQs masked, and
+//! abandoning them would leave the lock held or the interrupt half handled.
+//! So a fault taken with IRQs masked is never recovered from, and is as
+//! fatal as one outside `call_catching_faults`.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use super::{ExceptionMask, TrapFrame, DAIF_SHIFT};
+use crate::smp::this_cpu;
+
+/// A synchronous exception that was recovered from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// A data or instruction abort on accessing `address`.
+    Abort { address: usize },
+    /// A misaligned access to `address`, or a misaligned stack pointer or
+    /// branch target.
+    Alignment { address: usize },
+    /// A `BRK` instruction with the given immediate.
+    Breakpoint { comment: u16 },
+    /// Any other exception, with its syndrome. Only user programs, which
+    /// are ended by any exception, take these.
+    Unexpected { syndrome: u32 },
+}
+
+/// The caller's state saved by `fault_frame_call`. The layout is shared with
+/// `exceptions.S`.
+#[repr(C)]
+struct SavedRegisters {
+    /// `x19`-`x30`, then `sp`.
+    regs: [usize; 13],
+    /// `d8`-`d15`.
+    fp_regs: [u64; 8],
+    daif: usize,
+}
+
+pub struct FaultFrame {
+    saved: SavedRegisters,
+    /// The frame that was innermost when this one was made.
+    outer: *mut FaultFrame,
+    fault: Option<Fault>,
+}
+
+/// The innermost frame of this core, if any code on it is running under
+/// `call_catching_faults`. Each core has its own chain, as a fault on one
+/// core must not resume in a frame another one made.
+#[inline]
+fn innermost() -> &'static AtomicPtr<FaultFrame> {
+    &this_cpu().innermost_fault
+}
+
+/// The frames of code that isn't running at the moment. Each task unwinds
+/// on its own stack, so switching tasks has to switch the chain of frames
+/// as well.
+pub struct FaultFrames(*mut FaultFrame);
+
+impl FaultFrames {
+    pub const fn new() -> FaultFrames {
+        FaultFrames(ptr::null_mut())
+    }
+
+    /// Exchange these frames with those of the running code.
+    #[inline]
+    pub fn swap(&mut self) {
+        self.0 = innermost().swap(self.0, Ordering::SeqCst);
+    }
+}
+
+extern "C" {
+    fn fault_frame_call(arg: usize, func: usize, saved: *mut SavedRegisters) -> isize;
+    fn fault_frame_resume();
+}
+
+/// Call `func(arg)`, returning what it returns, or the fault it took.
+pub fn call_catching_faults<T>(
+    func: extern "C" fn(&mut T) -> isize,
+    arg: &mut T,
+) -> Result<isize, Fault> {
+    let mut frame = FaultFrame {
+        saved: SavedRegisters {
+            regs: [0; 13],
+            fp_regs: [0; 8],
+            daif: 0,
+        },
+        outer: innermost().load(Ordering::SeqCst),
+        fault: None,
+    };
+    innermost().store(&mut frame, Ordering::SeqCst);
+    let result =
+        unsafe { fault_frame_call(arg as *mut T as usize, func as usize, &mut frame.saved) };
+    innermost().store(frame.outer, Ordering::SeqCst);
+    match frame.fault {
+        Some(fault) => Err(fault),
+        None => Ok(result),
+    }
+}
+
+/// Called by `fault_frame_resume` to find the registers to return with.
+#[no_mangle]
+extern "C" fn innermost_fault_frame() -> *mut SavedRegisters {
+    unsafe { &mut (*innermost().load(Ordering::SeqCst)).saved }
+}
+
+/// Arrange for the exception being handled to return into the innermost
+/// frame, reporting `fault`. Returns false if there's no frame to return to,
+/// or the code that faulted had IRQs masked.
+pub(super) unsafe fn recover(fault: Fault, trap: &mut TrapFrame) -> bool {
+    if trap.spsr & ExceptionMask::I.bits() << DAIF_SHIFT != 0 {
+        return false;
+    }
+    let frame = match innermost().load(Ordering::SeqCst).as_mut() {
+        Some(frame) => frame,
+        None => return false,
+    };
+    frame.fault = Some(fault);
+    trap.elr = fault_frame_resume as usize;
+    true
+}
@@ -2,11 +2,12 @@ use bit_field::BitField;
 use core::fmt::{self, Write};
 use enum_repr::EnumRepr;
 
-use super::{ExceptionClass, ExceptionStatus};
-use crate::{
-    println,
-    rpi::mmio::{self, P_BASE},
+use super::{
+    controller,
+    faults::{self, Fault},
+    ExceptionClass, ExceptionStatus, FpFrame, TrapFrame,
 };
+use crate::{backtrace, println, process};
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug)]
@@ -30,40 +31,21 @@ pub enum ExceptionEntry {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn show_invalid_entry_message(entry: ExceptionEntry) -> ! {
+pub unsafe extern "C" fn show_invalid_entry_message(entry: ExceptionEntry, frame: &TrapFrame) -> ! {
     let status = ExceptionStatus::load().expect("Failed to load exception status");
 
     println!(
-        "Landed on undefined exception handler (entry {:?}), status = {:#?}",
-        entry, status
+        "Landed on undefined exception handler (entry {:?}), status = {:#?}\n{}",
+        entry, status, frame
     );
+    backtrace::print_from(Some(frame.elr), frame.x[29]);
 
     loop {}
 }
 
-const IRQ_BASIC_PENDING: usize = P_BASE + 0x0000_B200;
-const IRQ_PENDING_1: usize = P_BASE + 0x0000_B204;
-const IRQ_PENDING_2: usize = P_BASE + 0x0000_B208;
-const FIQ_CONTROL: usize = P_BASE + 0x0000_B20C;
-const ENABLE_IRQS_1: usize = P_BASE + 0x0000_B210;
-const ENABLE_IRQS_2: usize = P_BASE + 0x0000_B214;
-const ENABLE_BASIC_IRQS: usize = P_BASE + 0x0000_B218;
-const DISABLE_IRQS_1: usize = P_BASE + 0x0000_B21C;
-const DISABLE_IRQS_2: usize = P_BASE + 0x0000_B220;
-const DISABLE_BASIC_IRQS: usize = P_BASE + 0x0000_B224;
-
-const SYSTEM_TIMER_IRQ_0: u32 = 1 << 0;
-const SYSTEM_TIMER_IRQ_1: u32 = 1 << 1;
-const SYSTEM_TIMER_IRQ_2: u32 = 1 << 2;
-const SYSTEM_TIMER_IRQ_3: u32 = 1 << 3;
-
 #[no_mangle]
-pub unsafe extern "C" fn handle_irq() {
-    let irq = mmio::read(IRQ_PENDING_1);
-    match irq {
-        SYSTEM_TIMER_IRQ_1 => handle_timer_irq(),
-        _ => println!("Unknown pending irq: {:x}", irq),
-    };
+pub unsafe extern "C" fn handle_irq(_frame: &mut TrapFrame) {
+    controller::dispatch();
 }
 
 use core::sync::atomic::{AtomicUsize, Ordering};
@@ -106,16 +88,50 @@ pub enum DataFaultStatusCode {
     PageDomainFault = 0b111_110,
 }
 
+fn data_abort(status: &ExceptionStatus) -> Fault {
+    let address = status.fault_address as usize;
+    let status_code = DataFaultStatusCode::from_repr(status.iss.get_bits(0..=5) as u8);
+    match status_code {
+        Some(DataFaultStatusCode::AlignmentFault) => Fault::Alignment { address },
+        _ => Fault::Abort { address },
+    }
+}
+
+/// The fault that `status` describes, if it's one that code running under
+/// `faults::call_catching_faults` can be recovered from.
+fn recoverable_fault(status: &ExceptionStatus) -> Option<Fault> {
+    let address = status.fault_address as usize;
+    let fault = match status.exception_class {
+        Ok(ExceptionClass::DataAbortFromSame) => data_abort(status),
+        Ok(ExceptionClass::InstructionAbortFromSame) => Fault::Abort { address },
+        Ok(ExceptionClass::PCAlignmentFault) | Ok(ExceptionClass::SPAlignmentFault) => {
+            Fault::Alignment { address }
+        }
+        Ok(ExceptionClass::BRKFromAarch64) => Fault::Breakpoint {
+            comment: status.iss.get_bits(0..=15) as u16,
+        },
+        _ => return None,
+    };
+    Some(fault)
+}
+
 #[no_mangle]
-pub unsafe extern "C" fn handle_sync() {
+pub unsafe extern "C" fn handle_sync(frame: &mut TrapFrame) {
+    let status = ExceptionStatus::load().expect("Failed to load exception status");
+
+    if let Some(fault) = recoverable_fault(&status) {
+        if faults::recover(fault, frame) {
+            return;
+        }
+    }
+
     if SYNC_EXCS.fetch_add(1, Ordering::SeqCst) > 10 {
         println_semihosting!("Got too many sync exceptions!");
         loop {}
     }
 
-    let status = ExceptionStatus::load().expect("Failed to load exception status");
-
-    println_semihosting!("Got sync exception: {:#?}", status);
+    println_semihosting!("Got sync exception: {:#?}\n{}", status, frame);
+    backtrace::print_from(Some(frame.elr), frame.x[29]);
 
     match status.exception_class {
         Err(class) => println_semihosting!(
@@ -165,10 +181,43 @@ pub unsafe extern "C" fn handle_sync() {
                 status_code,
             );
         }
+        Ok(ExceptionClass::BRKFromAarch64) => {
+            // Carry on after it, rather than taking it again.
+            println_semihosting!("Got BRK #{:#x}", status.iss.get_bits(0..=15));
+            frame.skip_instruction();
+        }
         Ok(other) => {
             println_semihosting!("Got unhandled exception class {:?}: {:#?}", other, status);
         }
     }
 }
 
-unsafe fn handle_timer_irq() {}
+/// The fault that `status` describes, for an exception other than a system
+/// call taken from a user program.
+fn user_fault(status: &ExceptionStatus) -> Fault {
+    let address = status.fault_address as usize;
+    match status.exception_class {
+        Ok(ExceptionClass::DataAbortFromLower) => data_abort(status),
+        Ok(ExceptionClass::InstructionAbortFromLower) => Fault::Abort { address },
+        Ok(ExceptionClass::PCAlignmentFault) | Ok(ExceptionClass::SPAlignmentFault) => {
+            Fault::Alignment { address }
+        }
+        Ok(ExceptionClass::BRKFromAarch64) => Fault::Breakpoint {
+            comment: status.iss.get_bits(0..=15) as u16,
+        },
+        _ => Fault::Unexpected {
+            syndrome: status.exception_syndrome,
+        },
+    }
+}
+
+/// A synchronous exception from a user program: a system call, or a fault
+/// that ends it.
+#[no_mangle]
+pub unsafe extern "C" fn handle_sync_el0(frame: &mut TrapFrame, fp: &mut FpFrame) {
+    let status = ExceptionStatus::load().expect("Failed to load exception status");
+    match status.exception_class {
+        Ok(ExceptionClass::SVCFromAarch64) => process::system_call(frame, fp),
+        _ => process::fault(frame, fp, user_fault(&status)),
+    }
+}
@@ -27,12 +27,20 @@ extern crate futures;
 mod console;
 
 mod allocator;
+mod backtrace;
+mod executor;
+mod fat;
+mod forth;
 mod interrupts;
+mod math;
 mod panic_handler;
+mod process;
 mod rpi;
+mod smp;
 
 #[no_mangle]
 pub fn kernel_main() {
+    smp::init();
     println!("Hello, world!");
 
     let s = alloc::string::String::from("It's a string on the heap!");
@@ -40,6 +48,16 @@ pub fn kernel_main() {
 
     rpi::usb::init();
 
+    rpi::timer::init();
+
+    let cpus = smp::start_secondaries();
+    println!("{} of {} cores online", cpus, smp::MAX_CPUS);
+
+    let executor_works = executor::self_test();
+    if !executor_works {
+        println!("Executor self-test failed: a spawned task wasn't woken by its timer");
+    }
+
     // qemu_exit::aarch64::exit_success();
 
     // use rpi::framebuffer::{Framebuffer, Pixel};
@@ -67,7 +85,16 @@ pub fn kernel_main() {
     //     // }
     // }
 
-    loop {}
+    #[cfg(feature = "forth-tests")]
+    {
+        if !executor_works {
+            rpi::uart::UART::new().flush();
+            qemu_exit::aarch64::exit_failure();
+        }
+        forth::run_tests();
+    }
+
+    forth::run();
 
     qemu_exit::aarch64::exit_success()
 }
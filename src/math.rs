@@ -0,0 +1,524 @@
+//! Elementary functions on `f64`, which `core` doesn't provide. The square
+//! root and rounding use the FP unit's own instructions; everything else is
+//! a port of the algorithms and coefficients in FreeBSD's msun (fdlibm),
+//! accurate to within an ulp or two, except that `pow` loses a few bits for
+//! large non-integer powers.
+//!
+//! Trigonometric arguments are reduced with a three-part approximation of
+//! pi/2, which is accurate while the argument is below about 2^20 * pi/2.
+//! Beyond that the results lose precision gradually.
+
+const TWO_POW_1023: f64 = 8.98846567431158e307;
+const TWO_POW_MINUS_1022: f64 = 2.2250738585072014e-308;
+const TWO_POW_53: f64 = 9007199254740992.0;
+const TWO_POW_54: f64 = 18014398509481984.0;
+
+const LN2_HI: f64 = 6.93147180369123816490e-01;
+const LN2_LO: f64 = 1.90821492927058770002e-10;
+const LN2: f64 = 6.93147180559945286227e-01;
+const LN10: f64 = 2.30258509299404590109e+00;
+const PI: f64 = 3.14159265358979311600e+00;
+const PI_LO: f64 = 1.22464679914735317720e-16;
+const FRAC_PI_2: f64 = 1.57079632679489655800e+00;
+
+#[inline]
+pub fn abs(x: f64) -> f64 {
+    f64::from_bits(x.to_bits() & !(1 << 63))
+}
+
+#[inline]
+fn copysign(x: f64, sign: f64) -> f64 {
+    f64::from_bits((x.to_bits() & !(1 << 63)) | (sign.to_bits() & (1 << 63)))
+}
+
+/// The high 32 bits of `x`, which hold the sign, exponent and the top of the
+/// significand.
+#[inline]
+fn high_word(x: f64) -> u32 {
+    (x.to_bits() >> 32) as u32
+}
+
+#[inline]
+fn with_high_word(x: f64, high: u32) -> f64 {
+    f64::from_bits((high as u64) << 32 | (x.to_bits() & 0xFFFF_FFFF))
+}
+
+pub fn sqrt(x: f64) -> f64 {
+    let result: f64;
+    unsafe { asm!("fsqrt $0, $1" : "=w"(result) : "w"(x)) };
+    result
+}
+
+/// Round towards negative infinity.
+pub fn floor(x: f64) -> f64 {
+    let result: f64;
+    unsafe { asm!("frintm $0, $1" : "=w"(result) : "w"(x)) };
+    result
+}
+
+/// Round towards zero.
+pub fn trunc(x: f64) -> f64 {
+    let result: f64;
+    unsafe { asm!("frintz $0, $1" : "=w"(result) : "w"(x)) };
+    result
+}
+
+/// Round to the nearest integer, ties to even.
+pub fn round(x: f64) -> f64 {
+    let result: f64;
+    unsafe { asm!("frintn $0, $1" : "=w"(result) : "w"(x)) };
+    result
+}
+
+/// `x * 2^n`, without losing precision to intermediate overflow or
+/// underflow.
+pub fn scalbn(mut x: f64, mut n: i32) -> f64 {
+    if n > 1023 {
+        x *= TWO_POW_1023;
+        n -= 1023;
+        if n > 1023 {
+            x *= TWO_POW_1023;
+            n = (n - 1023).min(1023);
+        }
+    } else if n < -1022 {
+        // Scale by a little less than 2^-1022, so that the final step rounds
+        // only once.
+        x *= TWO_POW_MINUS_1022 * TWO_POW_53;
+        n += 1022 - 53;
+        if n < -1022 {
+            x *= TWO_POW_MINUS_1022 * TWO_POW_53;
+            n = (n + 1022 - 53).max(-1022);
+        }
+    }
+    x * f64::from_bits(((0x3FF + n) as u64) << 52)
+}
+
+pub fn exp(x: f64) -> f64 {
+    const OVERFLOW: f64 = 7.09782712893383973096e+02;
+    const UNDERFLOW: f64 = -7.45133219101941108420e+02;
+    const INV_LN2: f64 = 1.44269504088896338700e+00;
+    const P1: f64 = 1.66666666666666019037e-01;
+    const P2: f64 = -2.77777777770155933842e-03;
+    const P3: f64 = 6.61375632143793436117e-05;
+    const P4: f64 = -1.65339022054652515390e-06;
+    const P5: f64 = 4.13813679705723846039e-08;
+
+    if x.is_nan() {
+        return x;
+    }
+    if x > OVERFLOW {
+        return f64::INFINITY;
+    }
+    if x < UNDERFLOW {
+        return 0.0;
+    }
+    if abs(x) < 3.725290298461914e-09 {
+        // 2^-28
+        return 1.0 + x;
+    }
+    // x = k ln2 + r, |r| <= ln2 / 2
+    let k = (x * INV_LN2 + copysign(0.5, x)) as i32;
+    let hi = x - k as f64 * LN2_HI;
+    let lo = k as f64 * LN2_LO;
+    let r = hi - lo;
+    let t = r * r;
+    let c = r - t * (P1 + t * (P2 + t * (P3 + t * (P4 + t * P5))));
+    let y = 1.0 - ((lo - (r * c) / (2.0 - c)) - hi);
+    scalbn(y, k)
+}
+
+/// `exp(x) - 1`, accurate for small `x`.
+pub fn exp_m1(x: f64) -> f64 {
+    let u = exp(x);
+    if u == 1.0 {
+        return x;
+    }
+    let um1 = u - 1.0;
+    if um1 == -1.0 || u.is_infinite() {
+        return um1;
+    }
+    // The rounding error in `u` cancels out of the ratio.
+    um1 * x / ln(u)
+}
+
+/// The natural logarithm.
+pub fn ln(mut x: f64) -> f64 {
+    const LG1: f64 = 6.666666666666735130e-01;
+    const LG2: f64 = 3.999999999940941908e-01;
+    const LG3: f64 = 2.857142874366239149e-01;
+    const LG4: f64 = 2.222219843214978396e-01;
+    const LG5: f64 = 1.818357216161805012e-01;
+    const LG6: f64 = 1.531383769920937332e-01;
+    const LG7: f64 = 1.479819860511658591e-01;
+
+    let mut k = 0;
+    let mut hx = high_word(x);
+    if x.is_nan() || x < 0.0 {
+        return f64::NAN;
+    }
+    if x == 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if x.is_infinite() {
+        return x;
+    }
+    if hx < 0x0010_0000 {
+        // Subnormal: scale it up.
+        k -= 54;
+        x *= TWO_POW_54;
+        hx = high_word(x);
+    }
+    k += (hx >> 20) as i32 - 1023;
+    hx &= 0x000F_FFFF;
+    // Normalize x or x / 2 to between sqrt(2) / 2 and sqrt(2).
+    let i = (hx + 0x95F64) & 0x10_0000;
+    x = with_high_word(x, hx | (i ^ 0x3FF0_0000));
+    k += (i >> 20) as i32;
+    let f = x - 1.0;
+    let hfsq = 0.5 * f * f;
+    let s = f / (2.0 + f);
+    let z = s * s;
+    let w = z * z;
+    let t1 = w * (LG2 + w * (LG4 + w * LG6));
+    let t2 = z * (LG1 + w * (LG3 + w * (LG5 + w * LG7)));
+    let r = t2 + t1;
+    let dk = k as f64;
+    s * (hfsq + r) + dk * LN2_LO - hfsq + f + dk * LN2_HI
+}
+
+/// `ln(1 + x)`, accurate for small `x`.
+pub fn ln_1p(x: f64) -> f64 {
+    let u = 1.0 + x;
+    if u == 1.0 {
+        return x;
+    }
+    if u.is_infinite() {
+        return u;
+    }
+    // The rounding error in `u` cancels out of the ratio.
+    ln(u) * x / (u - 1.0)
+}
+
+pub fn log10(x: f64) -> f64 {
+    ln(x) / LN10
+}
+
+/// `x` to the power `y`.
+pub fn pow(x: f64, y: f64) -> f64 {
+    if y == 0.0 || x == 1.0 {
+        return 1.0;
+    }
+    if x.is_nan() || y.is_nan() {
+        return f64::NAN;
+    }
+    let integer = trunc(y) == y;
+    // Whether y is an odd integer, so that a negative x keeps its sign.
+    let odd = integer && abs(y) < TWO_POW_53 && (y as i64) & 1 == 1;
+    if x == 0.0 {
+        let result = if y < 0.0 { f64::INFINITY } else { 0.0 };
+        return if odd { copysign(result, x) } else { result };
+    }
+    if x < 0.0 && !integer {
+        return f64::NAN;
+    }
+    // Small integer powers are more accurate done by squaring.
+    if integer && abs(y) <= 64.0 {
+        let mut n = abs(y) as u32;
+        let mut base = x;
+        let mut result = 1.0;
+        while n > 0 {
+            if n & 1 == 1 {
+                result *= base;
+            }
+            base *= base;
+            n >>= 1;
+        }
+        return if y < 0.0 { 1.0 / result } else { result };
+    }
+    let result = exp(y * ln(abs(x)));
+    if x < 0.0 && odd {
+        -result
+    } else {
+        result
+    }
+}
+
+/// Reduce `x` to `y[0] + y[1]` in [-pi/4, pi/4], returning the number of
+/// quarter turns taken off, modulo four.
+fn rem_pio2(x: f64) -> (i32, f64, f64) {
+    const TO_INT: f64 = 6755399441055744.0;
+    const INV_PIO2: f64 = 6.36619772367581382433e-01;
+    const PIO2_1: f64 = 1.57079632673412561417e+00;
+    const PIO2_1T: f64 = 6.07710050650619224932e-11;
+    const PIO2_2: f64 = 6.07710050630396597660e-11;
+    const PIO2_2T: f64 = 2.02226624879595063154e-21;
+    const PIO2_3: f64 = 2.02226624871116645580e-21;
+    const PIO2_3T: f64 = 8.47842766036889956997e-32;
+
+    let exponent = |x: f64| ((x.to_bits() >> 52) & 0x7FF) as i32;
+    let n = x * INV_PIO2 + TO_INT - TO_INT;
+    let mut r = x - n * PIO2_1;
+    let mut w = n * PIO2_1T;
+    let mut y0 = r - w;
+    let ex = exponent(x);
+    if ex - exponent(y0) > 16 {
+        // Cancellation: take off more bits of pi/2.
+        let t = r;
+        w = n * PIO2_2;
+        r = t - w;
+        w = n * PIO2_2T - ((t - r) - w);
+        y0 = r - w;
+        if ex - exponent(y0) > 49 {
+            let t = r;
+            w = n * PIO2_3;
+            r = t - w;
+            w = n * PIO2_3T - ((t - r) - w);
+            y0 = r - w;
+        }
+    }
+    let y1 = (r - y0) - w;
+    // Past 2^53 the quadrant is lost anyway, and converting `n` would
+    // overflow.
+    let quadrant = if abs(n) < TWO_POW_53 { n as i64 & 3 } else { 0 };
+    (quadrant as i32, y0, y1)
+}
+
+/// sin(x + y) for |x + y| <= pi/4, where y is the tail of x.
+fn kernel_sin(x: f64, y: f64) -> f64 {
+    const S1: f64 = -1.66666666666666324348e-01;
+    const S2: f64 = 8.33333333332248946124e-03;
+    const S3: f64 = -1.98412698298579493134e-04;
+    const S4: f64 = 2.75573137070700676789e-06;
+    const S5: f64 = -2.50507602534068634195e-08;
+    const S6: f64 = 1.58969099521155010221e-10;
+
+    let z = x * x;
+    let w = z * z;
+    let r = S2 + z * (S3 + z * S4) + z * w * (S5 + z * S6);
+    let v = z * x;
+    x - ((z * (0.5 * y - v * r) - y) - v * S1)
+}
+
+/// cos(x + y) for |x + y| <= pi/4, where y is the tail of x.
+fn kernel_cos(x: f64, y: f64) -> f64 {
+    const C1: f64 = 4.16666666666666019037e-02;
+    const C2: f64 = -1.38888888888741095749e-03;
+    const C3: f64 = 2.48015872894767294178e-05;
+    const C4: f64 = -2.75573143513906633035e-07;
+    const C5: f64 = 2.08757232129817482790e-09;
+    const C6: f64 = -1.13596475577881948265e-11;
+
+    let z = x * x;
+    let w = z * z;
+    let r = z * (C1 + z * (C2 + z * C3)) + w * w * (C4 + z * (C5 + z * C6));
+    let hz = 0.5 * z;
+    let w = 1.0 - hz;
+    w + (((1.0 - w) - hz) + (z * r - x * y))
+}
+
+/// Both the sine and cosine of `x`.
+pub fn sin_cos(x: f64) -> (f64, f64) {
+    if !x.is_finite() {
+        return (f64::NAN, f64::NAN);
+    }
+    if abs(x) <= 0.7853981633974483 {
+        return (kernel_sin(x, 0.0), kernel_cos(x, 0.0));
+    }
+    let (n, y0, y1) = rem_pio2(x);
+    let (s, c) = (kernel_sin(y0, y1), kernel_cos(y0, y1));
+    match n {
+        0 => (s, c),
+        1 => (c, -s),
+        2 => (-s, -c),
+        _ => (-c, s),
+    }
+}
+
+pub fn sin(x: f64) -> f64 {
+    sin_cos(x).0
+}
+
+pub fn cos(x: f64) -> f64 {
+    sin_cos(x).1
+}
+
+pub fn tan(x: f64) -> f64 {
+    let (s, c) = sin_cos(x);
+    s / c
+}
+
+pub fn atan(x: f64) -> f64 {
+    const ATAN_HI: [f64; 4] = [
+        4.63647609000806093515e-01,
+        7.85398163397448278999e-01,
+        9.82793723247329054082e-01,
+        1.57079632679489655800e+00,
+    ];
+    const ATAN_LO: [f64; 4] = [
+        2.26987774529616870924e-17,
+        3.06161699786838301793e-17,
+        1.39033110312309984516e-17,
+        6.12323399573676603587e-17,
+    ];
+    const AT: [f64; 11] = [
+        3.33333333333329318027e-01,
+        -1.99999999998764832476e-01,
+        1.42857142725034663711e-01,
+        -1.11111104054623557880e-01,
+        9.09088713343650656196e-02,
+        -7.69187620504482999495e-02,
+        6.66107313738753120669e-02,
+        -5.83357013379057348645e-02,
+        4.97687799461593236017e-02,
+        -3.65315727442169155270e-02,
+        1.62858201153657823623e-02,
+    ];
+
+    if x.is_nan() {
+        return x;
+    }
+    let sign = x;
+    let ax = abs(x);
+    if ax >= 7.378697629483821e19 {
+        // 2^66
+        return copysign(ATAN_HI[3] + ATAN_LO[3], sign);
+    }
+    // Reduce the argument to near zero, using atan(x) = atan(c) + atan((x -
+    // c) / (1 + xc)) for one of four points c.
+    let (id, x) = if ax < 0.4375 {
+        if ax < 7.450580596923828e-09 {
+            // 2^-27
+            return x;
+        }
+        (None, x)
+    } else if ax < 0.6875 {
+        (Some(0), (2.0 * ax - 1.0) / (2.0 + ax))
+    } else if ax < 1.1875 {
+        (Some(1), (ax - 1.0) / (ax + 1.0))
+    } else if ax < 2.4375 {
+        (Some(2), (ax - 1.5) / (1.0 + 1.5 * ax))
+    } else {
+        (Some(3), -1.0 / ax)
+    };
+    let z = x * x;
+    let w = z * z;
+    let s1 = z * (AT[0] + w * (AT[2] + w * (AT[4] + w * (AT[6] + w * (AT[8] + w * AT[10])))));
+    let s2 = w * (AT[1] + w * (AT[3] + w * (AT[5] + w * (AT[7] + w * AT[9]))));
+    match id {
+        None => x - x * (s1 + s2),
+        Some(id) => {
+            let z = ATAN_HI[id] - ((x * (s1 + s2) - ATAN_LO[id]) - x);
+            copysign(z, sign)
+        }
+    }
+}
+
+/// The angle of the point `(x, y)` from the positive x axis, in (-pi, pi].
+pub fn atan2(y: f64, x: f64) -> f64 {
+    if x.is_nan() || y.is_nan() {
+        return f64::NAN;
+    }
+    let x_negative = x.is_sign_negative();
+    if y == 0.0 {
+        return if x_negative { copysign(PI, y) } else { y };
+    }
+    if x == 0.0 {
+        return copysign(FRAC_PI_2, y);
+    }
+    if x.is_infinite() {
+        let angle = if y.is_infinite() {
+            if x_negative {
+                3.0 * PI / 4.0
+            } else {
+                PI / 4.0
+            }
+        } else if x_negative {
+            PI
+        } else {
+            0.0
+        };
+        return copysign(angle, y);
+    }
+    let z = atan(abs(y / x));
+    let angle = if x_negative { PI - (z - PI_LO) } else { z };
+    copysign(angle, y)
+}
+
+pub fn asin(x: f64) -> f64 {
+    if abs(x) > 1.0 {
+        return f64::NAN;
+    }
+    atan2(x, sqrt((1.0 - x) * (1.0 + x)))
+}
+
+pub fn acos(x: f64) -> f64 {
+    if abs(x) > 1.0 {
+        return f64::NAN;
+    }
+    atan2(sqrt((1.0 - x) * (1.0 + x)), x)
+}
+
+pub fn sinh(x: f64) -> f64 {
+    let ax = abs(x);
+    if ax > 22.0 {
+        // e^-|x| no longer matters, and halving first avoids overflowing
+        // early.
+        let half = exp(0.5 * ax);
+        return copysign(0.5 * half * half, x);
+    }
+    let t = exp_m1(ax);
+    copysign(0.5 * (t + t / (t + 1.0)), x)
+}
+
+pub fn cosh(x: f64) -> f64 {
+    let ax = abs(x);
+    if ax > 22.0 {
+        let half = exp(0.5 * ax);
+        return 0.5 * half * half;
+    }
+    let t = exp(ax);
+    0.5 * (t + 1.0 / t)
+}
+
+pub fn tanh(x: f64) -> f64 {
+    let ax = abs(x);
+    if ax.is_nan() {
+        return x;
+    }
+    if ax > 22.0 {
+        return copysign(1.0, x);
+    }
+    let t = exp_m1(2.0 * ax);
+    copysign(t / (t + 2.0), x)
+}
+
+pub fn asinh(x: f64) -> f64 {
+    let ax = abs(x);
+    let result = if ax > 2.68435456e8 {
+        // 2^28: x^2 + 1 is x^2.
+        ln(ax) + LN2
+    } else {
+        let x2 = ax * ax;
+        ln_1p(ax + x2 / (1.0 + sqrt(1.0 + x2)))
+    };
+    copysign(result, x)
+}
+
+pub fn acosh(x: f64) -> f64 {
+    if x < 1.0 || x.is_nan() {
+        return f64::NAN;
+    }
+    if x > 2.68435456e8 {
+        return ln(x) + LN2;
+    }
+    let t = x - 1.0;
+    ln_1p(t + sqrt(2.0 * t + t * t))
+}
+
+pub fn atanh(x: f64) -> f64 {
+    let ax = abs(x);
+    if ax > 1.0 || x.is_nan() {
+        return f64::NAN;
+    }
+    copysign(0.5 * ln_1p(2.0 * ax / (1.0 - ax)), x)
+}
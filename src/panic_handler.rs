@@ -31,6 +31,8 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         let _ = writeln!(out, "No message available");
     }
+    crate::backtrace::print();
+    crate::rpi::uart::UART::new().flush();
 
     #[cfg(feature = "semihosting")]
     qemu_exit::aarch64::exit_failure();
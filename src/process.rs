@@ -0,0 +1,164 @@
+//! User programs, which run at EL0 in address spaces of their own and can
+//! only reach the rest of the system through system calls.
+//!
+//! A program is a flat binary, loaded read-only at `USER_START` and started
+//! at its first byte, with a stack below `USER_END`. `Process::run` runs it
+//! until it exits, faults, or makes a system call that has to wait, such as
+//! `sleep`. Whoever runs it decides what to do in the meantime, then calls
+//! `run` again to carry on; the Forth word `RUN-USER` lets the other tasks
+//! run.
+//!
+//! A program makes a system call with `svc #0`, with the call's number in
+//! `x8` and its arguments from `x0` (see `syscalls`). The result comes back
+//! in `x0`, and the other registers are preserved.
+
+use core::{sync::atomic::Ordering, time::Duration};
+
+use crate::{
+    interrupts::{faults::Fault, FpFrame, TrapFrame},
+    rpi::mmu::{Access, AddressSpace, MapError, PAGE_SIZE, USER_END, USER_START},
+    smp::this_cpu,
+};
+
+mod syscalls;
+
+/// The size in bytes of a program's stack.
+const STACK_SIZE: usize = 64 * 1024;
+
+/// Why `Process::run` returned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// The program exited with the given status, and can't be run again.
+    Exited(isize),
+    /// The program is sleeping for the given time.
+    Sleeping(Duration),
+    /// The program is waiting for input.
+    Waiting,
+    /// The program took a fault, and can't be run again.
+    Faulted(Fault),
+}
+
+/// The kernel's state when it entered a program, as `user_enter` saves it.
+/// The layout is shared with `exceptions.S`.
+#[repr(C)]
+struct KernelContext {
+    /// `x19`-`x30`, then `sp`.
+    regs: [usize; 13],
+    /// `d8`-`d15`.
+    fp_regs: [u64; 8],
+    daif: usize,
+}
+
+pub struct Process {
+    space: AddressSpace,
+    /// The program's registers while it isn't running.
+    frame: TrapFrame,
+    fp: FpFrame,
+    kernel: KernelContext,
+    /// Where `mmap` maps memory next.
+    next_map: usize,
+    /// Set by the exception handler that stops the program.
+    stop: Option<Stop>,
+}
+
+extern "C" {
+    fn user_enter(frame: *const TrapFrame, fp: *const FpFrame, kernel: *mut KernelContext);
+    fn user_return(kernel: *const KernelContext) -> !;
+}
+
+/// `len` rounded up to a whole number of pages.
+fn pages(len: usize) -> usize {
+    (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+impl Process {
+    /// Load a program from `code`.
+    pub fn new(code: &[u8]) -> Result<Process, MapError> {
+        let code_end = USER_START + pages(code.len().max(1));
+        let mut space = AddressSpace::new();
+        space.map(USER_START..code_end, Access::ReadExecute, code)?;
+        space.map(USER_END - STACK_SIZE..USER_END, Access::ReadWrite, &[])?;
+        Ok(Process {
+            space,
+            frame: TrapFrame {
+                x: [0; 31],
+                sp: USER_END,
+                elr: USER_START,
+                // EL0, with no exceptions masked.
+                spsr: 0,
+            },
+            fp: FpFrame::zero(),
+            kernel: KernelContext {
+                regs: [0; 13],
+                fp_regs: [0; 8],
+                daif: 0,
+            },
+            next_map: code_end,
+            stop: None,
+        })
+    }
+
+    /// The address of the next instruction the program will run.
+    pub fn pc(&self) -> usize {
+        self.frame.elr
+    }
+
+    /// Run the program until it stops.
+    pub fn run(&mut self) -> Stop {
+        if let Some(stop @ Stop::Exited(_)) | Some(stop @ Stop::Faulted(_)) = self.stop {
+            return stop;
+        }
+        unsafe {
+            let previous = self.space.activate();
+            let current = &this_cpu().current_process;
+            let outer = current.swap(self, Ordering::SeqCst);
+            user_enter(&self.frame, &self.fp, &mut self.kernel);
+            current.store(outer, Ordering::SeqCst);
+            AddressSpace::deactivate(previous);
+        }
+        let stop = self.stop.expect("A user program stopped without a reason");
+        if let Stop::Sleeping(_) | Stop::Waiting = stop {
+            self.stop = None;
+        }
+        stop
+    }
+
+    /// Save the program's registers and return from `run`.
+    unsafe fn leave(&mut self, frame: &TrapFrame, fp: &FpFrame, stop: Stop) -> ! {
+        self.frame = frame.clone();
+        self.fp = fp.clone();
+        self.stop = Some(stop);
+        user_return(&self.kernel)
+    }
+}
+
+unsafe fn current() -> &'static mut Process {
+    this_cpu()
+        .current_process
+        .load(Ordering::SeqCst)
+        .as_mut()
+        .expect("Exception from EL0 with no process running")
+}
+
+/// Handle the `svc` that a program took the exception `frame` for.
+pub unsafe fn system_call(frame: &mut TrapFrame, fp: &FpFrame) {
+    let process = current();
+    match syscalls::dispatch(process, frame) {
+        Ok(syscalls::Return::Value(value)) => frame.x[0] = value,
+        Ok(syscalls::Return::Stop(stop)) => {
+            frame.x[0] = 0;
+            process.leave(frame, fp, stop);
+        }
+        Ok(syscalls::Return::Retry(stop)) => {
+            // Back to the `svc`, which the exception return is just after.
+            frame.elr -= 4;
+            process.leave(frame, fp, stop);
+        }
+        Err(error) => frame.x[0] = -(error as isize) as usize,
+    }
+}
+
+/// End the program that took `fault`.
+pub unsafe fn fault(frame: &TrapFrame, fp: &FpFrame, fault: Fault) -> ! {
+    current().leave(frame, fp, Stop::Faulted(fault))
+}
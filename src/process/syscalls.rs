@@ -0,0 +1,121 @@
+//! The system calls, numbered by their place in `SYSTEM_CALLS`:
+//!
+//! | `x8` | Call    | Arguments        | Result                        |
+//! |------|---------|------------------|-------------------------------|
+//! | 0    | `exit`  | status           | doesn't return                |
+//! | 1    | `write` | fd, address, len | bytes written                 |
+//! | 2    | `read`  | fd, address, len | bytes read                    |
+//! | 3    | `sleep` | milliseconds     | 0                             |
+//! | 4    | `mmap`  | len              | address of `len` zeroed bytes |
+//!
+//! `read` reads the UART as file descriptor 0, waiting until at least one
+//! byte has arrived, and `write` writes to the console as 1 and 2. Errors
+//! are returned negated, with the numbers Linux uses.
+
+use alloc::string::String;
+use core::{fmt::Write, slice, time::Duration};
+
+use super::{pages, Process, Stop};
+use crate::{
+    interrupts::TrapFrame,
+    rpi::{
+        mmu::{Access, USER_END, USER_START},
+        uart::UART,
+    },
+};
+
+#[repr(isize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    BadDescriptor = 9,
+    OutOfMemory = 12,
+    BadAddress = 14,
+    InvalidArgument = 22,
+    NoSuchCall = 38,
+}
+
+/// What to do after a system call.
+pub enum Return {
+    /// Carry on, with this in `x0`.
+    Value(usize),
+    /// Stop the program. It carries on after the call when it's run again.
+    Stop(Stop),
+    /// Stop the program, and make the call again when it's run again.
+    Retry(Stop),
+}
+
+type SystemCall = fn(&mut Process, &[usize; 6]) -> Result<Return, Error>;
+
+const SYSTEM_CALLS: &[SystemCall] = &[exit, write, read, sleep, mmap];
+
+pub fn dispatch(process: &mut Process, frame: &TrapFrame) -> Result<Return, Error> {
+    let call = SYSTEM_CALLS.get(frame.x[8]).ok_or(Error::NoSuchCall)?;
+    let mut args = [0; 6];
+    args.copy_from_slice(&frame.x[..6]);
+    call(process, &args)
+}
+
+fn exit(_process: &mut Process, args: &[usize; 6]) -> Result<Return, Error> {
+    Ok(Return::Stop(Stop::Exited(args[0] as isize)))
+}
+
+fn write(process: &mut Process, args: &[usize; 6]) -> Result<Return, Error> {
+    let (fd, addr, len) = (args[0], args[1], args[2]);
+    if fd != 1 && fd != 2 {
+        return Err(Error::BadDescriptor);
+    }
+    if !process.space.contains(addr, len, false) {
+        return Err(Error::BadAddress);
+    }
+    let bytes = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+    let _ = crate::console::output().write_str(&String::from_utf8_lossy(bytes));
+    Ok(Return::Value(len))
+}
+
+fn read(process: &mut Process, args: &[usize; 6]) -> Result<Return, Error> {
+    let (fd, addr, len) = (args[0], args[1], args[2]);
+    if fd != 0 {
+        return Err(Error::BadDescriptor);
+    }
+    if !process.space.contains(addr, len, true) {
+        return Err(Error::BadAddress);
+    }
+    if len == 0 {
+        return Ok(Return::Value(0));
+    }
+    let buffer = unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) };
+    let mut uart = UART::new();
+    let mut count = 0;
+    while count < len {
+        match uart.try_read_byte() {
+            Some(byte) => buffer[count] = byte,
+            None => break,
+        }
+        count += 1;
+    }
+    if count == 0 {
+        return Ok(Return::Retry(Stop::Waiting));
+    }
+    Ok(Return::Value(count))
+}
+
+fn sleep(_process: &mut Process, args: &[usize; 6]) -> Result<Return, Error> {
+    let ms = args[0] as u64;
+    Ok(Return::Stop(Stop::Sleeping(Duration::from_millis(ms))))
+}
+
+fn mmap(process: &mut Process, args: &[usize; 6]) -> Result<Return, Error> {
+    let len = args[0];
+    if len == 0 || len > USER_END - USER_START {
+        return Err(Error::InvalidArgument);
+    }
+    // The space between the program and its stack is handed out in order.
+    let start = process.next_map;
+    let end = start + pages(len);
+    process
+        .space
+        .map(start..end, Access::ReadWrite, &[])
+        .map_err(|_| Error::OutOfMemory)?;
+    process.next_map = end;
+    Ok(Return::Value(start))
+}
@@ -1,8 +1,10 @@
 use core::fmt;
 use font8x8;
-use spin::Mutex;
 
-use crate::rpi::framebuffer::{Framebuffer, Pixel};
+use crate::{
+    interrupts::IrqMutex,
+    rpi::framebuffer::{Framebuffer, Pixel},
+};
 
 #[derive(Clone, Debug)]
 struct CellOffset {
@@ -10,6 +12,8 @@ struct CellOffset {
     offset_y_cells: u32,
     screen_width_cells: u32,
     screen_height_cells: u32,
+    /// Whether the cursor is currently drawn at the offset.
+    cursor_visible: bool,
 }
 
 impl CellOffset {
@@ -53,6 +57,18 @@ impl CellOffset {
         (self.offset_x_cells * 8, self.offset_y_cells * 8)
     }
 
+    /// Step back one cell, to the end of the previous line if at the start
+    /// of one.
+    #[inline]
+    fn retreat(&mut self) {
+        if self.offset_x_cells > 0 {
+            self.offset_x_cells -= 1;
+        } else if self.offset_y_cells > 0 {
+            self.offset_x_cells = self.screen_width_cells - 1;
+            self.offset_y_cells -= 1;
+        }
+    }
+
     #[inline]
     fn move_to_line_start(&mut self) {
         self.offset_x_cells = 0;
@@ -89,11 +105,12 @@ impl CellOffset {
     }
 }
 
-static CELL_OFFSET: Mutex<CellOffset> = Mutex::new(CellOffset {
+static CELL_OFFSET: IrqMutex<CellOffset> = IrqMutex::new(CellOffset {
     offset_x_cells: 0,
     offset_y_cells: 0,
     screen_width_cells: 640 / 8,
     screen_height_cells: 480 / 8,
+    cursor_visible: false,
 });
 
 fn lookup_codepoint(c: u32) -> Option<[u8; 8]> {
@@ -167,21 +184,25 @@ fn draw_char(fb: &mut Framebuffer, pixel_x: u32, pixel_y: u32, bytes: [u8; 8]) {
     }
 }
 
+fn invert_cell(fb: &mut Framebuffer, pixel_x: u32, pixel_y: u32) {
+    for y in pixel_y..pixel_y + 8 {
+        for x in pixel_x..pixel_x + 8 {
+            let Pixel { r, g, b } = fb[(x, y)];
+            fb[(x, y)] = Pixel::from((!r, !g, !b));
+        }
+    }
+}
+
 pub fn write_char(c: char) {
     Framebuffer::with(|fb| {
         let mut cell_offset = CELL_OFFSET.lock();
-        {
-            while cell_offset.needs_scroll() {
-                cell_offset.scroll_down(fb, 1);
-            }
-            const EMPTY: [u8; 8] = [0; 8];
-            // println!(
-            //     "Clearing cell at {:?} (pixel coords: {:?})",
-            //     cell_offset.cell_xy(),
-            //     cell_offset.top_left_pixel_xy()
-            // );
+        while cell_offset.needs_scroll() {
+            cell_offset.scroll_down(fb, 1);
+        }
+        if cell_offset.cursor_visible {
             let (x, y) = cell_offset.top_left_pixel_xy();
-            draw_char(fb, x, y, EMPTY);
+            invert_cell(fb, x, y);
+            cell_offset.cursor_visible = false;
         }
         if c == '\r' {
             cell_offset.move_to_line_start();
@@ -189,6 +210,10 @@ pub fn write_char(c: char) {
             cell_offset.move_to_line_start();
             cell_offset.move_to_next_line();
             return;
+        } else if c == '\x08' {
+            // Backspace moves the cursor without erasing, as on a terminal.
+            cell_offset.update_from_fb(fb);
+            cell_offset.retreat();
         } else {
             let codepoint = c as u32;
             let bytes: [u8; 8] = match lookup_codepoint(codepoint) {
@@ -215,10 +240,10 @@ pub fn write_char(c: char) {
         while cell_offset.needs_scroll() {
             cell_offset.scroll_down(fb, 1);
         }
-        {
-            const BLOCK: [u8; 8] = [0xFF; 8];
-            let (x, y) = cell_offset.top_left_pixel_xy();
-            draw_char(fb, x, y, BLOCK);
-        }
+        // The cursor inverts the cell under it, so that moving it back over
+        // a line being edited doesn't hide the text.
+        let (x, y) = cell_offset.top_left_pixel_xy();
+        invert_cell(fb, x, y);
+        cell_offset.cursor_visible = true;
     });
 }
@@ -0,0 +1,408 @@
+//! A polling driver for the SD card slot, through the Arasan SDHCI
+//! controller (which the BCM2837 docs call EMMC). It supports reading and
+//! writing single 512 byte sectors, which is all the Forth block words need.
+//!
+//! The initialization sequence follows the SD Host Controller and SD Physical
+//! Layer simplified specifications, and works both on hardware and in QEMU
+//! with `-drive if=sd`.
+
+use core::fmt;
+use spin::Mutex;
+
+use crate::rpi::{
+    gpio::{self, Pull},
+    mmio::{self, P_BASE},
+    timer::delay_us,
+};
+
+pub const SECTOR_SIZE: usize = 512;
+
+const GPIO_BASE: usize = P_BASE + 0x0020_0000;
+const GPIO_FSEL4: usize = GPIO_BASE + 0x10;
+const GPIO_FSEL5: usize = GPIO_BASE + 0x14;
+const GPIO_HEN1: usize = GPIO_BASE + 0x68;
+
+const EMMC_BASE: usize = P_BASE + 0x0030_0000;
+const EMMC_BLKSIZECNT: usize = EMMC_BASE + 0x04;
+const EMMC_ARG1: usize = EMMC_BASE + 0x08;
+const EMMC_CMDTM: usize = EMMC_BASE + 0x0C;
+const EMMC_RESP0: usize = EMMC_BASE + 0x10;
+const EMMC_DATA: usize = EMMC_BASE + 0x20;
+const EMMC_STATUS: usize = EMMC_BASE + 0x24;
+const EMMC_CONTROL0: usize = EMMC_BASE + 0x28;
+const EMMC_CONTROL1: usize = EMMC_BASE + 0x2C;
+const EMMC_INTERRUPT: usize = EMMC_BASE + 0x30;
+const EMMC_INT_MASK: usize = EMMC_BASE + 0x34;
+const EMMC_INT_EN: usize = EMMC_BASE + 0x38;
+const EMMC_SLOTISR_VER: usize = EMMC_BASE + 0xFC;
+
+// Commands, as written to CMDTM: the index in the top byte, then the response
+// type and data transfer flags.
+const CMD_GO_IDLE: u32 = 0x0000_0000;
+const CMD_ALL_SEND_CID: u32 = 0x0201_0000;
+const CMD_SEND_REL_ADDR: u32 = 0x0302_0000;
+const CMD_CARD_SELECT: u32 = 0x0703_0000;
+const CMD_SEND_IF_COND: u32 = 0x0802_0000;
+const CMD_READ_SINGLE: u32 = 0x1122_0010;
+const CMD_WRITE_SINGLE: u32 = 0x1822_0000;
+const CMD_APP_CMD: u32 = 0x3700_0000;
+const CMD_RSPNS_48: u32 = 0x0002_0000;
+// Application commands, sent after `CMD_APP_CMD`.
+const ACMD_SET_BUS_WIDTH: u32 = 0x0602_0000;
+const ACMD_SEND_OP_COND: u32 = 0x2902_0000;
+const ACMD_SEND_SCR: u32 = 0x3322_0010;
+
+const CMD_ERRORS_MASK: u32 = 0xFFF9_C004;
+const CMD_RCA_MASK: u32 = 0xFFFF_0000;
+
+const SR_READ_AVAILABLE: u32 = 0x0000_0800;
+const SR_DAT_INHIBIT: u32 = 0x0000_0002;
+const SR_CMD_INHIBIT: u32 = 0x0000_0001;
+
+const INT_DATA_TIMEOUT: u32 = 0x0010_0000;
+const INT_CMD_TIMEOUT: u32 = 0x0001_0000;
+const INT_READ_RDY: u32 = 0x0000_0020;
+const INT_WRITE_RDY: u32 = 0x0000_0010;
+const INT_DATA_DONE: u32 = 0x0000_0002;
+const INT_CMD_DONE: u32 = 0x0000_0001;
+const INT_ERROR_MASK: u32 = 0x017E_8000;
+
+const C0_HCTL_DWIDTH: u32 = 0x0000_0002;
+
+const C1_SRST_HC: u32 = 0x0100_0000;
+const C1_TOUNIT_MAX: u32 = 0x000E_0000;
+const C1_CLK_EN: u32 = 0x0000_0004;
+const C1_CLK_STABLE: u32 = 0x0000_0002;
+const C1_CLK_INTLEN: u32 = 0x0000_0001;
+
+const HOST_SPEC_V2: u32 = 1;
+
+const SCR_SD_BUS_WIDTH_4: u32 = 0x0000_0400;
+
+const ACMD41_VOLTAGE: u32 = 0x00FF_8000;
+const ACMD41_CMD_COMPLETE: u32 = 0x8000_0000;
+const ACMD41_CMD_CCS: u32 = 0x4000_0000;
+const ACMD41_ARG_HC: u32 = 0x51FF_8000;
+
+/// The base clock of the controller, in hertz.
+const BASE_CLOCK: u32 = 41_666_666;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The card didn't respond in time, or there is no card.
+    Timeout,
+    /// The controller or the card reported an error.
+    Command(u32),
+    /// The card doesn't support the voltage range we asked for.
+    Voltage,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Timeout => write!(f, "SD card timed out"),
+            Error::Command(status) => write!(f, "SD card error {:#010x}", status),
+            Error::Voltage => write!(f, "SD card voltage not supported"),
+        }
+    }
+}
+
+/// Wait for the bits in `mask` to clear in the status register.
+unsafe fn wait_status(mask: u32) -> Result<(), Error> {
+    for _ in 0..500_000 {
+        if mmio::read(EMMC_INTERRUPT) & INT_ERROR_MASK != 0 {
+            return Err(Error::Command(mmio::read(EMMC_INTERRUPT)));
+        }
+        if mmio::read(EMMC_STATUS) & mask == 0 {
+            return Ok(());
+        }
+        delay_us(1);
+    }
+    Err(Error::Timeout)
+}
+
+/// Wait for any of the interrupts in `mask`, and acknowledge them.
+unsafe fn wait_interrupt(mask: u32) -> Result<(), Error> {
+    let mut interrupt = 0;
+    for _ in 0..1_000_000 {
+        interrupt = mmio::read(EMMC_INTERRUPT);
+        if interrupt & (mask | INT_ERROR_MASK) != 0 {
+            break;
+        }
+        delay_us(1);
+    }
+    if interrupt & (mask | INT_ERROR_MASK) == 0
+        || interrupt & (INT_CMD_TIMEOUT | INT_DATA_TIMEOUT) != 0
+    {
+        mmio::write(EMMC_INTERRUPT, interrupt);
+        return Err(Error::Timeout);
+    }
+    if interrupt & INT_ERROR_MASK != 0 {
+        mmio::write(EMMC_INTERRUPT, interrupt);
+        return Err(Error::Command(interrupt));
+    }
+    mmio::write(EMMC_INTERRUPT, mask);
+    Ok(())
+}
+
+unsafe fn set_clock(frequency: u32, host_version: u32) -> Result<(), Error> {
+    wait_status(SR_CMD_INHIBIT | SR_DAT_INHIBIT)?;
+    mmio::write(EMMC_CONTROL1, mmio::read(EMMC_CONTROL1) & !C1_CLK_EN);
+    delay_us(10);
+    let divisor = BASE_CLOCK / frequency;
+    let divisor = if host_version > HOST_SPEC_V2 {
+        // 10-bit divided clock mode.
+        divisor.max(2)
+    } else {
+        // Version 2 hosts only divide by powers of two.
+        (divisor.next_power_of_two() / 2).max(2).min(0x80)
+    };
+    let field = ((divisor & 0xFF) << 8) | ((divisor & 0x300) >> 2);
+    mmio::write(
+        EMMC_CONTROL1,
+        (mmio::read(EMMC_CONTROL1) & 0xFFFF_003F) | field,
+    );
+    delay_us(10);
+    mmio::write(EMMC_CONTROL1, mmio::read(EMMC_CONTROL1) | C1_CLK_EN);
+    for _ in 0..10_000 {
+        if mmio::read(EMMC_CONTROL1) & C1_CLK_STABLE != 0 {
+            return Ok(());
+        }
+        delay_us(10);
+    }
+    Err(Error::Timeout)
+}
+
+/// Route the SD card pins to the controller: card detect on GPIO 47, and
+/// clock, command and data on GPIO 48 to 53, all on alternate function 3.
+unsafe fn init_gpio() {
+    let mut selector = mmio::read(GPIO_FSEL4);
+    selector &= !(7 << (7 * 3)); // gpio47 as input
+    selector |= (7 << (8 * 3)) | (7 << (9 * 3)); // alt3 for gpio48 and gpio49
+    mmio::write(GPIO_FSEL4, selector);
+    let mut selector = mmio::read(GPIO_FSEL5);
+    selector |= (7 << (0 * 3)) | (7 << (1 * 3)) | (7 << (2 * 3)) | (7 << (3 * 3));
+    mmio::write(GPIO_FSEL5, selector);
+
+    // Pull everything up.
+    gpio::set_pull(0b111_1111 << 47, Pull::Up);
+    mmio::write(GPIO_HEN1, mmio::read(GPIO_HEN1) | (1 << 15));
+}
+
+/// An initialized SD card.
+pub struct Card {
+    /// The relative card address, in the top half.
+    rca: u32,
+    /// Whether the card is addressed by sector (SDHC and SDXC) rather than by
+    /// byte.
+    high_capacity: bool,
+}
+
+/// The card in the slot, once it has been initialized.
+static CARD: Mutex<Option<Card>> = Mutex::new(None);
+
+impl Card {
+    /// Call `f` with the card in the slot, initializing it first if nothing
+    /// has used it yet. Everything that reads the card shares it this way, as
+    /// initializing it again would reset the controller under whoever had it
+    /// before.
+    pub fn with<T, F>(f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Card) -> Result<T, Error>,
+    {
+        let mut card = CARD.lock();
+        if card.is_none() {
+            *card = Some(Card::init()?);
+        }
+        f(card.as_mut().unwrap())
+    }
+
+    /// Reset the controller and bring up the card in the slot.
+    fn init() -> Result<Card, Error> {
+        unsafe {
+            init_gpio();
+            let host_version = (mmio::read(EMMC_SLOTISR_VER) >> 16) & 0xFF;
+
+            mmio::write(EMMC_CONTROL0, 0);
+            mmio::write(EMMC_CONTROL1, mmio::read(EMMC_CONTROL1) | C1_SRST_HC);
+            let mut reset = false;
+            for _ in 0..10_000 {
+                delay_us(10);
+                if mmio::read(EMMC_CONTROL1) & C1_SRST_HC == 0 {
+                    reset = true;
+                    break;
+                }
+            }
+            if !reset {
+                return Err(Error::Timeout);
+            }
+            mmio::write(
+                EMMC_CONTROL1,
+                mmio::read(EMMC_CONTROL1) | C1_CLK_INTLEN | C1_TOUNIT_MAX,
+            );
+            delay_us(10);
+            set_clock(400_000, host_version)?;
+            mmio::write(EMMC_INT_EN, 0xFFFF_FFFF);
+            mmio::write(EMMC_INT_MASK, 0xFFFF_FFFF);
+
+            let mut card = Card {
+                rca: 0,
+                high_capacity: false,
+            };
+            card.command(CMD_GO_IDLE, 0)?;
+            if card.command(CMD_SEND_IF_COND, 0x0000_01AA)? != 0x0000_01AA {
+                return Err(Error::Voltage);
+            }
+            let mut ocr = 0;
+            for _ in 0..6 {
+                delay_us(400);
+                // The card may not answer until it has finished powering up.
+                match card.app_command(ACMD_SEND_OP_COND, ACMD41_ARG_HC) {
+                    Ok(response) if response & ACMD41_CMD_COMPLETE != 0 => {
+                        ocr = response;
+                        break;
+                    }
+                    Ok(_) | Err(Error::Timeout) => (),
+                    Err(error) => return Err(error),
+                }
+            }
+            if ocr & ACMD41_CMD_COMPLETE == 0 {
+                return Err(Error::Timeout);
+            }
+            if ocr & ACMD41_VOLTAGE == 0 {
+                return Err(Error::Voltage);
+            }
+            card.high_capacity = ocr & ACMD41_CMD_CCS != 0;
+
+            card.command(CMD_ALL_SEND_CID, 0)?;
+            card.rca = card.command(CMD_SEND_REL_ADDR, 0)? & CMD_RCA_MASK;
+            set_clock(25_000_000, host_version)?;
+            let status = card.command(CMD_CARD_SELECT, card.rca)?;
+            card.check(status)?;
+
+            // Read the SD configuration register to find the bus widths.
+            wait_status(SR_DAT_INHIBIT)?;
+            mmio::write(EMMC_BLKSIZECNT, (1 << 16) | 8);
+            card.app_command(ACMD_SEND_SCR, 0)?;
+            wait_interrupt(INT_READ_RDY)?;
+            let mut scr = [0; 2];
+            let mut read = 0;
+            for _ in 0..100_000 {
+                if read == scr.len() {
+                    break;
+                }
+                if mmio::read(EMMC_STATUS) & SR_READ_AVAILABLE != 0 {
+                    scr[read] = mmio::read(EMMC_DATA);
+                    read += 1;
+                } else {
+                    delay_us(1);
+                }
+            }
+            if read != scr.len() {
+                return Err(Error::Timeout);
+            }
+            if scr[0] & SCR_SD_BUS_WIDTH_4 != 0 {
+                card.app_command(ACMD_SET_BUS_WIDTH, card.rca | (card.rca >> 16))?;
+                mmio::write(EMMC_CONTROL0, mmio::read(EMMC_CONTROL0) | C0_HCTL_DWIDTH);
+            }
+            Ok(card)
+        }
+    }
+
+    fn check(&self, status: u32) -> Result<u32, Error> {
+        if status & CMD_ERRORS_MASK != 0 {
+            Err(Error::Command(status))
+        } else {
+            Ok(status)
+        }
+    }
+
+    /// Send a command and return the first word of the response.
+    unsafe fn command(&mut self, code: u32, arg: u32) -> Result<u32, Error> {
+        wait_status(SR_CMD_INHIBIT)?;
+        mmio::write(EMMC_INTERRUPT, mmio::read(EMMC_INTERRUPT));
+        mmio::write(EMMC_ARG1, arg);
+        mmio::write(EMMC_CMDTM, code);
+        match code {
+            ACMD_SEND_OP_COND => delay_us(1000),
+            CMD_SEND_IF_COND | CMD_APP_CMD => delay_us(100),
+            _ => (),
+        }
+        wait_interrupt(INT_CMD_DONE)?;
+        let response = mmio::read(EMMC_RESP0);
+        match code {
+            CMD_SEND_REL_ADDR => {
+                // The card status bits come back packed into the low half.
+                let status = (response & 0x1FFF)
+                    | ((response & 0x2000) << 6)
+                    | ((response & 0x4000) << 8)
+                    | ((response & 0x8000) << 8);
+                self.check(status)?;
+                Ok(response)
+            }
+            _ => Ok(response),
+        }
+    }
+
+    unsafe fn app_command(&mut self, code: u32, arg: u32) -> Result<u32, Error> {
+        if self.rca == 0 {
+            self.command(CMD_APP_CMD, 0)?;
+        } else {
+            self.command(CMD_APP_CMD | CMD_RSPNS_48, self.rca)?;
+        }
+        self.command(code, arg)
+    }
+
+    fn address(&self, lba: u32) -> u32 {
+        if self.high_capacity {
+            lba
+        } else {
+            lba * SECTOR_SIZE as u32
+        }
+    }
+
+    pub fn read_sector(&mut self, lba: u32, buffer: &mut [u8; SECTOR_SIZE]) -> Result<(), Error> {
+        unsafe {
+            wait_status(SR_DAT_INHIBIT)?;
+            mmio::write(EMMC_BLKSIZECNT, (1 << 16) | SECTOR_SIZE as u32);
+            let status = self.command(CMD_READ_SINGLE, self.address(lba))?;
+            self.check(status)?;
+            wait_interrupt(INT_READ_RDY)?;
+            for word in buffer.chunks_mut(4) {
+                word.copy_from_slice(&mmio::read(EMMC_DATA).to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_sector(&mut self, lba: u32, buffer: &[u8; SECTOR_SIZE]) -> Result<(), Error> {
+        unsafe {
+            wait_status(SR_DAT_INHIBIT)?;
+            mmio::write(EMMC_BLKSIZECNT, (1 << 16) | SECTOR_SIZE as u32);
+            let status = self.command(CMD_WRITE_SINGLE, self.address(lba))?;
+            self.check(status)?;
+            wait_interrupt(INT_WRITE_RDY)?;
+            for word in buffer.chunks(4) {
+                let word = [word[0], word[1], word[2], word[3]];
+                mmio::write(EMMC_DATA, u32::from_le_bytes(word));
+            }
+            wait_interrupt(INT_DATA_DONE)
+        }
+    }
+
+    /// Find the first primary partition of type `kind` in the MBR, returning
+    /// its first sector and length in sectors.
+    pub fn find_partition(&mut self, kind: u8) -> Result<Option<(u32, u32)>, Error> {
+        let mut mbr = [0; SECTOR_SIZE];
+        self.read_sector(0, &mut mbr)?;
+        if mbr[510..512] != [0x55, 0xAA] {
+            return Ok(None);
+        }
+        let found = mbr[446..510].chunks(16).find(|entry| entry[4] == kind);
+        Ok(found.map(|entry| {
+            let start = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+            let len = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]);
+            (start, len)
+        }))
+    }
+}
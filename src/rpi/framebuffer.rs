@@ -1,7 +1,10 @@
 use core::{fmt, ops};
-use spin::Mutex;
 
-use super::mailbox::{self, Channel, PropertyMessage, PropertyTagList};
+use super::{
+    mailbox::{self, Channel, PropertyMessage, PropertyTagList},
+    mmu::clean_dcache,
+};
+use crate::{executor, interrupts::IrqMutex};
 
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug)]
@@ -107,7 +110,7 @@ impl Framebuffer {
         macro_rules! send_message {
             ($tag:expr, $msg:expr, $error:expr) => {{
                 let mut msg = PropertyMessage::new($tag, $msg).prepare();
-                match msg.send() {
+                match executor::block_on(msg.send()) {
                     Some(response) => (&**response).clone(),
                     None => return Err($error),
                 }
@@ -177,11 +180,15 @@ impl Framebuffer {
     {
         let mut fb_opt = FRAMEBUFFER.lock();
         match fb_opt.as_mut() {
-            Some(fb) => f(fb),
+            Some(fb) => {
+                f(fb);
+                fb.clean();
+            }
             None => {
                 match Self::new() {
                     Ok(mut fb) => {
                         f(&mut fb);
+                        fb.clean();
                         *fb_opt = Some(fb);
                     }
                     Err(e) => println!("Failed to initialize framebuffer: {}", e),
@@ -190,6 +197,13 @@ impl Framebuffer {
         }
     }
 
+    /// Write what's been drawn back from the data cache, as the GPU reads
+    /// the buffer from memory if it's in RAM that's mapped cacheable.
+    fn clean(&self) {
+        let start = self.buffer.as_ptr() as usize;
+        clean_dcache(start, start + self.buffer.len());
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -242,4 +256,4 @@ impl ops::IndexMut<(u32, u32)> for Framebuffer {
     }
 }
 
-static FRAMEBUFFER: spin::Mutex<Option<Framebuffer>> = Mutex::new(None);
+static FRAMEBUFFER: IrqMutex<Option<Framebuffer>> = IrqMutex::new(None);
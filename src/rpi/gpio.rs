@@ -0,0 +1,35 @@
+//! The pull-up and pull-down resistors of the GPIO pins, which the drivers
+//! set up for the pins they use.
+
+use crate::rpi::{
+    mmio::{self, P_BASE},
+    timer::delay_us,
+};
+
+const GPIO_BASE: usize = P_BASE + 0x0020_0000;
+const GPIO_PUD: usize = GPIO_BASE + 0x94;
+const GPIO_PUD_CLK0: usize = GPIO_BASE + 0x98;
+const GPIO_PUD_CLK1: usize = GPIO_BASE + 0x9C;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Pull {
+    Off = 0,
+    Down = 1,
+    Up = 2,
+}
+
+/// Set the resistors of the pins whose bits are set in `pins`, bit `n` for
+/// GPIO `n`, and leave the others alone. The control is clocked into the
+/// pins, and needs 150 cycles to set up and to hold, which is well under a
+/// microsecond.
+pub unsafe fn set_pull(pins: u64, pull: Pull) {
+    mmio::write(GPIO_PUD, pull as u32);
+    delay_us(1);
+    mmio::write(GPIO_PUD_CLK0, pins as u32);
+    mmio::write(GPIO_PUD_CLK1, (pins >> 32) as u32);
+    delay_us(1);
+    mmio::write(GPIO_PUD, Pull::Off as u32);
+    mmio::write(GPIO_PUD_CLK0, 0);
+    mmio::write(GPIO_PUD_CLK1, 0);
+}
@@ -1,12 +1,21 @@
 // use byteorder::{ByteOrder, NativeEndian};
 use core::{
     convert::TryInto,
-    fmt, mem, ops, slice,
+    fmt,
+    future::Future,
+    mem, ops,
+    pin::Pin,
+    slice,
     sync::atomic::{fence, Ordering},
+    task::{Context, Poll},
 };
 use field_offset::offset_of;
 
-use super::mmu::align_up;
+use super::mmu::{align_up, flush_dcache};
+use crate::{
+    executor::{self, WakerSlot},
+    interrupts::controller::{self, Irq},
+};
 
 const MAIL_BASE: usize = 0xB880;
 
@@ -55,87 +64,87 @@ unsafe fn write_reg(base: usize, offset: u8, value: u32) {
     ((MAPPED_REGISTERS_BASE + base + offset as usize) as *mut u32).write_volatile(value)
 }
 
-unsafe fn read_mailbox(channel: u8) -> u32 {
-    // 1. Read the status register until the empty flag is not set.
-    // 2. Read data from the read register.
-    // 3. If the lower four bits do not match the channel number desired repeat
-    //    from 1.
-    // 4. The upper 28 bits are the returned data.
-
-    // Wait for the mailbox to be non-empty
-    //     Execute a memory barrier
-    //     Read MAIL0_STATUS
-    //     Goto step 1 if MAIL_EMPTY bit is set
-    // Execute a memory barrier
-    // Read from MAIL0_READ
-    // Check the channel (lowest 4 bits) of the read value for the correct channel
-    // If the channel is not the one we wish to read from (i.e: 1), go to step 1
-    // Return the data (i.e: the read value >> 4)
-
-    // println!("Reading mailbox (want channel {})", channel);
-
-    let mut limit = 10;
-    loop {
-        let mut empty_limit = 10;
-        loop {
-            fence(Ordering::SeqCst);
-            if read_reg(MAIL_BASE, MAILBOX_OFFFSETS.status) & MAIL_EMPTY == 0 {
-                break;
-            }
-            if empty_limit == 0 {
-                panic!(
-                    "Gave up waiting for mail when reading from mailbox (channel {})",
-                    channel
-                );
-            }
-            empty_limit -= 1;
-        }
-        fence(Ordering::SeqCst);
-        let data: u32 = read_reg(MAIL_BASE, MAILBOX_OFFFSETS.read);
-        let read_channel = (data & 0x0F) as u8;
-        let data = data >> 4;
-        // println!(
-        //     "Got data from mailbox: {:#8x} (from channel {})",
-        //     data, read_channel
-        // );
-        if read_channel != channel {
-            // println!("Wrong channel, trying again...");
-            if limit == 0 {
-                panic!(
-                    "Got trampled too many times when reading from mailbox (channel {})",
-                    channel
-                );
-            }
-            limit -= 1;
-            continue;
+/// Bit 0 of the config register raises `Irq::ARM_MAILBOX` while there's
+/// mail to read.
+const MAIL_CONFIG_DATA_IRQ: u32 = 1 << 0;
+
+/// The waker of the `Call` waiting for its answer.
+static ANSWER_WAKER: WakerSlot = WakerSlot::new();
+
+static IRQ_REGISTERED: spin::Once<()> = spin::Once::new();
+
+/// The interrupt stays raised while there's mail, so it's turned off here
+/// and back on by the next `Call` that has to wait.
+fn handle_mailbox_irq() {
+    unsafe { write_reg(MAIL_BASE, MAILBOX_OFFFSETS.config, 0) };
+    ANSWER_WAKER.wake();
+}
+
+#[derive(Copy, Clone, Debug)]
+enum CallState {
+    Writing,
+    Reading,
+}
+
+/// A future that writes `data` to a mailbox channel, then waits for the
+/// answer on the same channel. Answers on other channels are thrown away.
+/// Only one call can be waiting at a time.
+pub struct Call {
+    channel: u8,
+    data: u32,
+    state: CallState,
+}
+
+impl Call {
+    pub fn new(channel: Channel, data: u32) -> Call {
+        Call {
+            channel: channel as u8,
+            data,
+            state: CallState::Writing,
         }
-        return data;
     }
 }
 
-unsafe fn write_mailbox(channel: u8, data: u32) {
-    // 1. Read the status register until the full flag is not set.
-    // 2. Write the data (shifted into the upper 28 bits) combined with the
-    //    channel (in the lower four bits) to the write register.
-    // println!("Writing {:#8x} to mailbox channel {}", data, channel);
-    let mut limit = 10;
-    loop {
-        // Wait for space
-        fence(Ordering::SeqCst);
-        if read_reg(MAIL_BASE, MAILBOX_OFFFSETS.status + 0x20) & MAIL_FULL == 0 {
-            break;
+impl Future for Call {
+    type Output = u32;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+        if let CallState::Writing = self.state {
+            // There's no interrupt for space to write, but the VideoCore
+            // empties its mailbox quickly.
+            fence(Ordering::SeqCst);
+            if unsafe { read_reg(MAIL_BASE, MAILBOX_OFFFSETS.status + 0x20) } & MAIL_FULL != 0 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            unsafe {
+                write_reg(
+                    MAIL_BASE,
+                    MAILBOX_OFFFSETS.write,
+                    self.data | self.channel as u32,
+                )
+            };
+            fence(Ordering::SeqCst);
+            self.state = CallState::Reading;
         }
-        if limit == 0 {
-            panic!(
-                "Gave up waiting for space to write to mailbox (channel {}, data: 0x{:08x})",
-                channel, data
-            );
+        loop {
+            fence(Ordering::SeqCst);
+            if unsafe { read_reg(MAIL_BASE, MAILBOX_OFFFSETS.status) } & MAIL_EMPTY != 0 {
+                IRQ_REGISTERED
+                    .call_once(|| controller::register(Irq::ARM_MAILBOX, handle_mailbox_irq));
+                ANSWER_WAKER.register(cx.waker());
+                // Mail that came since the check raises the interrupt as soon
+                // as it's enabled.
+                unsafe { write_reg(MAIL_BASE, MAILBOX_OFFFSETS.config, MAIL_CONFIG_DATA_IRQ) };
+                return Poll::Pending;
+            }
+            fence(Ordering::SeqCst);
+            let data = unsafe { read_reg(MAIL_BASE, MAILBOX_OFFFSETS.read) };
+            if (data & 0x0F) as u8 == self.channel {
+                return Poll::Ready(data >> 4);
+            }
         }
-        limit -= 1;
     }
-    write_reg(MAIL_BASE, MAILBOX_OFFFSETS.write, data | (channel as u32));
-    fence(Ordering::SeqCst);
-    // println!("Finished writing to mailbox");
 }
 
 pub trait PropertyTagList: Sized {
@@ -165,7 +174,10 @@ impl<T: Sized> PropertyTagList for PropertyMessage<T> {}
 // impl_ptl!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
 // impl_ptl!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
 
-#[repr(C, align(16))]
+/// Aligned to a cache line, which its size is then a multiple of, so that
+/// flushing it from the data cache around a call leaves nothing else out of
+/// date.
+#[repr(C, align(64))]
 #[derive(Debug)]
 pub struct PropertyMessageWrapper<TL: PropertyTagList> {
     buffer_size: u32,
@@ -197,47 +209,59 @@ impl<TL: PropertyTagList> PropertyMessageWrapper<TL> {
         unsafe { slice::from_raw_parts((self as *const Self) as *const u32, u32_size) }
     }
 
-    pub fn send<'a>(&'a mut self) -> Option<&'a TL>
+    /// Send the message to the firmware, for a future of its answer.
+    pub fn send(&mut self) -> SendMessage<TL>
     where
         TL: fmt::Debug,
     {
-        // println!("Property message before sending over mailbox: {:#x?}", self);
-        // println!(
-        //     "Property message quads before sending over mailbox: {:#x?}",
-        //     self.as_quads()
-        // );
-        const CHANNEL: u8 = Channel::PropertyTagsSend as u8;
-        println!("sending message {:x?}", self);
-        unsafe {
-            let ptr = self as *const Self;
-            let addr = ptr as usize;
-            write_mailbox(CHANNEL, addr.try_into().ok()?);
-            let resp_addr = read_mailbox(CHANNEL);
+        SendMessage {
+            message: Some(self),
+            call: None,
+        }
+    }
+}
+
+/// A future of the firmware's answer to a property message. It gives the
+/// tags with their values filled in, or `None` if the firmware couldn't
+/// answer.
+pub struct SendMessage<'a, TL: PropertyTagList> {
+    message: Option<&'a mut PropertyMessageWrapper<TL>>,
+    call: Option<Call>,
+}
+
+impl<'a, TL: PropertyTagList + fmt::Debug> Future for SendMessage<'a, TL> {
+    type Output = Option<&'a TL>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<&'a TL>> {
+        // Nothing here is pinned, so the fields can be moved freely.
+        let this = &mut *self;
+        let message = this
+            .message
+            .as_mut()
+            .expect("Polled a mailbox send after it finished");
+        if this.call.is_none() {
+            println!("sending message {:x?}", message);
+            let addr: u32 =
+                match (&**message as *const PropertyMessageWrapper<TL> as usize).try_into() {
+                    Ok(addr) => addr,
+                    Err(_) => return Poll::Ready(None),
+                };
+            // The VideoCore doesn't see what's in the cores' caches.
+            flush_dcache(addr as usize, addr as usize + mem::size_of_val(&**message));
+            this.call = Some(Call::new(Channel::PropertyTagsSend, addr));
+        }
+        let call = this.call.as_mut().unwrap();
+        if Pin::new(call).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let message = this.message.take().unwrap();
+        let start = &*message as *const PropertyMessageWrapper<TL> as usize;
+        flush_dcache(start, start + mem::size_of_val(&*message));
+        if message.code != 0x8000_0000 {
+            return Poll::Ready(None);
         }
-	// let resp_ptr = resp_addr as *const u32;
-	// println!("Got response from mailbox: {:#?}", &*resp_ptr);
-	// let resp_code: u32 = *resp_ptr.offset(1);
-	// println!(
-	//     "Property message after response {:#8x}: {:#x?}",
-	//     resp_addr, self
-	// );
-	// {
-	//     let message_quads = self.as_quads();
-	//     println!("Property message words: {:#x?}", message_quads);
-	// }
-	if self.code != 0x8000_0000 {
-	    return None;
-	}
-	// let msg_ptr = resp_ptr.offset(2);
-
-	// let value_buffer_size_ptr = msg_ptr.offset(1);
-	// let value_buffer_size = (*value_buffer_size_ptr) as usize;
-	// let value_buffer_ptr = msg_ptr.offset(3) as *const T;
-	// assert_eq!(value_buffer_size, mem::size_of::<T>());
-	// let value_ref = &*(value_buffer_ptr as *const T);
-	// Some(value_ref)
-	println!("received message: {:#x?}", self);
-	Some(&self.tags)
+        println!("received message: {:#x?}", message);
+        Poll::Ready(Some(&message.tags))
     }
 }
 
@@ -307,6 +331,62 @@ impl<T> PropertyMessage<T> {
     pub fn value(&self) -> &T {
         &self.buffer
     }
+
+    /// The length in bytes of the response to this tag, if the firmware
+    /// answered it.
+    pub fn response_len(&self) -> Option<usize> {
+        if self.code & 0x8000_0000 != 0 {
+            Some((self.code & 0x7FFF_FFFF) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// The largest value buffer, in bytes, of a property tag built at runtime.
+pub const MAX_RAW_VALUE_SIZE: usize = 256;
+
+/// The value buffer of a property tag whose size is only known at runtime.
+/// The extra word past `MAX_RAW_VALUE_SIZE` stays zero, so that there is
+/// always an end tag after the value however big it is.
+#[repr(C)]
+pub struct RawValue([u32; MAX_RAW_VALUE_SIZE / 4 + 1]);
+
+impl RawValue {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.0.as_ptr() as *const u8, MAX_RAW_VALUE_SIZE) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut u8, MAX_RAW_VALUE_SIZE) }
+    }
+}
+
+impl fmt::Debug for RawValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Leave out the unused tail of the buffer.
+        let used = self.0.iter().rposition(|word| *word != 0).map_or(0, |i| i + 1);
+        f.debug_list().entries(self.0[..used].iter()).finish()
+    }
+}
+
+impl PropertyMessage<RawValue> {
+    /// Build a property tag from bytes, for tags that aren't known until
+    /// runtime. The value buffer is `value_size` bytes, which must be enough
+    /// for both the request and the response.
+    pub fn raw(tag: u32, request: &[u8], value_size: usize) -> Option<Self> {
+        if request.len() > value_size || value_size > MAX_RAW_VALUE_SIZE {
+            return None;
+        }
+        let mut buffer = RawValue([0; MAX_RAW_VALUE_SIZE / 4 + 1]);
+        buffer.as_bytes_mut()[..request.len()].copy_from_slice(request);
+        Some(PropertyMessage {
+            tag,
+            buffer_size: ((value_size + 3) & !3) as u32,
+            code: request.len() as u32,
+            buffer,
+        })
+    }
 }
 
 // impl<T: fmt::Debug> PropertyMessage<T> {
@@ -316,15 +396,17 @@ impl<T> PropertyMessage<T> {
 //     }
 // }
 
+/// Send `msg` on `channel`, for the firmware's answer. `msg` should be alone
+/// in its cache lines, as for `PropertyMessageWrapper`.
 pub fn send_raw_message<T: fmt::Debug>(channel: Channel, msg: &mut T) -> Result<u32, ()> {
     let resp: u32;
     let msg_ptr = msg as *mut T;
     let msg_addr_usize = msg_ptr as usize;
     let msg_addr_u32 = msg_addr_usize.try_into().map_err(|_| ())?;
-    unsafe {
-        write_mailbox(channel as u8, msg_addr_u32);
-        resp = read_mailbox(channel as u8);
-    }
+    let msg_end = msg_addr_usize + mem::size_of::<T>();
+    flush_dcache(msg_addr_usize, msg_end);
+    resp = executor::block_on(Call::new(channel, msg_addr_u32));
+    flush_dcache(msg_addr_usize, msg_end);
     // println!(
     //     "Got response {:#8x} after raw message send: {:#x?}",
     //     resp, msg
@@ -20,3 +20,42 @@ pub unsafe fn read(reg: usize) -> u32 {
 pub unsafe fn write(reg: usize, data: u32) {
     (reg as *mut u32).write_volatile(data)
 }
+
+#[inline(always)]
+pub unsafe fn read_u16(reg: usize) -> u16 {
+    (reg as *const u16).read_volatile()
+}
+
+#[inline(always)]
+pub unsafe fn write_u16(reg: usize, data: u16) {
+    (reg as *mut u16).write_volatile(data)
+}
+
+#[inline(always)]
+pub unsafe fn read_u8(reg: usize) -> u8 {
+    (reg as *const u8).read_volatile()
+}
+
+#[inline(always)]
+pub unsafe fn write_u8(reg: usize, data: u8) {
+    (reg as *mut u8).write_volatile(data)
+}
+
+/// Data memory barrier: order memory accesses before it against those after.
+#[inline(always)]
+pub fn dmb() {
+    unsafe { asm!("dmb sy" :::: "volatile") }
+}
+
+/// Data synchronization barrier: wait for outstanding memory accesses to
+/// complete.
+#[inline(always)]
+pub fn dsb() {
+    unsafe { asm!("dsb sy" :::: "volatile") }
+}
+
+/// Instruction synchronization barrier: flush the pipeline.
+#[inline(always)]
+pub fn isb() {
+    unsafe { asm!("isb" :::: "volatile") }
+}
@@ -8,7 +8,9 @@ use core::{
     ops::{Index, IndexMut},
 };
 
+mod address_space;
 mod addrs;
+mod cache;
 mod descriptors;
 /// Items in this module should not be accessed by the kernel outside of the
 /// early boot process.
@@ -18,6 +20,8 @@ mod page_tables;
 mod ttbr;
 
 use self::{descriptors::*, levels::*, page_tables::PageTables};
+pub use address_space::{Access, AddressSpace, MapError, USER_END, USER_START};
+pub use cache::{clean_dcache, flush_dcache, sync_icache, sync_icache_aliases};
 pub use ttbr::TTBR;
 
 const ENTRY_COUNT: usize = 512;
@@ -25,6 +29,11 @@ const ENTRY_COUNT: usize = 512;
 pub const PAGE_SHIFT: usize = 12;
 pub const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
 
+/// The virtual addresses that the early page tables map as device memory,
+/// end exclusive.
+pub const DEVICE_MEMORY_START: usize = crate::rpi::mmio::P_BASE_OFFSET;
+pub const DEVICE_MEMORY_END: usize = early_init::PHYS_MEMORY_SIZE - SECTION_SIZE;
+
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PageTableIndex(u16);
@@ -130,10 +139,30 @@ pub fn align_up_bits(addr: usize, bits: usize) -> usize {
     align_down_bits(addr + (1 << bits), bits)
 }
 
+/// Look up the physical address that a read from `virt_addr` at EL1 would
+/// access, by asking the MMU with `AT S1E1R`. Returns `None` if the read
+/// would fault.
+pub fn translate(virt_addr: usize) -> Option<usize> {
+    let par_el1: usize;
+    unsafe {
+        asm!("at s1e1r, $1
+              isb
+              mrs $0, par_el1" : "=r"(par_el1) : "r"(virt_addr) :: "volatile");
+    }
+    if par_el1.get_bit(0) {
+        return None;
+    }
+    Some((par_el1.get_bits(12..48) << PAGE_SHIFT) | (virt_addr & (PAGE_SIZE - 1)))
+}
+
 // const UPPER_SIZE: usize = 1 << Upper::SHIFT;
 const MIDDLE_SIZE: usize = 1 << Middle::SHIFT;
 const BOTTOM_SIZE: usize = 1 << Bottom::SHIFT;
 
+/// Map `virt_addr_start..virt_addr_end` to physical memory from
+/// `phys_addr_start`. Entries that replace ones already in use may still be
+/// cached in the TLBs, of other cores as well as this one; call
+/// `smp::tlb_shootdown` before relying on the new ones.
 #[inline(never)]
 pub unsafe fn map_memory<'a>(
     tables: &'a mut PageTables<'_>,
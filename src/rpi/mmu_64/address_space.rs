@@ -0,0 +1,205 @@
+use alloc::{
+    alloc::{alloc_zeroed, Layout},
+    boxed::Box,
+    vec::Vec,
+};
+use core::ops::Range;
+
+use super::{
+    descriptors::DescriptorFlags, levels::*, map_memory_inner, page_tables::PageTables,
+    sync_icache_aliases, PageTable, PAGE_SIZE, TTBR,
+};
+
+/// The virtual addresses that user programs are given, end exclusive. They
+/// start above the kernel's 4 GiB identity mapping, in the part of the
+/// global table that only user tables fill in.
+pub const USER_START: usize = 0x1_0000_0000;
+pub const USER_END: usize = 0x2_0000_0000;
+
+/// How a range of user pages may be used.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Readable and executable, for program code.
+    ReadExecute,
+    /// Readable and writable, for data and stacks.
+    ReadWrite,
+}
+
+impl Access {
+    fn flags(self) -> DescriptorFlags {
+        // User mappings are not global, so that `activate` can flush them
+        // from the TLB without flushing the kernel's. The kernel never runs
+        // code from user pages.
+        let common = DescriptorFlags::ATTR_INDEX_NORMAL_WB
+            | DescriptorFlags::INNER_SHAREABLE
+            | DescriptorFlags::NON_SECURE
+            | DescriptorFlags::ACCESS
+            | DescriptorFlags::NOT_GLOBAL
+            | DescriptorFlags::PRIVILEGED_EXECUTE_NEVER;
+        match self {
+            Access::ReadExecute => common | DescriptorFlags::EL1_R0_EL0_RO,
+            Access::ReadWrite => {
+                common | DescriptorFlags::EL1_RW_EL0_RW | DescriptorFlags::EXECUTE_NEVER
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// The range isn't page aligned, or isn't within `USER_START..USER_END`.
+    BadRange,
+    /// Some of the range is mapped already.
+    Overlaps,
+    /// There isn't enough memory left for the pages.
+    OutOfMemory,
+}
+
+/// A page of memory given to a user program.
+#[repr(C, align(4096))]
+struct Frame([u8; PAGE_SIZE]);
+
+impl Frame {
+    /// A zeroed frame, or `None` if the heap is exhausted. Programs can ask
+    /// for a lot of memory, which shouldn't panic the kernel.
+    fn try_new() -> Option<Box<Frame>> {
+        unsafe {
+            let frame = alloc_zeroed(Layout::new::<Frame>()) as *mut Frame;
+            if frame.is_null() {
+                None
+            } else {
+                Some(Box::from_raw(frame))
+            }
+        }
+    }
+}
+
+/// The page tables of a user program. The kernel's part of the address
+/// space is shared by pointing at the kernel's own tables, which EL0 has no
+/// access to, so the kernel keeps running while one is active.
+pub struct AddressSpace {
+    global: Box<PageTable<Global>>,
+    middle: Vec<Box<PageTable<Middle>>>,
+    bottom: Vec<Box<PageTable<Bottom>>>,
+    frames: Vec<Box<Frame>>,
+    /// The ranges that are mapped, in the order they were.
+    regions: Vec<(Range<usize>, Access)>,
+}
+
+impl AddressSpace {
+    pub fn new() -> AddressSpace {
+        let mut global = Box::new(PageTable::new());
+        PageTables::with_page_tables(|kernel| {
+            for index in 0..USER_START >> Global::SHIFT {
+                global[index] = kernel.global[index];
+            }
+        });
+        AddressSpace {
+            global,
+            middle: Vec::new(),
+            bottom: Vec::new(),
+            frames: Vec::new(),
+            regions: Vec::new(),
+        }
+    }
+
+    /// Map `range` to new pages holding `contents` followed by zeroes.
+    pub fn map(
+        &mut self,
+        range: Range<usize>,
+        access: Access,
+        contents: &[u8],
+    ) -> Result<(), MapError> {
+        if range.start % PAGE_SIZE != 0
+            || range.end % PAGE_SIZE != 0
+            || range.start < USER_START
+            || range.end > USER_END
+            || range.start >= range.end
+            || contents.len() > range.end - range.start
+        {
+            return Err(MapError::BadRange);
+        }
+        let overlaps = self
+            .regions
+            .iter()
+            .any(|(region, _)| region.start < range.end && range.start < region.end);
+        if overlaps {
+            return Err(MapError::Overlaps);
+        }
+
+        let mut tables = PageTables {
+            global: &mut self.global,
+            middle: &mut self.middle,
+            bottom: &mut self.bottom,
+        };
+        let mut chunks = contents.chunks(PAGE_SIZE);
+        for page in range.clone().step_by(PAGE_SIZE) {
+            let mut frame = Frame::try_new().ok_or(MapError::OutOfMemory)?;
+            if let Some(chunk) = chunks.next() {
+                frame.0[..chunk.len()].copy_from_slice(chunk);
+            }
+            if access == Access::ReadExecute {
+                let start = frame.0.as_ptr() as usize;
+                sync_icache_aliases(start, start + PAGE_SIZE);
+            }
+            // The kernel's identity mapping makes the frame's address its
+            // physical one.
+            let mut phys = &*frame as *const Frame as usize;
+            let mut virt = page;
+            unsafe {
+                map_memory_inner(&mut tables, PAGE_SIZE, &mut virt, &mut phys, access.flags())
+            };
+            self.frames.push(frame);
+        }
+        // Make the new entries visible to the table walker before they're
+        // used.
+        unsafe { asm!("dsb ishst" :::: "volatile") };
+        self.regions.push((range, access));
+        Ok(())
+    }
+
+    /// Whether all of `len` bytes from `addr` are mapped, and writable if
+    /// `write` is set. System calls check the buffers they're given with it.
+    pub fn contains(&self, addr: usize, len: usize, write: bool) -> bool {
+        let end = match addr.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        if len == 0 {
+            return true;
+        }
+        // Regions never overlap, but adjacent ones can cover a buffer
+        // between them.
+        let mut covered = addr;
+        while covered < end {
+            let region = self
+                .regions
+                .iter()
+                .find(|(region, _)| region.contains(&covered));
+            match region {
+                Some((region, access)) if !write || *access == Access::ReadWrite => {
+                    covered = region.end;
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Switch this core's `TTBR0_EL1` to these tables. Returns the tables
+    /// that were in use, for `deactivate`.
+    pub unsafe fn activate(&self) -> TTBR<0, 1> {
+        let previous = TTBR::<0, 1>::load();
+        TTBR::<0, 1>::from(&*self.global as *const PageTable<Global>)
+            .set_outer_sharable()
+            .set_inner_region(0)
+            .set_outer_region(0)
+            .switch();
+        previous
+    }
+
+    /// Switch back to the tables `activate` replaced.
+    pub unsafe fn deactivate(previous: TTBR<0, 1>) {
+        previous.switch();
+    }
+}
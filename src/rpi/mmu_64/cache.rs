@@ -0,0 +1,97 @@
+//! Cache maintenance by address, for memory that's used by something other
+//! than the cores' data caches: instruction fetch, and devices such as the
+//! VideoCore that don't snoop the caches.
+
+fn cache_line_sizes() -> (usize, usize) {
+    let ctr: usize;
+    unsafe {
+        asm!("mrs $0, ctr_el0" : "=r"(ctr));
+    }
+    let d_line = 4 << ((ctr >> 16) & 0xF);
+    let i_line = 4 << (ctr & 0xF);
+    (d_line, i_line)
+}
+
+/// Make freshly written instructions in `start..end` visible to instruction
+/// fetch: clean the data cache to the point of unification, then invalidate
+/// the instruction cache over the same range.
+pub fn sync_icache(start: usize, end: usize) {
+    if start >= end {
+        return;
+    }
+    let (d_line, i_line) = cache_line_sizes();
+    unsafe {
+        let mut addr = start & !(d_line - 1);
+        while addr < end {
+            asm!("dc cvau, $0" :: "r"(addr) :: "volatile");
+            addr += d_line;
+        }
+        asm!("dsb ish" :::: "volatile");
+        let mut addr = start & !(i_line - 1);
+        while addr < end {
+            asm!("ic ivau, $0" :: "r"(addr) :: "volatile");
+            addr += i_line;
+        }
+        asm!("dsb ish
+              isb" :::: "volatile");
+    }
+}
+
+/// Like `sync_icache`, for instructions that will run at another virtual
+/// address than `start..end`, such as a user program's. The Cortex-A53's
+/// instruction cache is indexed by virtual address, so the whole of it is
+/// invalidated, on every core.
+pub fn sync_icache_aliases(start: usize, end: usize) {
+    if start >= end {
+        return;
+    }
+    let (d_line, _) = cache_line_sizes();
+    unsafe {
+        let mut addr = start & !(d_line - 1);
+        while addr < end {
+            asm!("dc cvau, $0" :: "r"(addr) :: "volatile");
+            addr += d_line;
+        }
+        asm!("dsb ish
+              ic ialluis
+              dsb ish
+              isb" :::: "volatile");
+    }
+}
+
+/// Write what the data cache holds of `start..end` back to memory, for a
+/// device to read.
+pub fn clean_dcache(start: usize, end: usize) {
+    if start >= end {
+        return;
+    }
+    let (d_line, _) = cache_line_sizes();
+    unsafe {
+        let mut addr = start & !(d_line - 1);
+        while addr < end {
+            asm!("dc cvac, $0" :: "r"(addr) :: "volatile");
+            addr += d_line;
+        }
+        asm!("dsb sy" :::: "volatile");
+    }
+}
+
+/// Write what the data cache holds of `start..end` back to memory and drop
+/// it, so that a device reads what was written there, and what the device
+/// writes is read afterwards. Anything else in the same cache lines must be
+/// left alone while the device has the memory, or writing it back would
+/// undo the device's writes.
+pub fn flush_dcache(start: usize, end: usize) {
+    if start >= end {
+        return;
+    }
+    let (d_line, _) = cache_line_sizes();
+    unsafe {
+        let mut addr = start & !(d_line - 1);
+        while addr < end {
+            asm!("dc civac, $0" :: "r"(addr) :: "volatile");
+            addr += d_line;
+        }
+        asm!("dsb sy" :::: "volatile");
+    }
+}
@@ -11,8 +11,11 @@ bitflags! {
 
         const PAGE_TABLE_FLAG = 1 << 1;
 
-        const ATTR_INDEX_DEVICE_NGNRNE = 0;
-        const ATTR_INDEX_NORMAL_NC = 1;
+        /// Which of the attributes in `MAIR_EL1` the memory has, as bits
+        /// 2-4. See `MAIR_VALUE` in `early_init`.
+        const ATTR_INDEX_DEVICE_NGNRNE = 0 << 2;
+        const ATTR_INDEX_NORMAL_NC = 1 << 2;
+        const ATTR_INDEX_NORMAL_WB = 2 << 2;
 
         const NON_SECURE = 1 << 5;
 
@@ -21,6 +24,10 @@ bitflags! {
         const EL1_R0_EL0_NONE = 0b10 << 6;
         const EL1_R0_EL0_RO = 0b11 << 6;
 
+        /// Kept coherent between the cores' caches. Only meaningful for
+        /// cacheable memory.
+        const INNER_SHAREABLE = 0b11 << 8;
+
         const ACCESS = 1 << 10;
         const NOT_GLOBAL = 1 << 11;
 
@@ -157,10 +164,6 @@ impl<L: PageTableLevelHasNext> PageTableDescriptor<L> {
             let new_table = super::get_table_for_virt(tables, virt_addr);
             *self =
                 PageTableDescriptor::new_page_table(new_table as *mut PageTable<L::Next> as usize);
-            println!(
-                "Pointing desciptor {:?} at table {:p}",
-                self, new_table as *mut _
-            );
             new_table
         })
     }
@@ -81,12 +81,15 @@ extern "C" {
 #[allow(non_upper_case_globals)]
 const MT_DEVICE_nGnRnE_FLAGS: u64 = 0x00;
 const MT_NORMAL_NC_FLAGS: u64 = 0x44;
+/// Inner and outer write-back, allocating on reads and writes.
+const MT_NORMAL_WB_FLAGS: u64 = 0xFF;
 const MAIR_VALUE: u64 = (MT_DEVICE_nGnRnE_FLAGS
-    << (8 * DescriptorFlags::ATTR_INDEX_DEVICE_NGNRNE.bits()))
-    | (MT_NORMAL_NC_FLAGS << (8 * DescriptorFlags::ATTR_INDEX_NORMAL_NC.bits()));
+    << (8 * (DescriptorFlags::ATTR_INDEX_DEVICE_NGNRNE.bits() >> 2)))
+    | (MT_NORMAL_NC_FLAGS << (8 * (DescriptorFlags::ATTR_INDEX_NORMAL_NC.bits() >> 2)))
+    | (MT_NORMAL_WB_FLAGS << (8 * (DescriptorFlags::ATTR_INDEX_NORMAL_WB.bits() >> 2)));
 
 const VA_START: usize = 0xffff_0000_0000_0000;
-const PHYS_MEMORY_SIZE: usize = 0x1_0000_0000;
+pub(super) const PHYS_MEMORY_SIZE: usize = 0x1_0000_0000;
 
 bitflags! {
     struct TCRFlags: u64 {
@@ -96,9 +99,18 @@ bitflags! {
         const T0SZ_2_32 = (64 - 32) << 0;
         const T1SZ_2_32 = (64 - 32) << 16;
 
-        const SH0_NON_SHARABLE = 0b00 << 8;
-        const SH0_OUTER_SHARABLE = 0b10 << 8;
-        const SH0_INNER_SHARABLE = 0b11 << 8;
+        const T0SZ_2_36 = (64 - 36) << 0;
+
+        // How the table walker caches the tables, which must match how the
+        // kernel writes them.
+        const IRGN0_WB = 0b01 << 8;
+        const ORGN0_WB = 0b01 << 10;
+        const IRGN1_WB = 0b01 << 24;
+        const ORGN1_WB = 0b01 << 26;
+
+        const SH0_NON_SHARABLE = 0b00 << 12;
+        const SH0_OUTER_SHARABLE = 0b10 << 12;
+        const SH0_INNER_SHARABLE = 0b11 << 12;
 
         const SH1_NON_SHARABLE = 0b00 << 28;
         const SH1_OUTER_SHARABLE = 0b10 << 28;
@@ -180,11 +192,17 @@ msr cpacr_el1, $0"
         println!("{:?}\t{:?}", TTBR::<0, 1>::load(), TTBR::<1, 1>::load());
     });
 
-    let tcr_el1_value = (TCRFlags::T0SZ_2_32
+    // TTBR0 covers 64 GiB, so that user programs have room above the 4 GiB
+    // identity mapping. Translation still starts at the global table.
+    let tcr_el1_value = (TCRFlags::T0SZ_2_36
         | TCRFlags::T1SZ_2_32
         | TCRFlags::TG0_4K
         | TCRFlags::TG1_4K
         | TCRFlags::IPS_1TB
+        | TCRFlags::IRGN0_WB
+        | TCRFlags::ORGN0_WB
+        | TCRFlags::IRGN1_WB
+        | TCRFlags::ORGN1_WB
         | TCRFlags::SH0_INNER_SHARABLE
         | TCRFlags::SH1_INNER_SHARABLE
         | TCRFlags::DISABLE_TTBR1_EL1)
@@ -322,19 +340,22 @@ unsafe fn create_page_tables(page_tables: &mut PageTables) {
         }
     }
 
-    let va_device_start = mmio::P_BASE_OFFSET;
-    let va_device_end = PHYS_MEMORY_SIZE - SECTION_SIZE;
+    let va_device_start = DEVICE_MEMORY_START;
+    let va_device_end = DEVICE_MEMORY_END;
 
     let va_start = &IMAGE_START as *const MARKER as usize;
-    let va_end = PHYS_MEMORY_SIZE;
     // assert_eq!(va_start, 0);
 
+    // RAM is cacheable, and coherent between the cores, so that their locks
+    // work. The peripherals above it are mapped as device memory below.
+    let ram_end = mmio::P_BASE_PHYSICAL_ADDR;
     super::map_memory(
         page_tables,
         0,
         va_start,
-        va_end,
-        DescriptorFlags::ATTR_INDEX_NORMAL_NC
+        ram_end,
+        DescriptorFlags::ATTR_INDEX_NORMAL_WB
+            | DescriptorFlags::INNER_SHAREABLE
             | DescriptorFlags::EL1_RW_EL0_NONE
             | DescriptorFlags::NON_SECURE
             | DescriptorFlags::ACCESS,
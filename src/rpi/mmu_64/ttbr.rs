@@ -77,6 +77,17 @@ impl TTBR<0, 1> {
         println!("Installing TTBR0_EL1 with value {:#016x}", self.value);
         asm!("msr ttbr0_el1, $0" :: "r"(self.value) :: "volatile");
     }
+
+    /// Install these tables quietly, for switching between address spaces,
+    /// and flush the TLB of the non-global entries of the old ones. Only user
+    /// mappings are non-global, and they all use ASID zero.
+    pub unsafe fn switch(self) {
+        asm!("msr ttbr0_el1, $0
+              isb
+              tlbi aside1, xzr
+              dsb ish
+              isb" :: "r"(self.value) : "memory" : "volatile");
+    }
 }
 
 impl TTBR<1, 1> {
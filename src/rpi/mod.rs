@@ -1,7 +1,9 @@
 use cfg_if::cfg_if;
 
 pub mod console;
+pub mod emmc;
 pub mod framebuffer;
+pub mod gpio;
 pub mod mailbox;
 pub mod mmio;
 
@@ -14,5 +16,6 @@ cfg_if! {
 
 #[cfg(feature = "semihosting")]
 pub mod semihosting;
+pub mod timer;
 pub mod uart;
 pub mod usb;
@@ -34,7 +34,7 @@ fn check_init() -> Result<(), ()> {
 }
 
 #[repr(usize)]
-enum OpenMode {
+pub enum OpenMode {
     R = 0,
     RB = 1,
     RPlus = 2,
@@ -49,8 +49,10 @@ enum OpenMode {
     APlusB = 11,
 }
 
+/// Open a file on the host, returning its handle. `filename` must be
+/// NUL-terminated.
 #[must_use]
-fn sys_open(filename: &[u8], mode: OpenMode) -> Option<usize> {
+pub fn sys_open(filename: &[u8], mode: OpenMode) -> Option<usize> {
     debug_assert_eq!(filename[filename.len() - 1], 0);
 
     #[repr(C)]
@@ -70,7 +72,8 @@ fn sys_open(filename: &[u8], mode: OpenMode) -> Option<usize> {
     let mut params = OpenParamBlock {
         bytes: filename.as_ptr(),
         mode: mode as usize,
-        len: filename.len(),
+        // The length doesn't include the terminator.
+        len: filename.len() - 1,
     };
     let params_ptr = &params as *const OpenParamBlock;
     let result = unsafe { syscall(0x01, params_ptr as usize) };
@@ -81,9 +84,49 @@ fn sys_open(filename: &[u8], mode: OpenMode) -> Option<usize> {
     }
 }
 
+pub fn sys_close(handle: usize) -> Result<(), ()> {
+    let result = unsafe { syscall(0x02, &handle as *const usize as usize) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+/// Write `bytes` to a file opened with `sys_open`. Returns the number of bytes
+/// that were *not* written, so zero means success.
+pub fn sys_write(handle: usize, bytes: &[u8]) -> usize {
+    let params: [usize; 3] = [handle, bytes.as_ptr() as usize, bytes.len()];
+    let result = unsafe { syscall(0x05, params.as_ptr() as usize) };
+    result as usize
+}
+
+/// Read into `buffer` from a file opened with `sys_open`. Returns the number
+/// of bytes that were *not* read, so a short count means end of file.
+pub fn sys_read(handle: usize, buffer: &mut [u8]) -> usize {
+    let params: [usize; 3] = [handle, buffer.as_mut_ptr() as usize, buffer.len()];
+    let result = unsafe { syscall(0x06, params.as_ptr() as usize) };
+    result as usize
+}
+
+/// The length of a file opened with `sys_open`.
+pub fn sys_flen(handle: usize) -> Option<usize> {
+    let result = unsafe { syscall(0x0C, &handle as *const usize as usize) };
+    if result >= 0 {
+        Some(result as usize)
+    } else {
+        None
+    }
+}
+
 #[inline(never)]
 fn sys_write0(bytes: &[u8]) {
     debug_assert_eq!(bytes[bytes.len() - 1], 0);
 
     let _result = unsafe { syscall(0x04, bytes.as_ptr() as usize) };
 }
+
+/// Write a NUL-terminated string to the host's console.
+pub unsafe fn puts(bytes: *const u8) {
+    syscall(0x04, bytes as usize);
+}
@@ -0,0 +1,391 @@
+//! The BCM2837 system timer, and the ARMv8 generic timer of each core.
+//!
+//! The system timer is a free-running 64-bit counter of microseconds since
+//! boot, which is what `Instant` measures. It has four compare registers
+//! that raise an interrupt when the low half of the counter matches them;
+//! the GPU uses zero and two, and compare register 3 runs the alarms here.
+//! Up to `MAX_ALARMS` one-shot and periodic alarms share it, by always
+//! setting it to the earliest deadline.
+//!
+//! `Timer` is a future for waiting without blocking, which the millisecond
+//! tick wakes.
+//!
+//! The generic timer counts at `counter_frequency()`, and each core has its
+//! own, which interrupts that core alone through the local interrupt
+//! controller as `Irq::CNTPNS`.
+
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    ops::{Add, Sub},
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use crate::{
+    interrupts::{
+        controller::{self, Irq, LOCAL_BASE},
+        IrqMutex,
+    },
+    rpi::mmio::{self, P_BASE},
+};
+
+const SYSTEM_TIMER_CS: usize = P_BASE + 0x0000_3000;
+const SYSTEM_TIMER_CLO: usize = P_BASE + 0x0000_3004;
+const SYSTEM_TIMER_CHI: usize = P_BASE + 0x0000_3008;
+const SYSTEM_TIMER_C3: usize = P_BASE + 0x0000_3018;
+
+/// The match flag of compare register 3 in `SYSTEM_TIMER_CS`.
+const SYSTEM_TIMER_MATCH_3: u32 = 1 << 3;
+
+/// The local controller's control register, which picks the generic
+/// timers' clock, and the prescaler applied to it.
+const LOCAL_CONTROL: usize = LOCAL_BASE + 0x00;
+const LOCAL_PRESCALER: usize = LOCAL_BASE + 0x08;
+
+/// A prescaler of 2^31 leaves the 19.2 MHz crystal clock as it is.
+const PRESCALER_DIVIDE_BY_ONE: u32 = 1 << 31;
+
+/// The most alarms that can be set at once.
+pub const MAX_ALARMS: usize = 16;
+
+/// The period of the tick that `ticks()` counts.
+const TICK: Duration = Duration::from_millis(1);
+
+/// A reading of the system timer, in microseconds since boot. It doesn't
+/// wrap for half a million years.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        // The two halves can't be read at once, so read the high half again
+        // to see whether the low one wrapped in between.
+        unsafe {
+            let high = mmio::read(SYSTEM_TIMER_CHI);
+            let low = mmio::read(SYSTEM_TIMER_CLO);
+            let again = mmio::read(SYSTEM_TIMER_CHI);
+            if again == high {
+                Instant((high as u64) << 32 | low as u64)
+            } else {
+                Instant((again as u64) << 32 | mmio::read(SYSTEM_TIMER_CLO) as u64)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn as_micros(self) -> u64 {
+        self.0
+    }
+
+    /// The time from `earlier` to this, or zero if `earlier` is later.
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        Duration::from_micros(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(self) -> Duration {
+        Instant::now().duration_since(self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration.as_micros() as u64))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Wait for at least `us` microseconds, without sleeping.
+pub fn delay_us(us: u64) {
+    let until = Instant::now() + Duration::from_micros(us);
+    while Instant::now() < until {}
+}
+
+pub fn delay_ms(ms: u64) {
+    delay_us(ms * 1000);
+}
+
+/// Identifies an alarm to `cancel`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AlarmId(u64);
+
+#[derive(Copy, Clone)]
+struct Alarm {
+    id: AlarmId,
+    deadline: Instant,
+    /// How often a periodic alarm goes off after the first time.
+    period: Option<Duration>,
+    handler: fn(),
+}
+
+/// The alarms, locked with IRQs masked as the alarm interrupt uses them.
+static ALARMS: IrqMutex<[Option<Alarm>; MAX_ALARMS]> = IrqMutex::new([None; MAX_ALARMS]);
+
+static NEXT_ALARM_ID: AtomicU64 = AtomicU64::new(0);
+
+extern "C" {
+    fn enable_irq();
+}
+
+/// The least time ahead of the counter that compare register 3 is set to,
+/// in microseconds. It only matches when the counter is equal to it, so
+/// setting it to a time that has passed by then would hold up every alarm
+/// until the counter wrapped, over an hour later.
+const MIN_LEAD: u64 = 50;
+
+/// Set compare register 3 to the earliest deadline.
+fn program(alarms: &[Option<Alarm>; MAX_ALARMS]) {
+    let next = alarms.iter().flatten().map(|alarm| alarm.deadline).min();
+    loop {
+        let now = Instant::now();
+        // The compare register only has the low half of the counter, so a
+        // deadline further away than half of that goes off early, and is
+        // put off again. With no alarms, the match is as far away as it can
+        // be.
+        let wait = match next {
+            Some(deadline) => deadline
+                .0
+                .saturating_sub(now.0)
+                .max(MIN_LEAD)
+                .min(i32::max_value() as u64),
+            None => u32::max_value() as u64,
+        };
+        let low = (now.0 + wait) as u32;
+        unsafe { mmio::write(SYSTEM_TIMER_C3, low) };
+        // If this was held up until the counter passed the match anyway, go
+        // again from the new time.
+        let counter = unsafe { mmio::read(SYSTEM_TIMER_CLO) };
+        if next.is_none() || low.wrapping_sub(counter) as i32 > 0 {
+            break;
+        }
+    }
+}
+
+fn set_alarm(deadline: Instant, period: Option<Duration>, handler: fn()) -> Option<AlarmId> {
+    let id = AlarmId(NEXT_ALARM_ID.fetch_add(1, Ordering::SeqCst));
+    let alarm = Alarm {
+        id,
+        deadline,
+        period,
+        handler,
+    };
+    let mut alarms = ALARMS.lock();
+    let slot = alarms.iter_mut().find(|slot| slot.is_none())?;
+    *slot = Some(alarm);
+    program(&alarms);
+    Some(id)
+}
+
+/// Call `handler` once, `after` from now. It's called from the timer
+/// interrupt. Returns `None` if `MAX_ALARMS` alarms are already set.
+pub fn alarm_after(after: Duration, handler: fn()) -> Option<AlarmId> {
+    set_alarm(Instant::now() + after, None, handler)
+}
+
+/// Call `handler` every `period` from now on, from the timer interrupt. If
+/// the handler falls behind, the calls it missed are skipped.
+pub fn alarm_every(period: Duration, handler: fn()) -> Option<AlarmId> {
+    let period = period.max(Duration::from_micros(1));
+    set_alarm(Instant::now() + period, Some(period), handler)
+}
+
+/// Stop the alarm `id`, returning whether it was still set.
+pub fn cancel(id: AlarmId) -> bool {
+    let mut alarms = ALARMS.lock();
+    let slot = alarms
+        .iter_mut()
+        .find(|slot| slot.map_or(false, |alarm| alarm.id == id));
+    match slot {
+        Some(slot) => {
+            *slot = None;
+            program(&alarms);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Take the handlers of the alarms that are due, rescheduling or removing
+/// the alarms, and set the compare register for the rest.
+fn take_due(now: Instant, due: &mut [Option<fn()>; MAX_ALARMS]) -> usize {
+    let mut alarms = ALARMS.lock();
+    let mut count = 0;
+    for slot in alarms.iter_mut() {
+        let alarm = match slot {
+            Some(alarm) if alarm.deadline <= now => alarm,
+            _ => continue,
+        };
+        due[count] = Some(alarm.handler);
+        count += 1;
+        match alarm.period {
+            Some(period) => {
+                alarm.deadline = alarm.deadline + period;
+                if alarm.deadline <= now {
+                    alarm.deadline = now + period;
+                }
+            }
+            None => *slot = None,
+        }
+    }
+    program(&alarms);
+    count
+}
+
+fn handle_alarm_irq() {
+    unsafe { mmio::write(SYSTEM_TIMER_CS, SYSTEM_TIMER_MATCH_3) };
+    // The handlers are called without the lock held, so that they can set
+    // and cancel alarms. Go round again in case another alarm fell due
+    // while they ran, since its match may have been missed.
+    loop {
+        let mut due = [None; MAX_ALARMS];
+        let count = take_due(Instant::now(), &mut due);
+        if count == 0 {
+            break;
+        }
+        for handler in due.iter().flatten() {
+            handler();
+        }
+    }
+}
+
+/// Milliseconds since `init`, counted by a periodic alarm.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+fn tick() {
+    TICKS.fetch_add(1, Ordering::SeqCst);
+    wake_timers();
+}
+
+/// The number of milliseconds since `init`. Unlike `Instant`, this only
+/// changes in an interrupt, so it's what code that waits with `wfi` uses.
+#[inline]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::SeqCst)
+}
+
+/// A future that's ready once a deadline has passed. It's woken by the
+/// tick, so it can be up to a millisecond late.
+pub struct Timer {
+    id: u64,
+    deadline: Instant,
+}
+
+/// The wakers of the pending `Timer`s, with their ids and deadlines.
+static TIMERS: IrqMutex<Vec<(u64, Instant, Waker)>> = IrqMutex::new(Vec::new());
+
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+impl Timer {
+    pub fn at(deadline: Instant) -> Timer {
+        Timer {
+            id: NEXT_TIMER_ID.fetch_add(1, Ordering::SeqCst),
+            deadline,
+        }
+    }
+
+    pub fn after(duration: Duration) -> Timer {
+        Timer::at(Instant::now() + duration)
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let mut timers = TIMERS.lock();
+        match timers.iter_mut().find(|(id, _, _)| *id == self.id) {
+            Some((_, _, waker)) => {
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => timers.push((self.id, self.deadline, cx.waker().clone())),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        TIMERS.lock().retain(|(id, _, _)| *id != self.id);
+    }
+}
+
+/// Wake the `Timer`s whose deadlines have passed.
+fn wake_timers() {
+    let mut timers = TIMERS.lock();
+    if timers.is_empty() {
+        return;
+    }
+    let now = Instant::now();
+    timers.retain(|(_, deadline, waker)| {
+        if *deadline <= now {
+            waker.wake_by_ref();
+        }
+        *deadline > now
+    });
+}
+
+/// Start the generic timers' clock, enable the alarms and the millisecond
+/// tick, and unmask IRQs.
+pub fn init() {
+    unsafe {
+        mmio::write(LOCAL_CONTROL, 0);
+        mmio::write(LOCAL_PRESCALER, PRESCALER_DIVIDE_BY_ONE);
+        mmio::write(SYSTEM_TIMER_CS, SYSTEM_TIMER_MATCH_3);
+    }
+    controller::register(Irq::SYSTEM_TIMER_3, handle_alarm_irq);
+    alarm_every(TICK, tick).expect("Failed to start the tick");
+    unsafe { enable_irq() };
+}
+
+/// The generic timer's count, which all the cores share.
+#[inline]
+pub fn counter() -> u64 {
+    let count: u64;
+    unsafe {
+        asm!("isb" :::: "volatile");
+        asm!("mrs $0, cntpct_el0" : "=r"(count) ::: "volatile");
+    }
+    count
+}
+
+/// How fast `counter()` counts, in Hz.
+#[inline]
+pub fn counter_frequency() -> u64 {
+    let frequency: u64;
+    unsafe { asm!("mrs $0, cntfrq_el0" : "=r"(frequency)) };
+    frequency
+}
+
+/// Make this core's generic timer raise `Irq::CNTPNS` once, `after` from
+/// now. The handler registered for it must call this again or
+/// `stop_core_timer`, as the interrupt stays pending until it does.
+pub fn arm_core_timer(after: Duration) {
+    let ticks = after.as_nanos() * counter_frequency() as u128 / 1_000_000_000;
+    let ticks = ticks.max(1).min(i32::max_value() as u128) as u64;
+    unsafe {
+        asm!("msr cntp_tval_el0, $0" :: "r"(ticks) :: "volatile");
+        // Enabled, and the interrupt not masked.
+        asm!("msr cntp_ctl_el0, $0" :: "r"(1usize) :: "volatile");
+    }
+    controller::enable(Irq::CNTPNS);
+}
+
+pub fn stop_core_timer() {
+    unsafe { asm!("msr cntp_ctl_el0, $0" :: "r"(0usize) :: "volatile") };
+}
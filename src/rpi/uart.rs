@@ -1,3 +1,4 @@
+mod ring;
 pub mod uart0;
 // #[cfg(not(feature = "rpi3"))]
 pub type UART = uart0::UART0;
@@ -0,0 +1,64 @@
+//! A lock-free ring buffer of bytes between one producer and one consumer,
+//! such as an IRQ handler and the code it interrupts. Each side only writes
+//! its own index, so neither has to mask interrupts or take a lock.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The size of a ring. A power of two, so that the indices can wrap.
+pub const RING_SIZE: usize = 4096;
+
+pub struct Ring {
+    buffer: UnsafeCell<[u8; RING_SIZE]>,
+    /// The count of bytes ever pushed, which only the producer changes.
+    head: AtomicUsize,
+    /// The count of bytes ever popped, which only the consumer changes.
+    tail: AtomicUsize,
+}
+
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    pub const fn new() -> Ring {
+        Ring {
+            buffer: UnsafeCell::new([0; RING_SIZE]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        head.wrapping_sub(self.tail.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add `byte` at the end, returning `false` if the ring is full. Only
+    /// the producer may call this, and only from one place at a time.
+    pub unsafe fn push(&self, byte: u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == RING_SIZE {
+            return false;
+        }
+        (*self.buffer.get())[head % RING_SIZE] = byte;
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Take the byte at the start. Only the consumer may call this, and
+    /// only from one place at a time.
+    pub unsafe fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if self.head.load(Ordering::Acquire) == tail {
+            return None;
+        }
+        let byte = (*self.buffer.get())[tail % RING_SIZE];
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+}
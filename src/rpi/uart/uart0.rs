@@ -1,14 +1,43 @@
-use core::fmt;
-
-use crate::rpi::mmio::{self, P_BASE};
+//! The mini UART, which is the console's serial port.
+//!
+//! It only has eight-byte FIFOs, so both directions are buffered in rings
+//! that its interrupt fills and empties: received bytes wait in `RX` until
+//! they're read, and written ones in `TX` until there's room to send them.
+//! With IRQs masked nothing empties the rings, so writes then go straight
+//! to the UART, and reads take what the UART has received themselves.
+//!
+//! The rings have one producer and one consumer each. Writers on any core
+//! take turns through `TX_LOCK`, which the interrupt takes too while it
+//! empties `TX`. Reading is only for the core that takes the interrupt,
+//! core 0.
+
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use super::ring::Ring;
+use crate::{
+    executor::{self, WakerSlot},
+    interrupts::{
+        controller::{self, Irq},
+        masked_exceptions, ExceptionMask, IrqMutex,
+    },
+    rpi::{
+        gpio::{self, Pull},
+        mmio::{self, P_BASE},
+    },
+};
 
 const GPIO_BASE: usize = P_BASE + 0x0020_0000;
 const GPIO_FSEL1: usize = GPIO_BASE + 0x04;
 const GPIO_SET0: usize = GPIO_BASE + 0x1C;
 const GPIO_CLR0: usize = GPIO_BASE + 0x28;
-const GPIO_PUD: usize = GPIO_BASE + 0x94;
-const GPIO_PUD_CLK0: usize = GPIO_BASE + 0x98;
 
+const AUX_IRQ: usize = P_BASE + 0x0021_5000;
 const AUX_ENABLES: usize = P_BASE + 0x0021_5004;
 const AUX_MU_IO_REG: usize = P_BASE + 0x0021_5040;
 const AUX_MU_IER_REG: usize = P_BASE + 0x0021_5044;
@@ -21,12 +50,20 @@ const AUX_MU_SCRATCH: usize = P_BASE + 0x0021_505C;
 const AUX_MU_CNTL_REG: usize = P_BASE + 0x0021_5060;
 const AUX_MU_STAT_REG: usize = P_BASE + 0x0021_5064;
 const AUX_MU_BAUD_REG: usize = P_BASE + 0x0021_5068;
-// // Controls actuation of pull up/down to ALL GPIO pins.
-// const GPPUD: usize = GPIO_BASE + 0x94;
-
-// // Controls actuation of pull up/down for specific GPIO pin.
-// const GPPUDCLK0: usize = GPIO_BASE + 0x98;
 
+/// The mini UART's bit in `AUX_IRQ`.
+const AUX_IRQ_MINI_UART: u32 = 1 << 0;
+/// The receive and transmit interrupt enables in `AUX_MU_IER_REG`. Bits 2
+/// and 3 are documented as unused, but the interrupts don't fire without
+/// them.
+const AUX_MU_IER_RX: u32 = 1 << 0 | 0b11 << 2;
+const AUX_MU_IER_TX: u32 = 1 << 1;
+
+/// Bits of `AUX_MU_LSR_REG`: a byte has been received, bytes were lost
+/// because the receive FIFO was full, and the transmit FIFO has room.
+const LSR_DATA_READY: u32 = 1 << 0;
+const LSR_RX_OVERRUN: u32 = 1 << 1;
+const LSR_TX_EMPTY: u32 = 1 << 5;
 // const UART0_BASE: usize = GPIO_BASE;
 
 // // The base address for UART.
@@ -52,19 +89,6 @@ const AUX_MU_BAUD_REG: usize = P_BASE + 0x0021_5068;
 // // const UART0_ITOP: usize = UART0_BASE + 0x88;
 // // const UART0_TDR: usize = UART0_BASE + 0x8C;
 
-extern "C" {
-    fn delay(count: usize);
-}
-// #[inline(always)]
-// unsafe fn delay(mut count: usize) {
-//     asm!("\
-// __delay_%=:
-//     subs %[count], %[count], #1;
-//     bne __delay_%=
-// " : "=r"(count) : "0"(count) : "cc");
-//     let _ = count;
-// }
-
 /// This function must only be called once.
 unsafe fn uart0_init() {
     let mut selector = mmio::read(GPIO_FSEL1);
@@ -74,11 +98,7 @@ unsafe fn uart0_init() {
     selector |= 2 << 15; // set alt5 for gpio15
     mmio::write(GPIO_FSEL1, selector);
 
-    mmio::write(GPIO_PUD, 0);
-    delay(150);
-    mmio::write(GPIO_PUD_CLK0, (1 << 14) | (1 << 15));
-    delay(150);
-    mmio::write(GPIO_PUD_CLK0, 0);
+    gpio::set_pull((1 << 14) | (1 << 15), Pull::Off);
 
     mmio::write(AUX_ENABLES, 1); // enable mini uart
     mmio::write(AUX_MU_CNTL_REG, 0); // disable auto flow control and disable receiver and transmitter (for now)
@@ -89,20 +109,11 @@ unsafe fn uart0_init() {
 
     mmio::write(AUX_MU_CNTL_REG, 3); // Now that setup is done, enable transmitter and receiver.
 
+    controller::register(Irq::AUX, handle_aux_irq);
+    mmio::write(AUX_MU_IER_REG, AUX_MU_IER_RX);
+
     // // Disable UART0.
     // mmio::write(UART0_CR, 0x0000_0000);
-    // // Setup the GPIO pin 14 && 15.
-
-    // // Disable pull up/down for all GPIO pins & delay for 150 cycles.
-    // mmio::write(GPPUD, 0x0000_0000);
-    // delay(150);
-
-    // // Disable pull up/down for pin 14,15 & delay for 150 cycles.
-    // mmio::write(GPPUDCLK0, (1 << 14) | (1 << 15));
-    // delay(150);
-
-    // // Write 0 to GPPUDCLK0 to make it take effect.
-    // mmio::write(GPPUDCLK0, 0x0000_0000);
 
     // // Clear pending interrupts.
     // mmio::write(UART0_ICR, 0x0000_07FF);
@@ -130,28 +141,133 @@ unsafe fn uart0_init() {
     // mmio::write(UART0_CR, (1 << 0) | (1 << 8) | (1 << 9));
 }
 
-#[inline]
-unsafe fn uart0_put_byte(byte: u8) {
+static UART0_INIT_ONCE: spin::Once<()> = spin::Once::new();
+
+static RX: Ring = Ring::new();
+static TX: Ring = Ring::new();
+
+/// Held by whichever core is `TX`'s producer or consumer.
+static TX_LOCK: IrqMutex<()> = IrqMutex::new(());
+
+/// The waker of the `ReadByte` waiting for a byte.
+static RX_WAKER: WakerSlot = WakerSlot::new();
+
+static FIFO_OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+static BUFFER_OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// How often received bytes have been lost since boot.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Overruns {
+    /// The UART's receive FIFO filled before it was emptied. Each can be
+    /// more than one byte.
+    pub fifo: usize,
+    /// A byte came in while `RX` was full.
+    pub buffer: usize,
+}
+
+fn irqs_masked() -> bool {
+    masked_exceptions().contains(ExceptionMask::I)
+}
+
+/// Move what the UART has received into `RX`. This is `RX`'s producer, so
+/// it's only called from the interrupt or with IRQs masked.
+unsafe fn receive() -> bool {
+    let mut received = false;
     loop {
-        if mmio::read(AUX_MU_LSR_REG) & 0x20 != 0 {
-            break;
+        let status = mmio::read(AUX_MU_LSR_REG);
+        if status & LSR_RX_OVERRUN != 0 {
+            FIFO_OVERRUNS.fetch_add(1, Ordering::Relaxed);
+        }
+        if status & LSR_DATA_READY == 0 {
+            return received;
+        }
+        if !RX.push(mmio::read(AUX_MU_IO_REG) as u8) {
+            BUFFER_OVERRUNS.fetch_add(1, Ordering::Relaxed);
         }
+        received = true;
     }
+}
+
+/// Move what's in `TX` to the UART while it has room, and turn the transmit
+/// interrupt off once `TX` is empty. This is `TX`'s consumer, so it's only
+/// called with `TX_LOCK` held.
+unsafe fn transmit() {
+    while mmio::read(AUX_MU_LSR_REG) & LSR_TX_EMPTY != 0 {
+        match TX.pop() {
+            Some(byte) => mmio::write(AUX_MU_IO_REG, byte as u32),
+            None => {
+                mmio::write(AUX_MU_IER_REG, AUX_MU_IER_RX);
+                return;
+            }
+        }
+    }
+}
+
+fn handle_aux_irq() {
+    let received = unsafe {
+        if mmio::read(AUX_IRQ) & AUX_IRQ_MINI_UART == 0 {
+            return;
+        }
+        let received = receive();
+        let _tx = TX_LOCK.lock();
+        transmit();
+        received
+    };
+    if received {
+        RX_WAKER.wake();
+    }
+}
+
+/// Send `byte` once everything before it has been sent, without waiting for
+/// the interrupt. `TX_LOCK` must be held.
+unsafe fn put_byte_now(byte: u8) {
+    while !TX.is_empty() {
+        transmit();
+    }
+    while mmio::read(AUX_MU_LSR_REG) & LSR_TX_EMPTY == 0 {}
     mmio::write(AUX_MU_IO_REG, byte as u32)
 }
 
-#[inline]
-unsafe fn uart0_get_byte() -> u8 {
-    // Wait for UART to have received something.
-    loop {
-        if mmio::read(AUX_MU_LSR_REG) & 0x01 != 0 {
-            break;
+fn uart0_put_byte(byte: u8) {
+    let masked = irqs_masked();
+    let _tx = TX_LOCK.lock();
+    unsafe {
+        if masked || !TX.push(byte) {
+            put_byte_now(byte);
+        } else {
+            mmio::write(AUX_MU_IER_REG, AUX_MU_IER_RX | AUX_MU_IER_TX);
         }
     }
-    mmio::read(AUX_MU_IO_REG) as u8
 }
 
-static UART0_INIT_ONCE: spin::Once<()> = spin::Once::new();
+/// `RX`'s consumer.
+fn uart0_try_get_byte() -> Option<u8> {
+    if irqs_masked() {
+        unsafe { receive() };
+    }
+    unsafe { RX.pop() }
+}
+
+/// A future for the next byte the UART receives.
+pub struct ReadByte<'a> {
+    _uart: &'a mut UART0,
+}
+
+impl Future for ReadByte<'_> {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<u8> {
+        if let Some(byte) = uart0_try_get_byte() {
+            return Poll::Ready(byte);
+        }
+        RX_WAKER.register(cx.waker());
+        // Look again, in case a byte came in before the waker was there.
+        match uart0_try_get_byte() {
+            Some(byte) => Poll::Ready(byte),
+            None => Poll::Pending,
+        }
+    }
+}
 
 pub struct UART0 {}
 
@@ -161,12 +277,35 @@ impl UART0 {
         UART0 {}
     }
 
-    pub fn read_byte(&mut self) -> u8 {
-        unsafe { uart0_get_byte() }
+    /// Wait for a byte to be received, and read it.
+    pub fn read_byte(&mut self) -> ReadByte {
+        ReadByte { _uart: self }
+    }
+
+    /// Read a byte if one has been received, without waiting.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        uart0_try_get_byte()
     }
 
+    /// Queue `byte` to be sent.
     pub fn write_byte(&mut self, byte: u8) {
-        unsafe { uart0_put_byte(byte) }
+        uart0_put_byte(byte)
+    }
+
+    /// Wait until everything written so far has been sent, as before
+    /// exiting QEMU.
+    pub fn flush(&mut self) {
+        let _tx = TX_LOCK.lock();
+        while !TX.is_empty() {
+            unsafe { transmit() };
+        }
+    }
+
+    pub fn overruns(&self) -> Overruns {
+        Overruns {
+            fifo: FIFO_OVERRUNS.load(Ordering::Relaxed),
+            buffer: BUFFER_OVERRUNS.load(Ordering::Relaxed),
+        }
     }
 }
 
@@ -180,7 +319,7 @@ impl fmt::Write for UART0 {
 }
 
 pub unsafe extern "C" fn uart_read_byte() -> u8 {
-    UART0::new().read_byte()
+    executor::block_on(UART0::new().read_byte())
 }
 
 pub unsafe extern "C" fn uart_put_byte(byte: usize) {
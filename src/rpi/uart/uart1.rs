@@ -3,7 +3,10 @@ use core::{
     sync::atomic::{AtomicBool, Ordering},
 };
 
-use crate::rpi::mailbox::{Channel, PropertyMessage, PropertyTagList};
+use crate::{
+    executor,
+    rpi::mailbox::{Channel, PropertyMessage, PropertyTagList},
+};
 
 // pub const GET_CLOCK_STATE_TAG: u32 = 0x0003_0001;
 // pub const SET_CLOCK_STATE_TAG: u32 = 0x0003_8001;
@@ -54,8 +57,7 @@ unsafe fn uart1_init() {
         },
     )
     .prepare();
-    let response = message
-        .send()
+    let response = executor::block_on(message.send())
         .expect("Failed to initialize UART1 (get power state failed)");
     let exists = ((response.info >> 1) & 1) == 0;
     let powered_on = ((response.info >> 0) & 1) == 1;
@@ -74,7 +76,7 @@ unsafe fn uart1_init() {
             },
         )
         .prepare();
-        let response = message.send().unwrap();
+        let response = executor::block_on(message.send()).unwrap();
         let powered_on = ((response.info >> 0) & 1) == 1;
         assert!(powered_on, "Failed to power on UART1: {:?}", **response);
     }
@@ -1,11 +1,31 @@
+//! The USB host, through the DWC OTG driver in `vendor/dwc_otg`.
+//!
+//! Once `usb_init` has set the controller up, its interrupt comes in as
+//! `Irq::USB`, and `handle_usb_irq` has the driver service it with
+//! `usb_event_poll`. The driver passes the boot protocol reports a keyboard
+//! sends on its interrupt endpoint to `keyboard::usb_keyboard_report` from
+//! there, which wakes `keyboard::next_event`.
+
+use crate::interrupts::controller::{self, Irq};
+
+pub mod keyboard;
+
 extern "C" {
     fn usb_init() -> i32;
     fn usb_stop() -> i32;
+    fn usb_event_poll();
 
     type USB_DEVICE;
 }
 
+fn handle_usb_irq() {
+    unsafe { usb_event_poll() };
+}
+
 pub fn init() {
     let result = unsafe { usb_init() };
     println!("Got result from USB init: {:?}", result);
+    if result == 0 {
+        controller::register(Irq::USB, handle_usb_irq);
+    }
 }
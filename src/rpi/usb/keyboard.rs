@@ -0,0 +1,151 @@
+//! Key presses and releases from a USB keyboard in the boot protocol.
+//!
+//! The USB driver passes each eight-byte report the keyboard sends to
+//! `usb_keyboard_report`, from its interrupt. A report holds the modifier
+//! keys as bits, and up to six other keys that are down, by HID usage ID.
+//! Comparing it with the last one gives the keys that went down and up,
+//! which queue up for `next_event`.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    slice,
+    task::{Context, Poll},
+};
+
+use bitflags::bitflags;
+
+use crate::{executor::WakerSlot, interrupts::IrqMutex};
+
+bitflags! {
+    /// The modifier keys, as the first byte of a report has them.
+    pub struct Modifiers: u8 {
+        const LEFT_CTRL = 1 << 0;
+        const LEFT_SHIFT = 1 << 1;
+        const LEFT_ALT = 1 << 2;
+        const LEFT_GUI = 1 << 3;
+        const RIGHT_CTRL = 1 << 4;
+        const RIGHT_SHIFT = 1 << 5;
+        const RIGHT_ALT = 1 << 6;
+        const RIGHT_GUI = 1 << 7;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// The key's HID usage ID, such as 0x04 for A.
+    pub usage: u8,
+    pub pressed: bool,
+    /// The modifier keys that were down at the time.
+    pub modifiers: Modifiers,
+}
+
+const NO_EVENT: KeyEvent = KeyEvent {
+    usage: 0,
+    pressed: false,
+    modifiers: Modifiers::empty(),
+};
+
+/// The most events that can be waiting. Any more are dropped.
+const QUEUE_LEN: usize = 64;
+
+/// The usage ID every key slot holds when too many keys are down to report.
+const ROLLOVER_ERROR: u8 = 0x01;
+
+/// The events, kept in a fixed ring as they're added from an interrupt,
+/// which mustn't allocate.
+struct Queue {
+    events: [KeyEvent; QUEUE_LEN],
+    start: usize,
+    len: usize,
+    /// The keys down in the last report.
+    keys: [u8; 6],
+}
+
+impl Queue {
+    fn push(&mut self, event: KeyEvent) {
+        if self.len < QUEUE_LEN {
+            self.events[(self.start + self.len) % QUEUE_LEN] = event;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<KeyEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.start];
+        self.start = (self.start + 1) % QUEUE_LEN;
+        self.len -= 1;
+        Some(event)
+    }
+}
+
+static QUEUE: IrqMutex<Queue> = IrqMutex::new(Queue {
+    events: [NO_EVENT; QUEUE_LEN],
+    start: 0,
+    len: 0,
+    keys: [0; 6],
+});
+
+static EVENT_WAKER: WakerSlot = WakerSlot::new();
+
+/// Take a boot protocol report from the keyboard. `report` points at `len`
+/// bytes; a short one is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn usb_keyboard_report(report: *const u8, len: usize) {
+    if report.is_null() || len < 8 {
+        return;
+    }
+    let report = slice::from_raw_parts(report, 8);
+    let modifiers = Modifiers::from_bits_truncate(report[0]);
+    let keys = &report[2..8];
+    if keys.iter().all(|&key| key == ROLLOVER_ERROR) {
+        return;
+    }
+    let mut queue = QUEUE.lock();
+    let last = queue.keys;
+    for &usage in last.iter().filter(|&&key| key != 0 && !keys.contains(&key)) {
+        queue.push(KeyEvent {
+            usage,
+            pressed: false,
+            modifiers,
+        });
+    }
+    for &usage in keys.iter().filter(|&&key| key != 0 && !last.contains(&key)) {
+        queue.push(KeyEvent {
+            usage,
+            pressed: true,
+            modifiers,
+        });
+    }
+    queue.keys.copy_from_slice(keys);
+    let any = queue.len != 0;
+    drop(queue);
+    if any {
+        EVENT_WAKER.wake();
+    }
+}
+
+/// A future for the next key event.
+pub struct NextEvent(());
+
+impl Future for NextEvent {
+    type Output = KeyEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<KeyEvent> {
+        let mut queue = QUEUE.lock();
+        match queue.pop() {
+            Some(event) => Poll::Ready(event),
+            None => {
+                EVENT_WAKER.register(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Wait for a key to go down or up.
+pub fn next_event() -> NextEvent {
+    NextEvent(())
+}
@@ -0,0 +1,202 @@
+//! Bringing up the other cores, and the data each core keeps for itself.
+//!
+//! At boot, cores 1-3 wait in `boot.S` for an address to appear in their
+//! slot of the spin table. `start_secondaries` gives each a stack and
+//! releases it into the same EL3 to EL1 drop that core 0 took, after which it
+//! shares core 0's page tables and exception vectors and calls
+//! `secondary_main`.
+//!
+//! Once online, a core takes IPIs (see `ipi`), so other cores can hand it
+//! work with `run_on`. Cores 1-3 do nothing else for now.
+//!
+//! Each core's `TPIDR_EL1` points at its own `Cpu`, which `this_cpu` returns.
+//! Data there is only touched by its own core, so it needs no locks.
+//!
+//! The Pi 3 only keeps exclusive loads and stores coherent between cores for
+//! cacheable, shareable memory, which is why RAM is mapped inner-shareable
+//! write-back, and why each core joins the caches' coherency in `boot.S`
+//! before it turns its MMU on.
+
+use alloc::{
+    alloc::{alloc_zeroed, handle_alloc_error, Layout},
+    boxed::Box,
+    vec::Vec,
+};
+use core::{
+    mem, ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    interrupts::{controller, faults::FaultFrame, IrqMutex},
+    process::Process,
+    rpi::{mmu::clean_dcache, timer::Instant},
+};
+
+mod ipi;
+
+pub use ipi::{broadcast, run_on, send, tlb_shootdown, Ipi};
+
+/// The number of cores on the Pi 3.
+pub const MAX_CPUS: usize = 4;
+
+/// The size in bytes of the stacks of cores 1-3.
+const STACK_SIZE: usize = 64 * 1024;
+
+/// How long to wait for a released core to come online.
+const START_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// What a released core needs to join core 0, as `secondary_el1_entry` in
+/// `boot.S` reads it. The layout is shared with `boot.h`.
+#[repr(C)]
+struct SecondaryBoot {
+    mair: usize,
+    tcr: usize,
+    ttbr0: usize,
+    ttbr1: usize,
+    sctlr: usize,
+    /// The initial stack pointer of each core.
+    stacks: [usize; MAX_CPUS],
+}
+
+#[no_mangle]
+static mut SECONDARY_BOOT: SecondaryBoot = SecondaryBoot {
+    mair: 0,
+    tcr: 0,
+    ttbr0: 0,
+    ttbr1: 0,
+    sctlr: 0,
+    stacks: [0; MAX_CPUS],
+};
+
+extern "C" {
+    static mut spin_table: [usize; MAX_CPUS];
+    #[link_name = "reset"]
+    fn boot_reset();
+}
+
+/// A function queued by `run_on`.
+type Work = Box<dyn FnOnce() + Send>;
+
+/// A core's own data.
+pub struct Cpu {
+    id: usize,
+    online: AtomicBool,
+    /// The user program running on this core, if any.
+    pub current_process: AtomicPtr<Process>,
+    /// The innermost `call_catching_faults` on this core, if any.
+    pub innermost_fault: AtomicPtr<FaultFrame>,
+    /// What other cores have asked this one to run. Unlike the rest, it's
+    /// shared between cores.
+    work: IrqMutex<Vec<Work>>,
+}
+
+impl Cpu {
+    const fn new(id: usize) -> Cpu {
+        Cpu {
+            id,
+            online: AtomicBool::new(false),
+            current_process: AtomicPtr::new(ptr::null_mut()),
+            innermost_fault: AtomicPtr::new(ptr::null_mut()),
+            work: IrqMutex::new(Vec::new()),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
+    }
+}
+
+static CPUS: [Cpu; MAX_CPUS] = [Cpu::new(0), Cpu::new(1), Cpu::new(2), Cpu::new(3)];
+
+/// The number of the core this is running on.
+#[inline]
+pub fn cpu_id() -> usize {
+    let mpidr: usize;
+    unsafe { asm!("mrs $0, mpidr_el1" : "=r"(mpidr)) };
+    mpidr & 0xFF
+}
+
+/// The data of the core this is running on.
+#[inline]
+pub fn this_cpu() -> &'static Cpu {
+    let cpu: *const Cpu;
+    unsafe { asm!("mrs $0, tpidr_el1" : "=r"(cpu)) };
+    debug_assert!(!cpu.is_null(), "this_cpu() used before smp::init()");
+    unsafe { &*cpu }
+}
+
+/// The data of core `id`.
+pub fn cpu(id: usize) -> &'static Cpu {
+    &CPUS[id]
+}
+
+/// Point this core's `TPIDR_EL1` at its `Cpu`, and mark it online once it
+/// can take IPIs.
+fn join(id: usize) {
+    let cpu = &CPUS[id];
+    unsafe { asm!("msr tpidr_el1, $0" :: "r"(cpu as *const Cpu) :: "volatile") };
+    ipi::init_this_core();
+    cpu.online.store(true, Ordering::SeqCst);
+}
+
+/// Set up core 0's data, and have the GPU's interrupts come to it, as the
+/// drivers expect whatever the firmware left the routing as. Called first
+/// thing in `kernel_main`.
+pub fn init() {
+    join(cpu_id());
+    controller::route_gpu_to_this_core();
+}
+
+/// Release cores 1-3 from the spin table, and wait for them to come online.
+/// Returns how many cores are online, counting this one.
+pub fn start_secondaries() -> usize {
+    let stack_layout = Layout::from_size_align(STACK_SIZE, 16).unwrap();
+    unsafe {
+        let boot = &mut SECONDARY_BOOT;
+        asm!("mrs $0, mair_el1" : "=r"(boot.mair));
+        asm!("mrs $0, tcr_el1" : "=r"(boot.tcr));
+        asm!("mrs $0, ttbr0_el1" : "=r"(boot.ttbr0));
+        asm!("mrs $0, ttbr1_el1" : "=r"(boot.ttbr1));
+        asm!("mrs $0, sctlr_el1" : "=r"(boot.sctlr));
+        for id in 1..MAX_CPUS {
+            let stack = alloc_zeroed(stack_layout);
+            if stack.is_null() {
+                handle_alloc_error(stack_layout);
+            }
+            boot.stacks[id] = stack as usize + STACK_SIZE;
+        }
+
+        // The cores read all this with their MMUs, and so their caches, off,
+        // so it has to be written back from this core's cache to memory
+        // before they're woken.
+        for id in 1..MAX_CPUS {
+            ptr::write_volatile(&mut spin_table[id], boot_reset as usize);
+        }
+        let boot_start = boot as *const SecondaryBoot as usize;
+        clean_dcache(boot_start, boot_start + mem::size_of::<SecondaryBoot>());
+        let table_start = spin_table.as_ptr() as usize;
+        clean_dcache(table_start, table_start + mem::size_of_val(&spin_table));
+        asm!("sev" :::: "volatile");
+    }
+
+    let start = Instant::now();
+    while !CPUS.iter().all(Cpu::is_online) && start.elapsed() < START_TIMEOUT {}
+    CPUS.iter().filter(|cpu| cpu.is_online()).count()
+}
+
+/// Where cores 1-3 start in Rust, on their own stacks with the MMU on and
+/// interrupts masked. They wait for IPIs from here on.
+#[no_mangle]
+pub extern "C" fn secondary_main(id: usize) -> ! {
+    join(id);
+    unsafe { asm!("msr daifclr, #2" :::: "volatile") };
+    loop {
+        unsafe { asm!("wfi" :::: "volatile") };
+    }
+}
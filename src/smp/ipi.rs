@@ -0,0 +1,130 @@
+//! Inter-processor interrupts, through the local controller's mailboxes.
+//!
+//! Each core has four 32-bit mailboxes. Writing to a mailbox's set register
+//! sets bits in it, which raises that core's mailbox interrupt until it
+//! writes them back to the clear register. Mailbox 0 carries IPIs, one bit
+//! per kind of `Ipi`, so several can be pending at once without being lost.
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    mem,
+    sync::atomic::{self, AtomicUsize, Ordering},
+};
+
+use super::{cpu, cpu_id, this_cpu, Work, MAX_CPUS};
+use crate::{
+    interrupts::{
+        controller::{self, Irq, LOCAL_BASE},
+        masked_exceptions, ExceptionMask,
+    },
+    rpi::mmio,
+};
+
+/// The set and clear registers of core 0's mailbox 0. Each core's come
+/// 0x10 bytes after the previous core's.
+const MAILBOX_SET: usize = LOCAL_BASE + 0x80;
+const MAILBOX_CLEAR: usize = LOCAL_BASE + 0xC0;
+const MAILBOX_STRIDE: usize = 0x10;
+
+/// The mailbox that IPIs are sent through.
+const IPI_MAILBOX: u8 = 0;
+
+/// The kinds of IPI, numbered by their bit in the mailbox.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Ipi {
+    /// Only wake the core from `wfi`.
+    Wake = 0,
+    /// Run the work queued for the core by `run_on`.
+    Call = 1,
+}
+
+/// Send `ipi` to core `id`.
+pub fn send(id: usize, ipi: Ipi) {
+    assert!(id < MAX_CPUS, "No such core: {}", id);
+    unsafe { mmio::write(MAILBOX_SET + MAILBOX_STRIDE * id, 1 << ipi as u32) };
+}
+
+/// Send `ipi` to every other core that's online.
+pub fn broadcast(ipi: Ipi) {
+    let this = cpu_id();
+    for id in (0..MAX_CPUS).filter(|&id| id != this && cpu(id).is_online()) {
+        send(id, ipi);
+    }
+}
+
+/// Run `f` on core `id`. If that's another core, it's queued and the core is
+/// sent an IPI, and this returns without waiting for it to run. It runs in
+/// the core's IRQ handler, so it mustn't block.
+pub fn run_on<F>(id: usize, f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    if id == cpu_id() {
+        return f();
+    }
+    let target = cpu(id);
+    assert!(target.is_online(), "Core {} isn't online", id);
+    target.work.lock().push(Box::new(f));
+    send(id, Ipi::Call);
+}
+
+/// Invalidate this core's TLB entries for EL1 and EL0.
+fn flush_local_tlb() {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vmalle1
+              dsb nsh
+              isb" :::: "volatile")
+    };
+}
+
+/// Invalidate the TLB entries of every core that's online, and wait until
+/// they all have, after which none of them is using a page table entry that
+/// was changed before the call. The other cores flush in their IRQ handlers,
+/// so this mustn't be called with IRQs masked, or two cores doing it at once
+/// would wait for each other forever.
+pub fn tlb_shootdown() {
+    assert!(
+        !masked_exceptions().contains(ExceptionMask::I),
+        "tlb_shootdown called with IRQs masked"
+    );
+    let this = cpu_id();
+    let remaining = Arc::new(AtomicUsize::new(0));
+    for id in (0..MAX_CPUS).filter(|&id| id != this && cpu(id).is_online()) {
+        remaining.fetch_add(1, Ordering::SeqCst);
+        let remaining = remaining.clone();
+        run_on(id, move || {
+            flush_local_tlb();
+            remaining.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    flush_local_tlb();
+    while remaining.load(Ordering::SeqCst) != 0 {
+        atomic::spin_loop_hint();
+    }
+}
+
+/// Run everything queued for this core, in the order it was queued.
+fn run_queued() {
+    let work: Vec<Work> = mem::replace(&mut *this_cpu().work.lock(), Vec::new());
+    for f in work {
+        f();
+    }
+}
+
+fn handle_mailbox() {
+    let clear = MAILBOX_CLEAR + MAILBOX_STRIDE * cpu_id();
+    // Clear before handling, so that an IPI sent meanwhile raises the
+    // interrupt again rather than being lost.
+    let pending = unsafe { mmio::read(clear) };
+    unsafe { mmio::write(clear, pending) };
+    if pending & 1 << Ipi::Call as u32 != 0 {
+        run_queued();
+    }
+}
+
+/// Take IPIs on this core.
+pub(super) fn init_this_core() {
+    controller::register(Irq::mailbox(IPI_MAILBOX), handle_mailbox);
+}